@@ -0,0 +1,190 @@
+use std::error::Error;
+use std::fmt;
+
+/// 算術演算で起こりうるエラー
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithError {
+    /// 0 による除算・剰余
+    DivideByZero,
+    /// 結果が型の最大値を超えた
+    Overflow,
+    /// 結果が型の最小値を下回った
+    Underflow,
+    /// 演算の定義域外の値が渡された (負の数の平方根など)
+    Domain,
+}
+
+impl fmt::Display for ArithError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            ArithError::DivideByZero => "division by zero",
+            ArithError::Overflow => "arithmetic overflow",
+            ArithError::Underflow => "arithmetic underflow",
+            ArithError::Domain => "argument out of domain",
+        };
+        f.write_str(msg)
+    }
+}
+
+// Display と Debug を実装していれば、 Error のメソッドはデフォルト実装で足りる。
+// これで `?` によって Box<dyn Error> へ自動的に変換できるようになる。
+impl Error for ArithError {}
+
+/// 失敗を `ArithError` で返す算術演算
+///
+/// 標準の `checked_*` メソッドは失敗の理由を区別せず `None` を返すだけなので、
+/// 何が起きたのかがわかるようにする。
+pub trait CheckedArith: Sized + Copy {
+    fn try_add(self, rhs: Self) -> Result<Self, ArithError>;
+    fn try_sub(self, rhs: Self) -> Result<Self, ArithError>;
+    fn try_mul(self, rhs: Self) -> Result<Self, ArithError>;
+    fn try_div(self, rhs: Self) -> Result<Self, ArithError>;
+    fn try_rem(self, rhs: Self) -> Result<Self, ArithError>;
+    fn try_neg(self) -> Result<Self, ArithError>;
+    fn try_pow(self, exp: u32) -> Result<Self, ArithError>;
+    /// 平方根 (小数点以下切り捨て)
+    fn try_sqrt(self) -> Result<Self, ArithError>;
+}
+
+// 符号なし整数は最小値が 0 なので、下回るのは減算と符号反転のときだけ
+macro_rules! impl_checked_arith_unsigned {
+    ($($t:ty)*) => {
+        $(
+            impl CheckedArith for $t {
+                fn try_add(self, rhs: Self) -> Result<Self, ArithError> {
+                    self.checked_add(rhs).ok_or(ArithError::Overflow)
+                }
+
+                fn try_sub(self, rhs: Self) -> Result<Self, ArithError> {
+                    self.checked_sub(rhs).ok_or(ArithError::Underflow)
+                }
+
+                fn try_mul(self, rhs: Self) -> Result<Self, ArithError> {
+                    self.checked_mul(rhs).ok_or(ArithError::Overflow)
+                }
+
+                fn try_div(self, rhs: Self) -> Result<Self, ArithError> {
+                    self.checked_div(rhs).ok_or(ArithError::DivideByZero)
+                }
+
+                fn try_rem(self, rhs: Self) -> Result<Self, ArithError> {
+                    self.checked_rem(rhs).ok_or(ArithError::DivideByZero)
+                }
+
+                fn try_neg(self) -> Result<Self, ArithError> {
+                    self.checked_neg().ok_or(ArithError::Underflow)
+                }
+
+                fn try_pow(self, exp: u32) -> Result<Self, ArithError> {
+                    self.checked_pow(exp).ok_or(ArithError::Overflow)
+                }
+
+                fn try_sqrt(self) -> Result<Self, ArithError> {
+                    Ok(self.isqrt())
+                }
+            }
+        )*
+    };
+}
+
+// 符号付き整数は、オペランドの符号から結果がどちらにはみ出したかを判断する
+macro_rules! impl_checked_arith_signed {
+    ($($t:ty)*) => {
+        $(
+            impl CheckedArith for $t {
+                fn try_add(self, rhs: Self) -> Result<Self, ArithError> {
+                    self.checked_add(rhs).ok_or(if rhs < 0 {
+                        ArithError::Underflow
+                    } else {
+                        ArithError::Overflow
+                    })
+                }
+
+                fn try_sub(self, rhs: Self) -> Result<Self, ArithError> {
+                    self.checked_sub(rhs).ok_or(if rhs > 0 {
+                        ArithError::Underflow
+                    } else {
+                        ArithError::Overflow
+                    })
+                }
+
+                fn try_mul(self, rhs: Self) -> Result<Self, ArithError> {
+                    self.checked_mul(rhs).ok_or(if (self < 0) != (rhs < 0) {
+                        ArithError::Underflow
+                    } else {
+                        ArithError::Overflow
+                    })
+                }
+
+                fn try_div(self, rhs: Self) -> Result<Self, ArithError> {
+                    if rhs == 0 {
+                        return Err(ArithError::DivideByZero);
+                    }
+                    // 0 以外で失敗するのは MIN / -1 のみ
+                    self.checked_div(rhs).ok_or(ArithError::Overflow)
+                }
+
+                fn try_rem(self, rhs: Self) -> Result<Self, ArithError> {
+                    if rhs == 0 {
+                        return Err(ArithError::DivideByZero);
+                    }
+                    self.checked_rem(rhs).ok_or(ArithError::Overflow)
+                }
+
+                fn try_neg(self) -> Result<Self, ArithError> {
+                    self.checked_neg().ok_or(ArithError::Overflow)
+                }
+
+                fn try_pow(self, exp: u32) -> Result<Self, ArithError> {
+                    self.checked_pow(exp).ok_or(if self < 0 && exp % 2 == 1 {
+                        ArithError::Underflow
+                    } else {
+                        ArithError::Overflow
+                    })
+                }
+
+                fn try_sqrt(self) -> Result<Self, ArithError> {
+                    self.checked_isqrt().ok_or(ArithError::Domain)
+                }
+            }
+        )*
+    };
+}
+
+impl_checked_arith_unsigned!(u8 u16 u32 u64 u128 usize);
+impl_checked_arith_signed!(i8 i16 i32 i64 i128 isize);
+
+#[test]
+fn test_error_kinds() {
+    assert_eq!(10u8.try_div(0), Err(ArithError::DivideByZero));
+    assert_eq!(10i64.try_rem(0), Err(ArithError::DivideByZero));
+    assert_eq!(u8::MAX.try_add(1), Err(ArithError::Overflow));
+    assert_eq!(0u32.try_sub(1), Err(ArithError::Underflow));
+    assert_eq!(1u16.try_neg(), Err(ArithError::Underflow));
+    assert_eq!(i8::MIN.try_add(-1), Err(ArithError::Underflow));
+    assert_eq!(i8::MAX.try_sub(-1), Err(ArithError::Overflow));
+    assert_eq!(i32::MIN.try_mul(2), Err(ArithError::Underflow));
+    assert_eq!(i32::MIN.try_mul(-1), Err(ArithError::Overflow));
+    assert_eq!(i128::MIN.try_div(-1), Err(ArithError::Overflow));
+    assert_eq!(i16::MIN.try_neg(), Err(ArithError::Overflow));
+    assert_eq!((-2i8).try_pow(7), Ok(-128));
+    assert_eq!((-2i8).try_pow(9), Err(ArithError::Underflow));
+    assert_eq!((-2i8).try_pow(8), Err(ArithError::Overflow));
+    assert_eq!((-4isize).try_sqrt(), Err(ArithError::Domain));
+    assert_eq!(17usize.try_sqrt(), Ok(4));
+}
+
+#[test]
+fn test_into_box_dyn_error() {
+    // chapter18 の grep_main のように Box<dyn Error> を返す関数でも `?` が使える
+    fn average(total: u64, count: u64) -> Result<u64, Box<dyn Error>> {
+        Ok(total.try_div(count)?)
+    }
+
+    let err = average(10, 0).unwrap_err();
+    assert_eq!(err.to_string(), "division by zero");
+    assert_eq!(
+        err.downcast_ref::<ArithError>(),
+        Some(&ArithError::DivideByZero)
+    );
+}
@@ -0,0 +1,522 @@
+use crate::arith::{ArithError, CheckedArith};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::iter::Peekable;
+use std::ops::Range;
+
+/// 入力中の位置 (0 始まりの文字単位)
+pub type Span = Range<usize>;
+
+/// 計算機のエラーの種類
+#[derive(Debug, Clone, PartialEq)]
+pub enum CalcErrorKind {
+    /// 式に使えない文字
+    UnexpectedChar(char),
+    /// ここに来るはずのないトークン
+    UnexpectedToken(String),
+    /// 式が途中で終わっている
+    UnexpectedEnd,
+    /// 閉じられていない括弧
+    UnclosedParen,
+    /// 数値として解釈できないリテラル (整数モードでの小数、桁あふれなど)
+    InvalidNumber(String),
+    /// 評価中の算術エラー
+    Arith(ArithError),
+}
+
+impl fmt::Display for CalcErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CalcErrorKind::UnexpectedChar(c) => write!(f, "unexpected character {:?}", c),
+            CalcErrorKind::UnexpectedToken(t) => write!(f, "unexpected {:?}", t),
+            CalcErrorKind::UnexpectedEnd => write!(f, "unexpected end of input"),
+            CalcErrorKind::UnclosedParen => write!(f, "unclosed parenthesis"),
+            CalcErrorKind::InvalidNumber(n) => write!(f, "invalid number {:?}", n),
+            CalcErrorKind::Arith(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// 発生位置つきの計算機のエラー
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalcError {
+    pub kind: CalcErrorKind,
+    pub span: Span,
+}
+
+impl CalcError {
+    fn new(kind: CalcErrorKind, span: Span) -> CalcError {
+        CalcError { kind, span }
+    }
+
+    /// エラー位置の列番号 (1 始まり)
+    pub fn column(&self) -> usize {
+        self.span.start + 1
+    }
+}
+
+impl fmt::Display for CalcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at column {}", self.kind, self.column())
+    }
+}
+
+impl Error for CalcError {}
+
+/// 計算機で扱える数値型
+///
+/// `i64` は CheckedArith によるチェック付き整数演算、 `f64` は IEEE 754 の浮動小数点演算で、
+/// 結果が無限大や NaN になった場合をエラーとする。
+pub trait Operand: Copy + fmt::Display {
+    fn from_literal(s: &str) -> Option<Self>;
+    fn add(self, rhs: Self) -> Result<Self, ArithError>;
+    fn sub(self, rhs: Self) -> Result<Self, ArithError>;
+    fn mul(self, rhs: Self) -> Result<Self, ArithError>;
+    fn div(self, rhs: Self) -> Result<Self, ArithError>;
+    fn rem(self, rhs: Self) -> Result<Self, ArithError>;
+    fn neg(self) -> Result<Self, ArithError>;
+    fn pow(self, exp: Self) -> Result<Self, ArithError>;
+}
+
+impl Operand for i64 {
+    fn from_literal(s: &str) -> Option<Self> {
+        s.parse().ok()
+    }
+
+    fn add(self, rhs: Self) -> Result<Self, ArithError> {
+        self.try_add(rhs)
+    }
+
+    fn sub(self, rhs: Self) -> Result<Self, ArithError> {
+        self.try_sub(rhs)
+    }
+
+    fn mul(self, rhs: Self) -> Result<Self, ArithError> {
+        self.try_mul(rhs)
+    }
+
+    fn div(self, rhs: Self) -> Result<Self, ArithError> {
+        self.try_div(rhs)
+    }
+
+    fn rem(self, rhs: Self) -> Result<Self, ArithError> {
+        self.try_rem(rhs)
+    }
+
+    fn neg(self) -> Result<Self, ArithError> {
+        self.try_neg()
+    }
+
+    fn pow(self, exp: Self) -> Result<Self, ArithError> {
+        // 整数の範囲では負の指数を表現できない
+        if exp < 0 {
+            return Err(ArithError::Domain);
+        }
+        let exp = u32::try_from(exp).map_err(|_| ArithError::Overflow)?;
+        self.try_pow(exp)
+    }
+}
+
+/// 浮動小数点の演算結果をチェックする
+fn check_float(x: f64) -> Result<f64, ArithError> {
+    if x.is_nan() {
+        Err(ArithError::Domain)
+    } else if x == f64::INFINITY {
+        Err(ArithError::Overflow)
+    } else if x == f64::NEG_INFINITY {
+        Err(ArithError::Underflow)
+    } else {
+        Ok(x)
+    }
+}
+
+impl Operand for f64 {
+    fn from_literal(s: &str) -> Option<Self> {
+        s.parse().ok().filter(|x: &f64| x.is_finite())
+    }
+
+    fn add(self, rhs: Self) -> Result<Self, ArithError> {
+        check_float(self + rhs)
+    }
+
+    fn sub(self, rhs: Self) -> Result<Self, ArithError> {
+        check_float(self - rhs)
+    }
+
+    fn mul(self, rhs: Self) -> Result<Self, ArithError> {
+        check_float(self * rhs)
+    }
+
+    fn div(self, rhs: Self) -> Result<Self, ArithError> {
+        if rhs == 0.0 {
+            return Err(ArithError::DivideByZero);
+        }
+        check_float(self / rhs)
+    }
+
+    fn rem(self, rhs: Self) -> Result<Self, ArithError> {
+        if rhs == 0.0 {
+            return Err(ArithError::DivideByZero);
+        }
+        check_float(self % rhs)
+    }
+
+    fn neg(self) -> Result<Self, ArithError> {
+        Ok(-self)
+    }
+
+    fn pow(self, exp: Self) -> Result<Self, ArithError> {
+        if self == 0.0 && exp < 0.0 {
+            return Err(ArithError::DivideByZero);
+        }
+        check_float(self.powf(exp))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(String),
+    Op(char),
+    LParen,
+    RParen,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Op(c) => write!(f, "{}", c),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+        }
+    }
+}
+
+/// chapter15 の parse_number と同様に、 peek で次の文字を見ながら数字を読み進める
+fn parse_number<I>(chars: &mut Peekable<I>) -> String
+where
+    I: Iterator<Item = (usize, char)>,
+{
+    let mut n = String::new();
+    while let Some(&(_, c)) = chars.peek() {
+        if c.is_ascii_digit() || c == '.' {
+            n.push(c);
+        } else {
+            break;
+        }
+        chars.next();
+    }
+    n
+}
+
+fn tokenize(src: &str) -> Result<Vec<(Token, Span)>, CalcError> {
+    let mut tokens = vec![];
+    let mut chars = src.chars().enumerate().peekable();
+
+    while let Some(&(i, c)) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '0'..='9' | '.' => {
+                let n = parse_number(&mut chars);
+                let len = n.chars().count();
+                tokens.push((Token::Number(n), i..i + len));
+            }
+            '+' | '-' | '*' | '/' | '%' | '^' => {
+                chars.next();
+                tokens.push((Token::Op(c), i..i + 1));
+            }
+            '(' => {
+                chars.next();
+                tokens.push((Token::LParen, i..i + 1));
+            }
+            ')' => {
+                chars.next();
+                tokens.push((Token::RParen, i..i + 1));
+            }
+            _ => return Err(CalcError::new(CalcErrorKind::UnexpectedChar(c), i..i + 1)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// 構文木
+///
+/// 演算子のノードは、エラー位置として示すための演算子の位置を持つ。
+#[derive(Debug)]
+enum Expr {
+    Number(String, Span),
+    Neg(Box<Expr>, Span),
+    Binary(char, Box<Expr>, Box<Expr>, Span),
+}
+
+/// 再帰下降パーサ
+///
+/// ```text
+/// expr   = term (("+" | "-") term)*
+/// term   = unary (("*" | "/" | "%") unary)*
+/// unary  = "-" unary | power
+/// power  = atom ("^" unary)?
+/// atom   = NUMBER | "(" expr ")"
+/// ```
+struct Parser {
+    tokens: Peekable<std::vec::IntoIter<(Token, Span)>>,
+    /// 入力の末尾の位置 (式が途中で終わっていた場合のエラー位置)
+    end: usize,
+}
+
+impl Parser {
+    fn peek_op(&mut self, ops: &[char]) -> Option<(char, Span)> {
+        match self.tokens.peek() {
+            Some((Token::Op(c), span)) if ops.contains(c) => Some((*c, span.clone())),
+            _ => None,
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, CalcError> {
+        let mut lhs = self.term()?;
+        while let Some((op, span)) = self.peek_op(&['+', '-']) {
+            self.tokens.next();
+            let rhs = self.term()?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs), span);
+        }
+        Ok(lhs)
+    }
+
+    fn term(&mut self) -> Result<Expr, CalcError> {
+        let mut lhs = self.unary()?;
+        while let Some((op, span)) = self.peek_op(&['*', '/', '%']) {
+            self.tokens.next();
+            let rhs = self.unary()?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs), span);
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, CalcError> {
+        if let Some((_, span)) = self.peek_op(&['-']) {
+            self.tokens.next();
+            let operand = self.unary()?;
+            return Ok(Expr::Neg(Box::new(operand), span));
+        }
+        self.power()
+    }
+
+    fn power(&mut self) -> Result<Expr, CalcError> {
+        let base = self.atom()?;
+        if let Some((op, span)) = self.peek_op(&['^']) {
+            self.tokens.next();
+            // 右結合にするため、指数側は再帰的に読む
+            let exp = self.unary()?;
+            return Ok(Expr::Binary(op, Box::new(base), Box::new(exp), span));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Expr, CalcError> {
+        match self.tokens.next() {
+            Some((Token::Number(n), span)) => Ok(Expr::Number(n, span)),
+            Some((Token::LParen, open)) => {
+                let inner = self.expr()?;
+                match self.tokens.next() {
+                    Some((Token::RParen, _)) => Ok(inner),
+                    Some((t, span)) => Err(unexpected(t, span)),
+                    None => Err(CalcError::new(CalcErrorKind::UnclosedParen, open)),
+                }
+            }
+            Some((t, span)) => Err(unexpected(t, span)),
+            None => Err(CalcError::new(
+                CalcErrorKind::UnexpectedEnd,
+                self.end..self.end,
+            )),
+        }
+    }
+}
+
+fn unexpected(token: Token, span: Span) -> CalcError {
+    CalcError::new(CalcErrorKind::UnexpectedToken(token.to_string()), span)
+}
+
+fn parse(src: &str) -> Result<Expr, CalcError> {
+    let tokens = tokenize(src)?;
+    let mut parser = Parser {
+        tokens: tokens.into_iter().peekable(),
+        end: src.chars().count(),
+    };
+    let expr = parser.expr()?;
+    match parser.tokens.next() {
+        None => Ok(expr),
+        Some((t, span)) => Err(unexpected(t, span)),
+    }
+}
+
+fn evaluate<N: Operand>(expr: &Expr) -> Result<N, CalcError> {
+    let arith_err = |span: &Span| {
+        let span = span.clone();
+        move |e| CalcError::new(CalcErrorKind::Arith(e), span)
+    };
+
+    match expr {
+        Expr::Number(n, span) => N::from_literal(n)
+            .ok_or_else(|| CalcError::new(CalcErrorKind::InvalidNumber(n.clone()), span.clone())),
+        Expr::Neg(operand, span) => evaluate::<N>(operand)?.neg().map_err(arith_err(span)),
+        Expr::Binary(op, lhs, rhs, span) => {
+            let l = evaluate::<N>(lhs)?;
+            let r = evaluate::<N>(rhs)?;
+            let result = match op {
+                '+' => l.add(r),
+                '-' => l.sub(r),
+                '*' => l.mul(r),
+                '/' => l.div(r),
+                '%' => l.rem(r),
+                '^' => l.pow(r),
+                _ => unreachable!("the parser only produces known operators"),
+            };
+            result.map_err(arith_err(span))
+        }
+    }
+}
+
+/// 中置記法の式を解析し、 `N` の演算で評価する
+///
+/// ```
+/// use chapter7::calc::eval;
+///
+/// assert_eq!(eval::<i64>("1 + 2 * (3 - 1)"), Ok(5));
+/// assert_eq!(eval::<f64>("1 / 4"), Ok(0.25));
+///
+/// let err = eval::<i64>("1 + 2 / 0").unwrap_err();
+/// assert_eq!(err.to_string(), "division by zero at column 7");
+/// ```
+pub fn eval<N: Operand>(src: &str) -> Result<N, CalcError> {
+    evaluate(&parse(src)?)
+}
+
+/// repl で評価した行の結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ReplSummary {
+    /// エラーになった行の数
+    pub failed: usize,
+    /// 最後に評価した行がエラーになったか
+    pub last_failed: bool,
+}
+
+/// 1 行ずつ式を読み込んで評価結果を output に出力する
+///
+/// 空行は読み飛ばし、エラーが起きても次の行の処理を続ける。
+/// エラーの場合は、式と位置を示すキャレットとエラーメッセージを errors に出力する。
+pub fn repl<N, R, W, E>(input: R, mut output: W, mut errors: E) -> io::Result<ReplSummary>
+where
+    N: Operand,
+    R: BufRead,
+    W: Write,
+    E: Write,
+{
+    let mut summary = ReplSummary::default();
+    for line_result in input.lines() {
+        let line = line_result?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match eval::<N>(&line) {
+            Ok(v) => {
+                writeln!(output, "{}", v)?;
+                summary.last_failed = false;
+            }
+            Err(e) => {
+                // 結果と混ざらないよう、出力を先に書き出しておく
+                output.flush()?;
+                writeln!(errors, "{}", line)?;
+                let width = e.span.len().max(1);
+                writeln!(errors, "{}{}", " ".repeat(e.span.start), "^".repeat(width))?;
+                writeln!(errors, "error: {}", e)?;
+                summary.failed += 1;
+                summary.last_failed = true;
+            }
+        }
+    }
+    Ok(summary)
+}
+
+#[test]
+fn test_eval_integer() {
+    assert_eq!(eval::<i64>("42"), Ok(42));
+    assert_eq!(eval::<i64>("1 + 2 * 3"), Ok(7));
+    assert_eq!(eval::<i64>("(1 + 2) * 3"), Ok(9));
+    assert_eq!(eval::<i64>("10 - 4 - 3"), Ok(3));
+    assert_eq!(eval::<i64>("7 / 2 % 2"), Ok(1));
+    assert_eq!(eval::<i64>("-2 ^ 2"), Ok(-4));
+    assert_eq!(eval::<i64>("(-2) ^ 2"), Ok(4));
+    assert_eq!(eval::<i64>("2 ^ 3 ^ 2"), Ok(512));
+    assert_eq!(eval::<i64>("--3"), Ok(3));
+}
+
+#[test]
+fn test_eval_float() {
+    assert_eq!(eval::<f64>("1.5 * 2"), Ok(3.0));
+    assert_eq!(eval::<f64>("2 ^ -1"), Ok(0.5));
+    assert_eq!(eval::<f64>("7 % 2.5"), Ok(2.0));
+}
+
+#[test]
+fn test_eval_errors() {
+    fn err<N: Operand + fmt::Debug>(src: &str) -> String {
+        eval::<N>(src).unwrap_err().to_string()
+    }
+
+    assert_eq!(err::<i64>("1 + 2 / 0"), "division by zero at column 7");
+    assert_eq!(
+        err::<i64>("(1 - 1) % (3 - 3)"),
+        "division by zero at column 9"
+    );
+    assert_eq!(
+        err::<i64>("9223372036854775807 + 1"),
+        "arithmetic overflow at column 21"
+    );
+    assert_eq!(err::<i64>("2 ^ -1"), "argument out of domain at column 3");
+    assert_eq!(err::<i64>("1.5 + 1"), "invalid number \"1.5\" at column 1");
+    assert_eq!(err::<i64>("1 + x"), "unexpected character 'x' at column 5");
+    assert_eq!(err::<i64>("1 +"), "unexpected end of input at column 4");
+    assert_eq!(err::<i64>("(1 + 2"), "unclosed parenthesis at column 1");
+    assert_eq!(err::<i64>("1 2"), "unexpected \"2\" at column 3");
+    assert_eq!(err::<i64>("1 + )"), "unexpected \")\" at column 5");
+    assert_eq!(err::<f64>("1 / 0.0"), "division by zero at column 3");
+    assert_eq!(err::<f64>("10 ^ 400"), "arithmetic overflow at column 4");
+    assert_eq!(err::<f64>("1..2"), "invalid number \"1..2\" at column 1");
+}
+
+#[test]
+fn test_repl() {
+    let input = io::Cursor::new("1 + 1\n\n2 * (3 / 0)\n3\n");
+    let mut output = vec![];
+    let mut errors = vec![];
+    let summary = repl::<i64, _, _, _>(input, &mut output, &mut errors).unwrap();
+
+    assert_eq!(String::from_utf8(output).unwrap(), "2\n3\n");
+    assert_eq!(
+        String::from_utf8(errors).unwrap(),
+        "2 * (3 / 0)\n\
+         \x20      ^\n\
+         error: division by zero at column 8\n"
+    );
+    assert_eq!(
+        summary,
+        ReplSummary {
+            failed: 1,
+            last_failed: false
+        }
+    );
+
+    let input = io::Cursor::new("1 +\n");
+    let summary = repl::<i64, _, _, _>(input, io::sink(), io::sink()).unwrap();
+    assert_eq!(
+        summary,
+        ReplSummary {
+            failed: 1,
+            last_failed: true
+        }
+    );
+}
@@ -0,0 +1,2 @@
+pub mod arith;
+pub mod calc;
@@ -1,6 +1,40 @@
-use std::io;
+use chapter7::arith::{ArithError, CheckedArith};
+use chapter7::calc;
+use std::io::{self, IsTerminal};
 
+/// cargo run [COMMAND]
+///     COMMAND: repl [--float]
+///         標準入力から 1 行ずつ式を読み込んで計算結果を出力する
+///         --float を付けると f64 で計算する (デフォルトは i64)
+///         エラーは標準エラー出力に出力し、端末からの入力なら最後の行が、
+///         そうでなければいずれかの行がエラーになったとき終了コード 1 で終了する
 fn main() {
+    let mut args = std::env::args().skip(1);
+    if let Some(c) = args.next() {
+        if c != "repl" {
+            eprintln!("invalid command");
+            std::process::exit(1);
+        }
+        let float = args.any(|a| a == "--float");
+        let stdin = io::stdin();
+        let interactive = stdin.is_terminal();
+        let result = if float {
+            calc::repl::<f64, _, _, _>(stdin.lock(), io::stdout(), io::stderr())
+        } else {
+            calc::repl::<i64, _, _, _>(stdin.lock(), io::stdout(), io::stderr())
+        };
+        match result {
+            Ok(summary) if interactive && summary.last_failed => std::process::exit(1),
+            Ok(summary) if !interactive && summary.failed > 0 => std::process::exit(1),
+            Ok(_) => {}
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
     // let ans = divide(10, 0);
     let ans = match divide_safe(10, 0) {
         Ok(a) => a,
@@ -13,15 +47,18 @@ fn main() {
     println!("{}", ans)
 }
 
+// 0 で割ると panic するので、 main では divide_safe を使う
+#[cfg(test)]
 fn divide(num: usize, divisor: usize) -> usize {
     num / divisor
 }
 
-fn divide_safe(num: usize, divisor: usize) -> Result<usize, io::Error> {
-    if divisor == 0 {
-        let err_msg = "cannot divide with 0 value";
-        return Err(io::Error::new(io::ErrorKind::Other, err_msg));
-    }
+#[test]
+#[should_panic(expected = "divide by zero")]
+fn test_divide_panics() {
+    divide(10, 0);
+}
 
-    Ok(num / divisor)
+fn divide_safe(num: usize, divisor: usize) -> Result<usize, ArithError> {
+    num.try_div(divisor)
 }
@@ -1,14 +1,289 @@
-use std::ops::Range;
+use std::ops::{Range, RangeInclusive};
+
+mod set;
+mod tree;
+
+pub use set::RangeSet;
+pub use tree::IntervalTree;
+
+/// Operations shared by every kind of interval.
+///
+/// Implemented for half-open `Range<T>` and closed `RangeInclusive<T>`
+/// over any `T: Ord + Copy`. An interval with no points in it is empty,
+/// and empty intervals never overlap anything.
+pub trait Interval: Sized {
+    /// The type of the interval's endpoints.
+    type Point: Ord + Copy;
+
+    /// Return true if the interval has no points in it.
+    fn is_empty_interval(&self) -> bool;
+
+    /// Return true if `x` lies inside the interval.
+    fn contains_point(&self, x: Self::Point) -> bool;
+
+    /// Return true if the two intervals share at least one point.
+    fn overlaps(&self, other: &Self) -> bool;
+
+    /// Return the points common to both intervals, or `None` if there are
+    /// none.
+    fn intersection(&self, other: &Self) -> Option<Self>;
+
+    /// Return the smallest interval covering both, as long as that doesn't
+    /// add any points that weren't in either one. Disjoint intervals have
+    /// no such union, so this returns `None` for them.
+    fn union(&self, other: &Self) -> Option<Self>;
+
+    /// Return true if every point of `other` is also in `self`. An empty
+    /// interval is contained in everything.
+    fn encloses(&self, other: &Self) -> bool;
+}
+
+/// Intervals whose set difference can be expressed as intervals of the
+/// same kind.
+///
+/// `Range<T>` always qualifies. `RangeInclusive<T>` needs to step to the
+/// neighbouring value to exclude an endpoint, so `T` must be `Discrete`.
+pub trait Difference: Interval {
+    /// Return the points of `self` that aren't in `other`, as at most two
+    /// non-empty intervals in ascending order.
+    fn difference(&self, other: &Self) -> Vec<Self>;
+}
+
+/// Types whose values have an immediate predecessor and successor, such as
+/// the integers.
+pub trait Discrete: Ord + Copy {
+    /// The value just before `self`. Only called when `self` isn't the
+    /// minimum value.
+    fn pred(self) -> Self;
+
+    /// The value just after `self`. Only called when `self` isn't the
+    /// maximum value.
+    fn succ(self) -> Self;
+}
+
+macro_rules! impl_discrete {
+    ($($t:ty)*) => {
+        $(
+            impl Discrete for $t {
+                fn pred(self) -> Self {
+                    self - 1
+                }
+
+                fn succ(self) -> Self {
+                    self + 1
+                }
+            }
+        )*
+    };
+}
+
+impl_discrete!(u8 u16 u32 u64 u128 usize i8 i16 i32 i64 i128 isize);
+
+impl<T: Ord + Copy> Interval for Range<T> {
+    type Point = T;
+
+    fn is_empty_interval(&self) -> bool {
+        self.start >= self.end
+    }
+
+    fn contains_point(&self, x: T) -> bool {
+        self.start <= x && x < self.end
+    }
+
+    fn overlaps(&self, other: &Self) -> bool {
+        !self.is_empty_interval()
+            && !other.is_empty_interval()
+            && self.start < other.end
+            && self.end > other.start
+    }
+
+    fn intersection(&self, other: &Self) -> Option<Self> {
+        if !self.overlaps(other) {
+            return None;
+        }
+        Some(self.start.max(other.start)..self.end.min(other.end))
+    }
+
+    fn union(&self, other: &Self) -> Option<Self> {
+        if self.is_empty_interval() {
+            return Some(other.clone());
+        }
+        if other.is_empty_interval() {
+            return Some(self.clone());
+        }
+        // Half-open ranges that merely touch, like 0..3 and 3..5, still
+        // union to a single range.
+        if self.start > other.end || other.start > self.end {
+            return None;
+        }
+        Some(self.start.min(other.start)..self.end.max(other.end))
+    }
+
+    fn encloses(&self, other: &Self) -> bool {
+        other.is_empty_interval() || (self.start <= other.start && other.end <= self.end)
+    }
+}
+
+impl<T: Ord + Copy> Difference for Range<T> {
+    fn difference(&self, other: &Self) -> Vec<Self> {
+        if self.is_empty_interval() {
+            return vec![];
+        }
+        if !self.overlaps(other) {
+            return vec![self.clone()];
+        }
+
+        let mut pieces = vec![];
+        if self.start < other.start {
+            pieces.push(self.start..other.start);
+        }
+        if other.end < self.end {
+            pieces.push(other.end..self.end);
+        }
+        pieces
+    }
+}
+
+impl<T: Ord + Copy> Interval for RangeInclusive<T> {
+    type Point = T;
+
+    fn is_empty_interval(&self) -> bool {
+        self.start() > self.end()
+    }
+
+    fn contains_point(&self, x: T) -> bool {
+        *self.start() <= x && x <= *self.end()
+    }
+
+    fn overlaps(&self, other: &Self) -> bool {
+        !self.is_empty_interval()
+            && !other.is_empty_interval()
+            && self.start() <= other.end()
+            && self.end() >= other.start()
+    }
+
+    fn intersection(&self, other: &Self) -> Option<Self> {
+        if !self.overlaps(other) {
+            return None;
+        }
+        Some(*self.start().max(other.start())..=*self.end().min(other.end()))
+    }
+
+    /// Closed ranges only merge when they share a point: without knowing
+    /// the next value after `self.end()`, `0..=2` and `3..=5` can't be told
+    /// apart from `0.0..=2.0` and `3.0..=5.0`.
+    fn union(&self, other: &Self) -> Option<Self> {
+        if self.is_empty_interval() {
+            return Some(other.clone());
+        }
+        if other.is_empty_interval() {
+            return Some(self.clone());
+        }
+        if !self.overlaps(other) {
+            return None;
+        }
+        Some(*self.start().min(other.start())..=*self.end().max(other.end()))
+    }
+
+    fn encloses(&self, other: &Self) -> bool {
+        other.is_empty_interval() || (self.start() <= other.start() && other.end() <= self.end())
+    }
+}
+
+impl<T: Discrete> Difference for RangeInclusive<T> {
+    fn difference(&self, other: &Self) -> Vec<Self> {
+        if self.is_empty_interval() {
+            return vec![];
+        }
+        if !self.overlaps(other) {
+            return vec![self.clone()];
+        }
+
+        // Because the two overlap, `other.start()` is above `self.start()`
+        // whenever there's a left piece, so it has a predecessor; likewise
+        // `other.end()` has a successor whenever there's a right piece.
+        let mut pieces = vec![];
+        if self.start() < other.start() {
+            pieces.push(*self.start()..=other.start().pred());
+        }
+        if other.end() < self.end() {
+            pieces.push(other.end().succ()..=*self.end());
+        }
+        pieces
+    }
+}
 
 /// Return true if two ranges overlap.
 ///
-///     assert_eq!(ranges::overlap(0..7, 3..10), true);
-///     assert_eq!(ranges::overlap(1..5, 101..105), false);
+/// ```
+/// assert_eq!(ranges::overlap(0..7, 3..10), true);
+/// assert_eq!(ranges::overlap(1..5, 101..105), false);
+/// ```
 ///
 /// If either range is empty, they don't count as overlapping.
 ///
-///     assert_eq!(ranges::overlap(0..0, 0..10), false);
+/// ```
+/// assert_eq!(ranges::overlap(0..0, 0..10), false);
+/// ```
+///
+/// Closed ranges work too, and overlap if they share an endpoint.
+///
+/// ```
+/// assert_eq!(ranges::overlap(0..=3, 3..=10), true);
+/// ```
+pub fn overlap<I: Interval>(r1: I, r2: I) -> bool {
+    r1.overlaps(&r2)
+}
+
+/// Return the range of values common to both ranges, or `None` if they
+/// don't overlap.
+///
+/// ```
+/// assert_eq!(ranges::intersection(0..7, 3..10), Some(3..7));
+/// assert_eq!(ranges::intersection(1..5, 101..105), None);
+/// assert_eq!(ranges::intersection('a'..='m', 'h'..='z'), Some('h'..='m'));
+/// ```
+pub fn intersection<I: Interval>(r1: I, r2: I) -> Option<I> {
+    r1.intersection(&r2)
+}
+
+/// Return a single range covering both ranges, or `None` if there's a gap
+/// between them.
+///
+/// ```
+/// assert_eq!(ranges::union(0..7, 3..10), Some(0..10));
+/// assert_eq!(ranges::union(0..3, 3..10), Some(0..10));
+/// assert_eq!(ranges::union(0..3, 4..10), None);
+/// ```
+///
+/// An empty range adds nothing, so the union is just the other range.
+///
+/// ```
+/// assert_eq!(ranges::union(20..20, 3..10), Some(3..10));
+/// ```
+pub fn union<I: Interval>(r1: I, r2: I) -> Option<I> {
+    r1.union(&r2)
+}
+
+/// Return the parts of `r1` not covered by `r2`.
+///
+/// ```
+/// assert_eq!(ranges::difference(0..10, 3..5), vec![0..3, 5..10]);
+/// assert_eq!(ranges::difference(0..10, 5..20), vec![0..5]);
+/// assert_eq!(ranges::difference(0..10, 0..10), vec![]);
+/// assert_eq!(ranges::difference(0..=10, 3..=5), vec![0..=2, 6..=10]);
+/// ```
+pub fn difference<I: Difference>(r1: I, r2: I) -> Vec<I> {
+    r1.difference(&r2)
+}
+
+/// Return true if every value in `inner` is also in `outer`.
 ///
-pub fn overlap(r1: Range<usize>, r2: Range<usize>) -> bool {
-    r1.start < r1.end && r2.start < r2.end && r1.start < r2.end && r1.end > r2.start
+/// ```
+/// assert_eq!(ranges::contains(0..10, 3..5), true);
+/// assert_eq!(ranges::contains(0..10, 5..20), false);
+/// assert_eq!(ranges::contains(0..10, 50..50), true);
+/// ```
+pub fn contains<I: Interval>(outer: I, inner: I) -> bool {
+    outer.encloses(&inner)
 }
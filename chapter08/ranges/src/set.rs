@@ -0,0 +1,135 @@
+use std::ops::Range;
+
+use crate::{Difference, Interval};
+
+/// A set of values stored as sorted, disjoint half-open ranges.
+///
+/// The ranges are kept normalized: none is empty, none overlaps or touches
+/// another, and they're sorted by start. Inserting a range merges it with
+/// every range it overlaps or touches.
+///
+/// ```
+/// use ranges::RangeSet;
+///
+/// let mut set = RangeSet::new();
+/// set.insert(0..3);
+/// set.insert(10..12);
+/// set.insert(3..5);
+/// assert_eq!(set.ranges(), &[0..5, 10..12]);
+///
+/// set.insert(4..11);
+/// assert_eq!(set.ranges(), &[0..12]);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RangeSet<T> {
+    ranges: Vec<Range<T>>,
+}
+
+impl<T: Ord + Copy> RangeSet<T> {
+    /// Return a new, empty set.
+    pub fn new() -> RangeSet<T> {
+        RangeSet { ranges: vec![] }
+    }
+
+    /// Return the normalized ranges making up the set.
+    pub fn ranges(&self) -> &[Range<T>] {
+        &self.ranges
+    }
+
+    /// Return an iterator over the ranges making up the set.
+    pub fn iter(&self) -> std::slice::Iter<'_, Range<T>> {
+        self.ranges.iter()
+    }
+
+    /// Return the number of disjoint ranges in the set.
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    /// Return true if the set contains no values.
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Add every value in `range` to the set.
+    pub fn insert(&mut self, range: Range<T>) {
+        if range.is_empty_interval() {
+            return;
+        }
+
+        // `first` is the first range that ends at or after `range` starts,
+        // `last` the first one that starts after `range` ends. Everything
+        // in between overlaps or touches `range`, and gets merged with it.
+        let first = self.ranges.partition_point(|r| r.end < range.start);
+        let last = self.ranges.partition_point(|r| r.start <= range.end);
+
+        let mut merged = range;
+        if first < last {
+            merged.start = merged.start.min(self.ranges[first].start);
+            merged.end = merged.end.max(self.ranges[last - 1].end);
+        }
+        self.ranges.splice(first..last, std::iter::once(merged));
+    }
+
+    /// Remove every value in `range` from the set.
+    pub fn remove(&mut self, range: Range<T>) {
+        if range.is_empty_interval() {
+            return;
+        }
+
+        let first = self.ranges.partition_point(|r| r.end <= range.start);
+        let last = self.ranges.partition_point(|r| r.start < range.end);
+        if first >= last {
+            return;
+        }
+
+        let remaining: Vec<Range<T>> = self.ranges[first..last]
+            .iter()
+            .flat_map(|r| r.difference(&range))
+            .collect();
+        self.ranges.splice(first..last, remaining);
+    }
+
+    /// Return true if `x` is in the set.
+    pub fn contains(&self, x: T) -> bool {
+        let i = self.ranges.partition_point(|r| r.end <= x);
+        i < self.ranges.len() && self.ranges[i].contains_point(x)
+    }
+
+    /// Return true if any value in `range` is in the set.
+    pub fn overlaps(&self, range: &Range<T>) -> bool {
+        let i = self.ranges.partition_point(|r| r.end <= range.start);
+        i < self.ranges.len() && self.ranges[i].overlaps(range)
+    }
+}
+
+impl<T: Ord + Copy> Default for RangeSet<T> {
+    fn default() -> Self {
+        RangeSet::new()
+    }
+}
+
+impl<T: Ord + Copy> Extend<Range<T>> for RangeSet<T> {
+    fn extend<I: IntoIterator<Item = Range<T>>>(&mut self, iter: I) {
+        for range in iter {
+            self.insert(range);
+        }
+    }
+}
+
+impl<T: Ord + Copy> FromIterator<Range<T>> for RangeSet<T> {
+    fn from_iter<I: IntoIterator<Item = Range<T>>>(iter: I) -> Self {
+        let mut set = RangeSet::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl<'a, T> IntoIterator for &'a RangeSet<T> {
+    type Item = &'a Range<T>;
+    type IntoIter = std::slice::Iter<'a, Range<T>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.ranges.iter()
+    }
+}
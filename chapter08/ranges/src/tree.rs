@@ -0,0 +1,306 @@
+use std::cmp::Ordering;
+use std::ops::Range;
+
+use crate::Interval;
+
+/// A collection of ranges with attached values, indexed for fast overlap
+/// queries.
+///
+/// This is an AVL tree ordered by `(start, end)`, where each node also
+/// records the largest `end` in its subtree. Queries use that to skip
+/// subtrees that end before the query starts, so inserting, removing and
+/// querying all take time logarithmic in the number of entries, plus the
+/// number of matches reported.
+///
+/// The same range may be inserted more than once. Empty ranges can be
+/// stored, but never match a query, just as `overlap` never reports them.
+///
+/// ```
+/// use ranges::IntervalTree;
+///
+/// let mut meetings = IntervalTree::new();
+/// meetings.insert(9..11, "standup");
+/// meetings.insert(10..12, "review");
+/// meetings.insert(13..14, "lunch");
+///
+/// let names: Vec<_> = meetings.query_overlapping(&(11..13))
+///     .into_iter()
+///     .map(|(_, name)| *name)
+///     .collect();
+/// assert_eq!(names, vec!["review"]);
+///
+/// assert_eq!(meetings.query_point(10).len(), 2);
+/// ```
+pub struct IntervalTree<T, V> {
+    root: Link<T, V>,
+    len: usize,
+}
+
+type Link<T, V> = Option<Box<Node<T, V>>>;
+
+struct Node<T, V> {
+    range: Range<T>,
+    value: V,
+    /// The largest `range.end` anywhere in this subtree.
+    max_end: T,
+    height: usize,
+    left: Link<T, V>,
+    right: Link<T, V>,
+}
+
+impl<T: Ord + Copy, V> IntervalTree<T, V> {
+    /// Return a new, empty tree.
+    pub fn new() -> IntervalTree<T, V> {
+        IntervalTree { root: None, len: 0 }
+    }
+
+    /// Return the number of entries in the tree.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Return true if the tree has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Add `range` to the tree, carrying `value`.
+    pub fn insert(&mut self, range: Range<T>, value: V) {
+        self.root = Some(insert(self.root.take(), range, value));
+        self.len += 1;
+    }
+
+    /// Remove an entry whose range is exactly `range`, returning its value.
+    /// If several entries have that range, which one is removed is
+    /// unspecified.
+    pub fn remove(&mut self, range: &Range<T>) -> Option<V> {
+        let (root, removed) = remove(self.root.take(), range);
+        self.root = root;
+        if removed.is_some() {
+            self.len -= 1;
+        }
+        removed
+    }
+
+    /// Return every entry whose range overlaps `range`, ordered by
+    /// `(start, end)`.
+    pub fn query_overlapping(&self, range: &Range<T>) -> Vec<(&Range<T>, &V)> {
+        let mut found = vec![];
+        if !range.is_empty_interval() {
+            collect_overlapping(&self.root, range, &mut found);
+        }
+        found
+    }
+
+    /// Return every entry whose range contains `x`, ordered by
+    /// `(start, end)`.
+    pub fn query_point(&self, x: T) -> Vec<(&Range<T>, &V)> {
+        let mut found = vec![];
+        collect_point(&self.root, x, &mut found);
+        found
+    }
+
+    /// Return the number of nodes on the longest path from the root to a
+    /// leaf. An empty tree has height zero. The tree keeps itself balanced,
+    /// so this stays below `1.44 * log2(len + 2)`.
+    pub fn height(&self) -> usize {
+        height(&self.root)
+    }
+
+    /// Return every entry in the tree, ordered by `(start, end)`.
+    pub fn entries(&self) -> Vec<(&Range<T>, &V)> {
+        let mut found = vec![];
+        collect_all(&self.root, &mut found);
+        found
+    }
+}
+
+impl<T: Ord + Copy, V> Default for IntervalTree<T, V> {
+    fn default() -> Self {
+        IntervalTree::new()
+    }
+}
+
+impl<T: Ord + Copy, V> Node<T, V> {
+    fn new(range: Range<T>, value: V) -> Box<Node<T, V>> {
+        Box::new(Node {
+            max_end: range.end,
+            range,
+            value,
+            height: 1,
+            left: None,
+            right: None,
+        })
+    }
+
+    /// Recompute `height` and `max_end` from the children.
+    fn update(&mut self) {
+        self.height = 1 + height(&self.left).max(height(&self.right));
+        self.max_end = self.range.end;
+        for child in [&self.left, &self.right].into_iter().flatten() {
+            self.max_end = self.max_end.max(child.max_end);
+        }
+    }
+}
+
+fn height<T, V>(link: &Link<T, V>) -> usize {
+    link.as_ref().map_or(0, |node| node.height)
+}
+
+fn compare<T: Ord>(a: &Range<T>, b: &Range<T>) -> Ordering {
+    a.start.cmp(&b.start).then(a.end.cmp(&b.end))
+}
+
+fn rotate_right<T: Ord + Copy, V>(mut node: Box<Node<T, V>>) -> Box<Node<T, V>> {
+    let mut left = node.left.take().expect("rotate_right needs a left child");
+    node.left = left.right.take();
+    node.update();
+    left.right = Some(node);
+    left.update();
+    left
+}
+
+fn rotate_left<T: Ord + Copy, V>(mut node: Box<Node<T, V>>) -> Box<Node<T, V>> {
+    let mut right = node.right.take().expect("rotate_left needs a right child");
+    node.right = right.left.take();
+    node.update();
+    right.left = Some(node);
+    right.update();
+    right
+}
+
+/// Restore the AVL invariant at `node`, assuming its subtrees satisfy it
+/// and their heights differ by at most two.
+fn rebalance<T: Ord + Copy, V>(mut node: Box<Node<T, V>>) -> Box<Node<T, V>> {
+    node.update();
+    let left_height = height(&node.left);
+    let right_height = height(&node.right);
+
+    if left_height > right_height + 1 {
+        let left = node.left.take().unwrap();
+        node.left = Some(if height(&left.left) < height(&left.right) {
+            rotate_left(left)
+        } else {
+            left
+        });
+        return rotate_right(node);
+    }
+    if right_height > left_height + 1 {
+        let right = node.right.take().unwrap();
+        node.right = Some(if height(&right.right) < height(&right.left) {
+            rotate_right(right)
+        } else {
+            right
+        });
+        return rotate_left(node);
+    }
+    node
+}
+
+fn insert<T: Ord + Copy, V>(link: Link<T, V>, range: Range<T>, value: V) -> Box<Node<T, V>> {
+    let mut node = match link {
+        None => return Node::new(range, value),
+        Some(node) => node,
+    };
+    if compare(&range, &node.range) == Ordering::Less {
+        node.left = Some(insert(node.left.take(), range, value));
+    } else {
+        node.right = Some(insert(node.right.take(), range, value));
+    }
+    rebalance(node)
+}
+
+fn remove<T: Ord + Copy, V>(link: Link<T, V>, range: &Range<T>) -> (Link<T, V>, Option<V>) {
+    let mut node = match link {
+        None => return (None, None),
+        Some(node) => node,
+    };
+    match compare(range, &node.range) {
+        Ordering::Less => {
+            let (left, removed) = remove(node.left.take(), range);
+            node.left = left;
+            (Some(rebalance(node)), removed)
+        }
+        Ordering::Greater => {
+            let (right, removed) = remove(node.right.take(), range);
+            node.right = right;
+            (Some(rebalance(node)), removed)
+        }
+        Ordering::Equal => {
+            let Node {
+                value, left, right, ..
+            } = *node;
+            let rest = match (left, right) {
+                (None, only) | (only, None) => only,
+                (Some(left), Some(right)) => {
+                    // Replace this node with the smallest node of its right
+                    // subtree.
+                    let (right, mut successor) = remove_min(right);
+                    successor.left = Some(left);
+                    successor.right = right;
+                    Some(rebalance(successor))
+                }
+            };
+            (rest, Some(value))
+        }
+    }
+}
+
+/// Detach the leftmost node of the subtree rooted at `node`, returning the
+/// rest of the subtree and the detached node.
+fn remove_min<T: Ord + Copy, V>(mut node: Box<Node<T, V>>) -> (Link<T, V>, Box<Node<T, V>>) {
+    match node.left.take() {
+        None => (node.right.take(), node),
+        Some(left) => {
+            let (left, min) = remove_min(left);
+            node.left = left;
+            (Some(rebalance(node)), min)
+        }
+    }
+}
+
+fn collect_overlapping<'a, T: Ord + Copy, V>(
+    link: &'a Link<T, V>,
+    range: &Range<T>,
+    found: &mut Vec<(&'a Range<T>, &'a V)>,
+) {
+    let node = match link {
+        Some(node) if node.max_end > range.start => node,
+        // Nothing in this subtree ends after `range` starts.
+        _ => return,
+    };
+    collect_overlapping(&node.left, range, found);
+    if node.range.overlaps(range) {
+        found.push((&node.range, &node.value));
+    }
+    // Everything to the right starts at or after this node does.
+    if node.range.start < range.end {
+        collect_overlapping(&node.right, range, found);
+    }
+}
+
+fn collect_point<'a, T: Ord + Copy, V>(
+    link: &'a Link<T, V>,
+    x: T,
+    found: &mut Vec<(&'a Range<T>, &'a V)>,
+) {
+    let node = match link {
+        Some(node) if node.max_end > x => node,
+        _ => return,
+    };
+    collect_point(&node.left, x, found);
+    if node.range.contains_point(x) {
+        found.push((&node.range, &node.value));
+    }
+    if node.range.start <= x {
+        collect_point(&node.right, x, found);
+    }
+}
+
+fn collect_all<'a, T, V>(link: &'a Link<T, V>, found: &mut Vec<(&'a Range<T>, &'a V)>) {
+    if let Some(node) = link {
+        collect_all(&node.left, found);
+        found.push((&node.range, &node.value));
+        collect_all(&node.right, found);
+    }
+}
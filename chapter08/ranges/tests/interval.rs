@@ -0,0 +1,28 @@
+use ranges::{Difference, Interval};
+
+#[test]
+fn half_open_ranges() {
+    assert_eq!((0..10).intersection(&(10..20)), None);
+    assert_eq!((0..10).union(&(10..20)), Some(0..20));
+    assert_eq!((0..10).difference(&(20..30)), vec![0..10]);
+    assert!((0..10).encloses(&(0..10)));
+    assert!(!(0..10).contains_point(10));
+}
+
+#[test]
+fn closed_ranges() {
+    assert_eq!((0..=10).intersection(&(10..=20)), Some(10..=10));
+    assert_eq!((0..=9).union(&(10..=20)), None);
+    assert_eq!((0..=10).difference(&(0..=5)), vec![6..=10]);
+    assert!((0..=10).contains_point(10));
+}
+
+#[test]
+fn closed_ranges_at_type_limits() {
+    assert_eq!((0..=u8::MAX).difference(&(0..=254)), vec![255..=255]);
+    assert_eq!(
+        (i8::MIN..=i8::MAX).difference(&(-127..=127)),
+        vec![-128..=-128]
+    );
+    assert_eq!((0..=u8::MAX).difference(&(0..=u8::MAX)), vec![]);
+}
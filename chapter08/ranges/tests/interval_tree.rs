@@ -0,0 +1,133 @@
+use ranges::{overlap, IntervalTree};
+use std::ops::Range;
+
+/// A small linear congruential generator, so the tests are repeatable
+/// without pulling in a random number crate.
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self, bound: usize) -> usize {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        ((self.0 >> 33) as usize) % bound
+    }
+
+    fn range(&mut self) -> Range<usize> {
+        let start = self.next(1000);
+        start..start + self.next(50)
+    }
+}
+
+/// Return the entries of `entries` overlapping `query`, sorted the way
+/// the tree reports them.
+fn brute_force(entries: &[(Range<usize>, usize)], query: &Range<usize>) -> Vec<usize> {
+    let mut found: Vec<&(Range<usize>, usize)> = entries
+        .iter()
+        .filter(|(r, _)| overlap(r.clone(), query.clone()))
+        .collect();
+    found.sort_by_key(|(r, id)| (r.start, r.end, *id));
+    found.into_iter().map(|(_, id)| *id).collect()
+}
+
+fn ids(found: Vec<(&Range<usize>, &usize)>) -> Vec<usize> {
+    let mut ids: Vec<_> = found
+        .into_iter()
+        .map(|(r, id)| (r.start, r.end, *id))
+        .collect();
+    // Entries with identical ranges may come back in any order.
+    ids.sort();
+    ids.into_iter().map(|(_, _, id)| id).collect()
+}
+
+#[test]
+fn queries_match_brute_force() {
+    let mut rng = Lcg(1);
+    let mut tree = IntervalTree::new();
+    let mut entries = vec![];
+
+    for id in 0..2000 {
+        let range = rng.range();
+        tree.insert(range.clone(), id);
+        entries.push((range, id));
+    }
+
+    // Remove a third of the entries again, so removal and rebalancing get
+    // exercised as well.
+    for _ in 0..700 {
+        let range = entries[rng.next(entries.len())].0.clone();
+        // With duplicate ranges, the tree may drop any one of them, so
+        // remove whichever entry it reports.
+        let id = tree.remove(&range).unwrap();
+        entries.retain(|(_, i)| *i != id);
+    }
+    assert_eq!(tree.len(), entries.len());
+
+    for _ in 0..500 {
+        let query = rng.range();
+        assert_eq!(
+            ids(tree.query_overlapping(&query)),
+            brute_force(&entries, &query)
+        );
+
+        let x = rng.next(1100);
+        assert_eq!(ids(tree.query_point(x)), brute_force(&entries, &(x..x + 1)));
+    }
+}
+
+#[test]
+fn empty_ranges_never_match() {
+    let mut tree = IntervalTree::new();
+    tree.insert(5..5, "empty");
+    tree.insert(0..10, "full");
+
+    assert_eq!(tree.len(), 2);
+    assert_eq!(tree.query_point(5).len(), 1);
+    assert_eq!(tree.query_overlapping(&(3..8)).len(), 1);
+    assert!(tree.query_overlapping(&(3..3)).is_empty());
+}
+
+#[test]
+fn remove_missing_range() {
+    let mut tree = IntervalTree::new();
+    tree.insert(0..10, 'a');
+    assert_eq!(tree.remove(&(0..11)), None);
+    assert_eq!(tree.remove(&(0..10)), Some('a'));
+    assert!(tree.is_empty());
+    assert!(tree.query_point(3).is_empty());
+}
+
+#[test]
+fn sorted_inserts_stay_balanced() {
+    // Inserting in order would make a plain binary search tree a linked
+    // list; with 100,000 entries, queries would then take noticeably long.
+    let mut tree = IntervalTree::new();
+    for i in 0..100_000u32 {
+        tree.insert(i..i + 2, i);
+    }
+    for i in (0..100_000u32).step_by(1000) {
+        assert_eq!(tree.query_point(i + 1).len(), 2);
+    }
+    assert_eq!(tree.entries().len(), 100_000);
+    assert_balanced(&tree);
+
+    // Removing every other entry must rebalance as well.
+    for i in (0..100_000u32).step_by(2) {
+        assert_eq!(tree.remove(&(i..i + 2)), Some(i));
+    }
+    assert_eq!(tree.len(), 50_000);
+    assert_balanced(&tree);
+}
+
+/// Check the AVL height bound, which a degenerate tree would blow past.
+fn assert_balanced<V>(tree: &IntervalTree<u32, V>) {
+    let bound = 1.44 * ((tree.len() + 2) as f64).log2();
+    assert!(
+        tree.height() as f64 <= bound,
+        "height {} exceeds {:.1} for {} entries",
+        tree.height(),
+        bound,
+        tree.len()
+    );
+}
@@ -0,0 +1,265 @@
+//! Exhaustive property checks for the interval operations.
+//!
+//! Rather than sampling random inputs, these tests enumerate every range
+//! whose endpoints fall in a small window of values, and check every pair
+//! of them against a reference implementation that works on explicit sets
+//! of points. The window is placed both at zero and against the top of the
+//! type's range, so `usize::MAX`-style boundaries get the same coverage.
+
+use ranges::{Difference, Interval, IntervalTree, RangeSet};
+use std::ops::{Range, RangeInclusive};
+
+/// Number of distinct endpoint values in the window. Points are stored as
+/// bits of a `u32`, so this must stay below 32.
+const WIDTH: usize = 10;
+
+/// A set of points in the window, as a bitmask of offsets from its base.
+type Points = u32;
+
+/// Return true if the set bits of `points` form one unbroken run.
+fn is_contiguous(points: Points) -> bool {
+    if points == 0 {
+        return true;
+    }
+    let shifted = points >> points.trailing_zeros();
+    shifted & (shifted + 1) == 0
+}
+
+/// Return every half-open range with endpoints in `base..=base + WIDTH`,
+/// including empty and reversed ones.
+fn half_open_ranges(base: usize) -> Vec<Range<usize>> {
+    let mut ranges = vec![];
+    for start in 0..=WIDTH {
+        for end in 0..=WIDTH {
+            ranges.push(base + start..base + end);
+        }
+    }
+    ranges
+}
+
+/// Return every closed range with endpoints in `base..base + WIDTH`,
+/// including reversed (empty) ones.
+fn closed_ranges(base: usize) -> Vec<RangeInclusive<usize>> {
+    let mut ranges = vec![];
+    for start in 0..WIDTH {
+        for end in 0..WIDTH {
+            ranges.push(base + start..=base + end);
+        }
+    }
+    ranges
+}
+
+fn half_open_points(base: usize, r: &Range<usize>) -> Points {
+    (r.start..r.end).fold(0, |bits, x| bits | 1 << (x - base))
+}
+
+fn closed_points(base: usize, r: &RangeInclusive<usize>) -> Points {
+    r.clone().fold(0, |bits, x| bits | 1 << (x - base))
+}
+
+/// The window bases for half-open ranges: at zero, and with the last
+/// endpoint at `usize::MAX`.
+fn bases() -> [usize; 2] {
+    [0, usize::MAX - WIDTH]
+}
+
+/// The window bases for closed ranges, whose last point can be
+/// `usize::MAX` itself.
+fn closed_bases() -> [usize; 2] {
+    [0, usize::MAX - (WIDTH - 1)]
+}
+
+#[test]
+fn half_open_matches_reference() {
+    for base in bases() {
+        let all = half_open_ranges(base);
+        for a in &all {
+            let pa = half_open_points(base, a);
+            assert_eq!(a.is_empty_interval(), pa == 0, "{:?}", a);
+
+            for x in base..=base + WIDTH {
+                let expected = x - base < WIDTH && pa & 1 << (x - base) != 0;
+                assert_eq!(a.contains_point(x), expected, "{:?} contains {}", a, x);
+            }
+
+            for b in &all {
+                let pb = half_open_points(base, b);
+                let both = pa & pb;
+
+                // overlap is symmetric, and means sharing a point.
+                assert_eq!(a.overlaps(b), both != 0, "{:?} overlaps {:?}", a, b);
+                assert_eq!(a.overlaps(b), b.overlaps(a), "{:?} overlaps {:?}", a, b);
+                assert_eq!(ranges::overlap(a.clone(), b.clone()), both != 0);
+
+                // intersection is symmetric, and holds exactly the shared
+                // points.
+                let i = a.intersection(b);
+                assert_eq!(i, b.intersection(a), "{:?} & {:?}", a, b);
+                match &i {
+                    None => assert_eq!(both, 0, "{:?} & {:?}", a, b),
+                    Some(r) => {
+                        assert_eq!(half_open_points(base, r), both, "{:?} & {:?}", a, b);
+                        assert!(!r.is_empty_interval());
+                    }
+                }
+
+                // union exists exactly when the points form one run.
+                match a.union(b) {
+                    None => assert!(!is_contiguous(pa | pb), "{:?} | {:?}", a, b),
+                    Some(r) => {
+                        assert_eq!(half_open_points(base, &r), pa | pb, "{:?} | {:?}", a, b)
+                    }
+                }
+                assert_eq!(a.union(b).is_some(), b.union(a).is_some());
+
+                // difference holds exactly the points of `a` not in `b`, as
+                // non-empty, ascending, non-touching pieces.
+                let pieces = a.difference(b);
+                assert!(pieces.len() <= 2);
+                let covered = pieces
+                    .iter()
+                    .fold(0, |bits, r| bits | half_open_points(base, r));
+                assert_eq!(covered, pa & !pb, "{:?} - {:?}", a, b);
+                assert!(pieces.iter().all(|r| !r.is_empty_interval()));
+                assert!(pieces.windows(2).all(|w| w[0].end < w[1].start));
+
+                // encloses means every point of `b` is in `a`.
+                assert_eq!(a.encloses(b), pb & !pa == 0, "{:?} encloses {:?}", a, b);
+            }
+        }
+    }
+}
+
+#[test]
+fn closed_matches_reference() {
+    for base in closed_bases() {
+        let all = closed_ranges(base);
+        for a in &all {
+            let pa = closed_points(base, a);
+            assert_eq!(a.is_empty_interval(), pa == 0, "{:?}", a);
+
+            for b in &all {
+                let pb = closed_points(base, b);
+                let both = pa & pb;
+
+                assert_eq!(a.overlaps(b), both != 0, "{:?} overlaps {:?}", a, b);
+                assert_eq!(a.overlaps(b), b.overlaps(a));
+
+                match a.intersection(b) {
+                    None => assert_eq!(both, 0, "{:?} & {:?}", a, b),
+                    Some(r) => assert_eq!(closed_points(base, &r), both, "{:?} & {:?}", a, b),
+                }
+
+                // Closed ranges only union when one is empty or they share
+                // a point; merely adjacent ones are kept apart.
+                match a.union(b) {
+                    None => assert!(pa != 0 && pb != 0 && both == 0, "{:?} | {:?}", a, b),
+                    Some(r) => {
+                        assert_eq!(closed_points(base, &r), pa | pb, "{:?} | {:?}", a, b)
+                    }
+                }
+
+                let pieces = a.difference(b);
+                assert!(pieces.len() <= 2);
+                let covered = pieces
+                    .iter()
+                    .fold(0, |bits, r| bits | closed_points(base, r));
+                assert_eq!(covered, pa & !pb, "{:?} - {:?}", a, b);
+                assert!(pieces.iter().all(|r| !r.is_empty_interval()));
+                assert!(pieces.windows(2).all(|w| w[0].end() < w[1].start()));
+
+                assert_eq!(a.encloses(b), pb & !pa == 0, "{:?} encloses {:?}", a, b);
+            }
+        }
+    }
+}
+
+#[test]
+fn closed_ranges_reach_type_limits() {
+    // Closed ranges can include the maximum value itself, which half-open
+    // ones can't; make sure stepping past the endpoints never overflows.
+    for end in [u8::MAX - 1, u8::MAX] {
+        for cut in [0, 1, u8::MAX - 1, u8::MAX] {
+            let whole = 0..=end;
+            let pieces = whole.difference(&(cut..=cut));
+            let count: usize = pieces.iter().map(|r| r.clone().count()).sum();
+            let removed = if cut <= end { 1 } else { 0 };
+            assert_eq!(
+                count,
+                end as usize + 1 - removed,
+                "0..={} - {}..={}",
+                end,
+                cut,
+                cut
+            );
+        }
+    }
+    assert_eq!(
+        (i64::MIN..=i64::MAX).difference(&(i64::MIN..=i64::MAX)),
+        vec![]
+    );
+    assert_eq!(
+        (usize::MAX..=usize::MAX).intersection(&(0..=usize::MAX)),
+        Some(usize::MAX..=usize::MAX)
+    );
+}
+
+#[test]
+fn range_set_matches_reference() {
+    for base in bases() {
+        let all = half_open_ranges(base);
+        // Apply every range as an insertion, then every range as a
+        // removal, in a scrambled but fixed order, checking the set against
+        // a bitmask after each step.
+        let mut set = RangeSet::new();
+        let mut expected: Points = 0;
+        for step in 0..all.len() * 2 {
+            let r = &all[step * 37 % all.len()];
+            let bits = half_open_points(base, r);
+            if step % 3 == 2 {
+                set.remove(r.clone());
+                expected &= !bits;
+            } else {
+                set.insert(r.clone());
+                expected |= bits;
+            }
+
+            let actual = set.iter().fold(0, |acc, r| acc | half_open_points(base, r));
+            assert_eq!(actual, expected, "after step {} ({:?})", step, r);
+
+            // Normalized: non-empty, sorted, neither overlapping nor
+            // touching.
+            assert!(set.iter().all(|r| !r.is_empty_interval()));
+            assert!(set.ranges().windows(2).all(|w| w[0].end < w[1].start));
+
+            for x in base..base + WIDTH {
+                assert_eq!(set.contains(x), expected & 1 << (x - base) != 0);
+            }
+        }
+    }
+}
+
+#[test]
+fn interval_tree_matches_reference() {
+    for base in bases() {
+        let all = half_open_ranges(base);
+        let mut tree = IntervalTree::new();
+        for (id, r) in all.iter().enumerate() {
+            tree.insert(r.clone(), id);
+        }
+
+        for query in &all {
+            let pq = half_open_points(base, query);
+            let mut found: Vec<usize> = tree
+                .query_overlapping(query)
+                .into_iter()
+                .map(|(_, id)| *id)
+                .collect();
+            found.sort();
+            let expected: Vec<usize> = (0..all.len())
+                .filter(|&id| half_open_points(base, &all[id]) & pq != 0)
+                .collect();
+            assert_eq!(found, expected, "query {:?}", query);
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use ranges::RangeSet;
+
+#[test]
+fn insert_merges_overlapping_and_touching_ranges() {
+    let mut set = RangeSet::new();
+    set.insert(10..20);
+    set.insert(30..40);
+    set.insert(0..5);
+    assert_eq!(set.ranges(), &[0..5, 10..20, 30..40]);
+
+    set.insert(5..10);
+    assert_eq!(set.ranges(), &[0..20, 30..40]);
+
+    set.insert(15..35);
+    assert_eq!(set.len(), 1);
+    assert_eq!(set.ranges()[0], 0..40);
+}
+
+#[test]
+fn insert_ignores_empty_ranges() {
+    let mut set = RangeSet::new();
+    let (start, end) = (7, 3);
+    set.insert(5..5);
+    set.insert(start..end);
+    assert!(set.is_empty());
+}
+
+#[test]
+fn remove_splits_ranges() {
+    let mut set: RangeSet<u32> = vec![0..10, 20..30].into_iter().collect();
+    set.remove(5..25);
+    assert_eq!(set.ranges(), &[0..5, 25..30]);
+
+    set.remove(2..3);
+    assert_eq!(set.ranges(), &[0..2, 3..5, 25..30]);
+
+    set.remove(0..100);
+    assert!(set.is_empty());
+}
+
+#[test]
+fn contains_and_overlaps() {
+    let set: RangeSet<i64> = vec![-10..-5, 0..3].into_iter().collect();
+    assert!(set.contains(-10));
+    assert!(!set.contains(-5));
+    assert!(set.contains(2));
+    assert!(!set.contains(3));
+
+    assert!(set.overlaps(&(-6..0)));
+    assert!(!set.overlaps(&(-5..0)));
+    assert!(!set.overlaps(&(1..1)));
+}
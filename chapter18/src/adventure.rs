@@ -0,0 +1,478 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::rooms::{self, MapError, Problem, RoomId, RoomMap};
+
+/// アイテムの名前
+pub type ItemId = String;
+
+/// ゲームの世界
+///
+/// rooms は RoomMap と同じ形式。鍵のかかった出口も `[方向, 鍵になるアイテム]` の列で表す。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct World {
+    #[serde(serialize_with = "rooms::sorted")]
+    pub rooms: RoomMap,
+    /// 開始位置
+    pub start: RoomId,
+    /// 部屋ごとに置かれているアイテム
+    #[serde(default, serialize_with = "rooms::sorted")]
+    pub items: HashMap<RoomId, Vec<ItemId>>,
+    /// 部屋ごとの鍵のかかった出口 (方向と、通るのに必要なアイテム)
+    #[serde(default, serialize_with = "rooms::sorted")]
+    pub locks: HashMap<RoomId, Vec<(char, ItemId)>>,
+}
+
+impl World {
+    /// 世界の誤りを部屋の名前順に列挙する
+    pub fn validate(&self) -> Vec<Problem> {
+        let mut problems = rooms::validate(&self.rooms);
+        let unknown = |room: &RoomId| Problem::UnknownRoom { room: room.clone() };
+        if !self.rooms.contains_key(&self.start) {
+            problems.push(unknown(&self.start));
+        }
+        for room in self.items.keys() {
+            if !self.rooms.contains_key(room) {
+                problems.push(unknown(room));
+            }
+        }
+        for (room, locks) in &self.locks {
+            let Some(exits) = self.rooms.get(room) else {
+                problems.push(unknown(room));
+                continue;
+            };
+            for (direction, _) in locks {
+                if !exits.iter().any(|(d, _)| d == direction) {
+                    problems.push(Problem::LockWithoutExit {
+                        room: room.clone(),
+                        direction: *direction,
+                    });
+                }
+            }
+        }
+        rooms::sort_problems(&mut problems);
+        problems
+    }
+
+    /// JSON から世界を読み込み、誤りがないか確かめる
+    pub fn load<R: Read>(reader: R) -> Result<World, MapError> {
+        let world: World = serde_json::from_reader(reader)?;
+        let problems = world.validate();
+        if !problems.is_empty() {
+            return Err(MapError::Invalid(problems));
+        }
+        Ok(world)
+    }
+}
+
+/// ゲームの状態 (世界とプレイヤー)
+///
+/// save と load ではこの構造体をそのまま JSON で読み書きする。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Game {
+    pub world: World,
+    /// プレイヤーのいる部屋
+    pub location: RoomId,
+    /// プレイヤーの持ち物
+    #[serde(default)]
+    pub inventory: Vec<ItemId>,
+}
+
+impl Game {
+    /// 開始位置から新しくゲームを始める
+    pub fn new(world: World) -> Game {
+        Game {
+            location: world.start.clone(),
+            world,
+            inventory: vec![],
+        }
+    }
+
+    /// JSON からゲームの状態を読み込み、誤りがないか確かめる
+    pub fn load<R: Read>(reader: R) -> Result<Game, MapError> {
+        let game: Game = serde_json::from_reader(reader)?;
+        let mut problems = game.world.validate();
+        if !game.world.rooms.contains_key(&game.location) {
+            problems.push(Problem::UnknownRoom {
+                room: game.location.clone(),
+            });
+        }
+        if !problems.is_empty() {
+            return Err(MapError::Invalid(problems));
+        }
+        Ok(game)
+    }
+
+    /// ゲームの状態を JSON で書き出す
+    pub fn save<W: Write>(&self, writer: W) -> Result<(), MapError> {
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+
+    pub fn load_file(path: &Path) -> Result<Game, MapError> {
+        Game::load(BufReader::new(File::open(path)?))
+    }
+
+    pub fn save_file(&self, path: &Path) -> Result<(), MapError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.save(&mut writer)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// 今いる部屋の出口 direction を通るのに必要で、持っていないアイテム
+    fn missing_key(&self, direction: char) -> Option<&str> {
+        self.world
+            .locks
+            .get(&self.location)?
+            .iter()
+            .find(|(d, key)| *d == direction && !self.inventory.contains(key))
+            .map(|(_, key)| key.as_str())
+    }
+
+    /// 今いる部屋の様子を出力する
+    pub fn describe<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "== {} ==", self.location)?;
+        if let Some(items) = self.world.items.get(&self.location) {
+            if !items.is_empty() {
+                writeln!(out, "You see: {}", items.join(", "))?;
+            }
+        }
+        let exits: Vec<String> = self.world.rooms[&self.location]
+            .iter()
+            .map(|(direction, target)| match self.missing_key(*direction) {
+                Some(_) => format!("{} ({}, locked)", direction, target),
+                None => format!("{} ({})", direction, target),
+            })
+            .collect();
+        if exits.is_empty() {
+            writeln!(out, "There is no way out.")
+        } else {
+            writeln!(out, "Exits: {}", exits.join(", "))
+        }
+    }
+
+    /// コマンドを 1 つ実行し、結果を out に出力する
+    ///
+    /// ゲームを続けるなら true を返す。
+    pub fn apply<W: Write>(&mut self, action: Action, out: &mut W) -> io::Result<bool> {
+        match action {
+            Action::Go(direction) => {
+                // 方向は大文字と小文字を区別しない
+                let exit = self.world.rooms[&self.location]
+                    .iter()
+                    .find(|(d, _)| d.eq_ignore_ascii_case(&direction))
+                    .cloned();
+                match exit {
+                    None => writeln!(out, "You can't go that way.")?,
+                    Some((direction, target)) => {
+                        if let Some(key) = self.missing_key(direction) {
+                            writeln!(
+                                out,
+                                "The way {} is locked. You need the {}.",
+                                direction, key
+                            )?;
+                        } else {
+                            self.location = target;
+                            self.describe(out)?;
+                        }
+                    }
+                }
+            }
+            Action::Look => self.describe(out)?,
+            Action::Inventory => {
+                if self.inventory.is_empty() {
+                    writeln!(out, "You are carrying nothing.")?;
+                } else {
+                    writeln!(out, "You are carrying: {}", self.inventory.join(", "))?;
+                }
+            }
+            Action::Take(item) => {
+                // アイテムのない部屋に空のリストを作らないよう、見つかったときだけ変更する
+                let found = self.world.items.get_mut(&self.location).and_then(|items| {
+                    let i = items.iter().position(|i| *i == item)?;
+                    Some(items.remove(i))
+                });
+                match found {
+                    Some(taken) => {
+                        self.inventory.push(taken);
+                        writeln!(out, "Taken: {}", item)?;
+                    }
+                    None => writeln!(out, "There is no {} here.", item)?,
+                }
+            }
+            Action::Drop(item) => match self.inventory.iter().position(|i| *i == item) {
+                Some(i) => {
+                    let item = self.inventory.remove(i);
+                    writeln!(out, "Dropped: {}", item)?;
+                    self.world
+                        .items
+                        .entry(self.location.clone())
+                        .or_default()
+                        .push(item);
+                }
+                None => writeln!(out, "You don't have {}.", item)?,
+            },
+            Action::Save(path) => match self.save_file(&path) {
+                Ok(()) => writeln!(out, "Saved to {}.", path.display())?,
+                Err(e) => writeln!(out, "Could not save: {}", e)?,
+            },
+            Action::Load(path) => match Game::load_file(&path) {
+                Ok(game) => {
+                    *self = game;
+                    writeln!(out, "Loaded {}.", path.display())?;
+                    self.describe(out)?;
+                }
+                Err(e) => writeln!(out, "Could not load: {}", e)?,
+            },
+            Action::Help => writeln!(out, "{}", HELP)?,
+            Action::Quit => {
+                writeln!(out, "Bye.")?;
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// help コマンドで表示する説明
+const HELP: &str = "Commands: go DIRECTION, look, inventory, take ITEM, drop ITEM, \
+                    save FILE, load FILE, help, quit";
+
+/// プレイヤーのコマンド
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Go(char),
+    Look,
+    Inventory,
+    Take(ItemId),
+    Drop(ItemId),
+    Save(PathBuf),
+    Load(PathBuf),
+    Help,
+    Quit,
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    /// 1 行のコマンドを解釈する
+    ///
+    /// アイテムの名前やファイル名には空白を含めてもよい。
+    fn from_str(line: &str) -> Result<Action, String> {
+        let line = line.trim();
+        let (command, rest) = match line.split_once(char::is_whitespace) {
+            Some((command, rest)) => (command, rest.trim()),
+            None => (line, ""),
+        };
+        let argument = |what: &str| {
+            if rest.is_empty() {
+                Err(format!("{} what? ({} {})", command, command, what))
+            } else {
+                Ok(rest.to_string())
+            }
+        };
+        match command.to_lowercase().as_str() {
+            "go" => {
+                let mut chars = rest.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(direction), None) => Ok(Action::Go(direction)),
+                    _ => Err("go where? (go DIRECTION)".to_string()),
+                }
+            }
+            "look" | "l" => Ok(Action::Look),
+            "inventory" | "i" => Ok(Action::Inventory),
+            "take" | "get" => argument("ITEM").map(Action::Take),
+            "drop" => argument("ITEM").map(Action::Drop),
+            "save" => argument("FILE").map(|f| Action::Save(f.into())),
+            "load" => argument("FILE").map(|f| Action::Load(f.into())),
+            "help" | "?" => Ok(Action::Help),
+            "quit" | "exit" => Ok(Action::Quit),
+            _ => Err(format!("I don't understand {:?}. Type help.", line)),
+        }
+    }
+}
+
+/// input からコマンドを 1 行ずつ読んでゲームを進める
+///
+/// quit か入力の終わりで終了する。
+pub fn play<R: BufRead, W: Write>(game: &mut Game, input: R, out: &mut W) -> io::Result<()> {
+    game.describe(out)?;
+    write!(out, "> ")?;
+    out.flush()?;
+    for line in input.lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            match line.parse::<Action>() {
+                Ok(action) => {
+                    if !game.apply(action, out)? {
+                        return Ok(());
+                    }
+                }
+                Err(e) => writeln!(out, "{}", e)?,
+            }
+        }
+        write!(out, "> ")?;
+        out.flush()?;
+    }
+    writeln!(out)
+}
+
+/// play のコマンドライン引数
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    #[arg(help = "World file (JSON)")]
+    pub world: PathBuf,
+}
+
+/// play コマンドのエントリポイント
+pub fn play_main(args: Args) -> Result<i32, Box<dyn Error>> {
+    let world = World::load(BufReader::new(File::open(&args.world)?))?;
+    let mut game = Game::new(world);
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    play(&mut game, stdin.lock(), &mut stdout.lock())?;
+    Ok(0)
+}
+
+#[cfg(test)]
+const SAMPLE_WORLD: &str = r#"{
+    "rooms": {
+        "Hall": [["N", "Kitchen"], ["E", "Vault"]],
+        "Kitchen": [["S", "Hall"]],
+        "Vault": [["W", "Hall"]]
+    },
+    "start": "Hall",
+    "items": {"Kitchen": ["key", "bread"]},
+    "locks": {"Hall": [["E", "key"]]}
+}"#;
+
+/// script をコマンドとして入力し、出力を返す
+#[cfg(test)]
+fn run_script(game: &mut Game, script: &str) -> String {
+    let mut out = vec![];
+    play(game, script.as_bytes(), &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn test_play() {
+    let mut game = Game::new(World::load(SAMPLE_WORLD.as_bytes()).unwrap());
+    let output = run_script(
+        &mut game,
+        "go e\ngo n\ntake key\ntake sword\ninventory\ngo S\ngo E\ndance\nquit\nlook\n",
+    );
+    assert_eq!(
+        output,
+        "== Hall ==\n\
+         Exits: N (Kitchen), E (Vault, locked)\n\
+         > The way E is locked. You need the key.\n\
+         > == Kitchen ==\n\
+         You see: key, bread\n\
+         Exits: S (Hall)\n\
+         > Taken: key\n\
+         > There is no sword here.\n\
+         > You are carrying: key\n\
+         > == Hall ==\n\
+         Exits: N (Kitchen), E (Vault)\n\
+         > == Vault ==\n\
+         Exits: W (Hall)\n\
+         > I don't understand \"dance\". Type help.\n\
+         > Bye.\n"
+    );
+    assert_eq!(game.location, "Vault");
+    assert_eq!(game.world.items["Kitchen"], vec!["bread"]);
+
+    // 入力が終われば終了する
+    let output = run_script(&mut game, "go x\ndrop key\n");
+    assert!(output.ends_with("> You can't go that way.\n> Dropped: key\n> \n"));
+    assert_eq!(game.world.items["Vault"], vec!["key"]);
+}
+
+#[test]
+fn test_save_and_load() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("save.json");
+    let mut game = Game::new(World::load(SAMPLE_WORLD.as_bytes()).unwrap());
+    let output = run_script(
+        &mut game,
+        &format!("go N\ntake bread\nsave {}\n", path.display()),
+    );
+    assert!(output.contains(&format!("> Saved to {}.\n", path.display())));
+    let saved = game.clone();
+
+    // 保存した後に進めた状態は load で元に戻る
+    let output = run_script(
+        &mut game,
+        &format!("drop bread\ngo S\nload {}\ninventory\n", path.display()),
+    );
+    assert!(output.contains("== Kitchen ==\nYou see: key\n"));
+    assert!(output.contains("> You are carrying: bread\n"));
+    assert_eq!(game, saved);
+
+    let output = run_script(&mut game, "load /nonexistent/save.json\nsave\n");
+    assert!(output.contains("> Could not load: "));
+    assert!(output.contains("> save what? (save FILE)\n"));
+    assert_eq!(game, saved);
+
+    // 取れなかったアイテムのために、部屋の空のリストが保存されることはない
+    let output = run_script(
+        &mut game,
+        &format!("go S\ntake sword\nsave {}\n", path.display()),
+    );
+    assert!(output.contains("> There is no sword here.\n"));
+    assert!(!game.world.items.contains_key("Hall"));
+    let loaded = Game::load_file(&path).unwrap();
+    assert!(!loaded.world.items.contains_key("Hall"));
+    assert_eq!(loaded, game);
+}
+
+#[test]
+fn test_invalid_world() {
+    let json = r#"{
+        "rooms": {"A": [["N", "B"]], "B": []},
+        "start": "C",
+        "items": {"D": ["coin"]},
+        "locks": {"A": [["S", "key"]]}
+    }"#;
+    match World::load(json.as_bytes()) {
+        Err(MapError::Invalid(problems)) => assert_eq!(
+            problems,
+            vec![
+                Problem::LockWithoutExit {
+                    room: "A".to_string(),
+                    direction: 'S'
+                },
+                Problem::UnknownRoom {
+                    room: "C".to_string()
+                },
+                Problem::UnknownRoom {
+                    room: "D".to_string()
+                },
+            ]
+        ),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_action() {
+    assert_eq!("go N".parse(), Ok(Action::Go('N')));
+    assert_eq!("  LOOK ".parse(), Ok(Action::Look));
+    assert_eq!("i".parse(), Ok(Action::Inventory));
+    assert_eq!(
+        "take rusty key".parse(),
+        Ok(Action::Take("rusty key".to_string()))
+    );
+    assert!("go".parse::<Action>().is_err());
+    assert!("go north".parse::<Action>().is_err());
+    assert!("drop".parse::<Action>().is_err());
+}
@@ -0,0 +1,240 @@
+use std::io::{self, Write};
+
+/// 差分を表す 1 つの操作
+///
+/// 値は変更前 (Delete, Equal の 1 つ目) と変更後 (Insert, Equal の 2 つ目) の要素の位置。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Edit {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// old を new に変える最短の編集手順を求める (Myers のアルゴリズム)
+///
+/// 変更の数を D とすると、 O((N + M) * D) の時間と O(D^2) のメモリで求まる。
+pub fn diff<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Edit> {
+    let n = old.len() as isize;
+    let m = new.len() as isize;
+    let max = n + m;
+    // v[k + max] は、対角線 k (= x - y) 上で到達できた最も遠い x
+    let mut v = vec![0isize; 2 * max as usize + 2];
+    // 各ステップ d の後の v[-d..=d] を、後で経路を辿り直すために残しておく
+    let mut trace: Vec<Vec<isize>> = vec![];
+
+    'search: for d in 0..=max {
+        for k in (-d..=d).step_by(2) {
+            let i = (k + max) as usize;
+            // 上 (挿入) から来るか、左 (削除) から来るか
+            let mut x = if k == -d || (k != d && v[i - 1] < v[i + 1]) {
+                v[i + 1]
+            } else {
+                v[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[i] = x;
+            if x >= n && y >= m {
+                trace.push(v[(max - d) as usize..=(max + d) as usize].to_vec());
+                break 'search;
+            }
+        }
+        trace.push(v[(max - d) as usize..=(max + d) as usize].to_vec());
+    }
+
+    // 終点から逆向きに辿る
+    let mut edits = vec![];
+    let (mut x, mut y) = (n, m);
+    for d in (1..trace.len() as isize).rev() {
+        let prev = &trace[d as usize - 1];
+        let at = |k: isize| prev[(k + d - 1) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = at(prev_k);
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            edits.push(Edit::Equal(x as usize, y as usize));
+        }
+        if x == prev_x {
+            y -= 1;
+            edits.push(Edit::Insert(y as usize));
+        } else {
+            x -= 1;
+            edits.push(Edit::Delete(x as usize));
+        }
+    }
+    while x > 0 {
+        x -= 1;
+        y -= 1;
+        edits.push(Edit::Equal(x as usize, y as usize));
+    }
+
+    edits.reverse();
+    edits
+}
+
+/// hunk のヘッダの行範囲 (1 行なら行数を省く)
+fn hunk_range(start: usize, len: usize) -> String {
+    match len {
+        // 空の範囲は直前の行の番号で表す
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, len),
+    }
+}
+
+/// 1 行分を `+` などの記号付きで出力する
+fn write_line<W: Write>(out: &mut W, mark: u8, line: &[u8]) -> io::Result<()> {
+    out.write_all(&[mark])?;
+    out.write_all(line)?;
+    if !line.ends_with(b"\n") {
+        out.write_all(b"\n\\ No newline at end of file\n")?;
+    }
+    Ok(())
+}
+
+/// old と new の行単位の差分を、前後 context 行を含む unified 形式で出力する
+///
+/// 差分がなければ何も出力せず false を返す。
+pub fn write_unified<W: Write>(
+    out: &mut W,
+    old_name: &str,
+    new_name: &str,
+    old: &[u8],
+    new: &[u8],
+    context: usize,
+) -> io::Result<bool> {
+    let a: Vec<&[u8]> = old.split_inclusive(|&b| b == b'\n').collect();
+    let b: Vec<&[u8]> = new.split_inclusive(|&b| b == b'\n').collect();
+    let edits = diff(&a, &b);
+    let changes: Vec<usize> = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| !matches!(e, Edit::Equal(..)))
+        .map(|(i, _)| i)
+        .collect();
+    if changes.is_empty() {
+        return Ok(false);
+    }
+
+    writeln!(out, "--- {}", old_name)?;
+    writeln!(out, "+++ {}", new_name)?;
+
+    // 変更の間の共通部分が context * 2 行以下なら、同じ hunk にまとめる
+    let mut i = 0;
+    while i < changes.len() {
+        let mut j = i;
+        while j + 1 < changes.len() && changes[j + 1] - changes[j] <= context * 2 + 1 {
+            j += 1;
+        }
+        let start = changes[i].saturating_sub(context);
+        let end = (changes[j] + context + 1).min(edits.len());
+        let hunk = &edits[start..end];
+
+        // hunk の先頭より前にある行数
+        let (old_start, new_start) = edits[..start].iter().fold((0, 0), |(o, n), e| match e {
+            Edit::Equal(..) => (o + 1, n + 1),
+            Edit::Delete(_) => (o + 1, n),
+            Edit::Insert(_) => (o, n + 1),
+        });
+        let old_len = hunk
+            .iter()
+            .filter(|e| !matches!(e, Edit::Insert(_)))
+            .count();
+        let new_len = hunk
+            .iter()
+            .filter(|e| !matches!(e, Edit::Delete(_)))
+            .count();
+        writeln!(
+            out,
+            "@@ -{} +{} @@",
+            hunk_range(old_start, old_len),
+            hunk_range(new_start, new_len)
+        )?;
+        for edit in hunk {
+            match *edit {
+                Edit::Equal(x, _) => write_line(out, b' ', a[x])?,
+                Edit::Delete(x) => write_line(out, b'-', a[x])?,
+                Edit::Insert(y) => write_line(out, b'+', b[y])?,
+            }
+        }
+        i = j + 1;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+fn unified(old: &str, new: &str) -> String {
+    let mut out = vec![];
+    write_unified(&mut out, "a", "b", old.as_bytes(), new.as_bytes(), 1).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn test_diff() {
+    let old: Vec<char> = "ABCABBA".chars().collect();
+    let new: Vec<char> = "CBABAC".chars().collect();
+    let edits = diff(&old, &new);
+    // 最短の編集は 5 操作
+    assert_eq!(
+        edits
+            .iter()
+            .filter(|e| !matches!(e, Edit::Equal(..)))
+            .count(),
+        5
+    );
+
+    // 編集手順を当てはめると new になる
+    let mut result = vec![];
+    for edit in &edits {
+        match *edit {
+            Edit::Equal(x, y) => {
+                assert_eq!(old[x], new[y]);
+                result.push(old[x]);
+            }
+            Edit::Insert(y) => result.push(new[y]),
+            Edit::Delete(_) => {}
+        }
+    }
+    assert_eq!(result, new);
+
+    assert_eq!(diff::<u8>(&[], &[]), vec![]);
+    assert_eq!(diff(&[1], &[]), vec![Edit::Delete(0)]);
+    assert_eq!(diff(&[], &[1]), vec![Edit::Insert(0)]);
+    assert_eq!(diff(&[1], &[1]), vec![Edit::Equal(0, 0)]);
+}
+
+#[test]
+fn test_write_unified() {
+    assert_eq!(unified("a\nb\n", "a\nb\n"), "");
+
+    assert_eq!(
+        unified(
+            "1\n2\n3\n4\n5\n6\n7\n8\n",
+            "1\n2\nthree\n4\n5\n6\n7\nEIGHT\n"
+        ),
+        "--- a\n+++ b\n\
+         @@ -2,3 +2,3 @@\n 2\n-3\n+three\n 4\n\
+         @@ -7,2 +7,2 @@\n 7\n-8\n+EIGHT\n"
+    );
+    // 間の共通部分が短ければ 1 つの hunk にまとめる
+    assert_eq!(
+        unified("1\n2\n3\n4\n", "one\n2\n3\nfour\n"),
+        "--- a\n+++ b\n@@ -1,4 +1,4 @@\n-1\n+one\n 2\n 3\n-4\n+four\n"
+    );
+    assert_eq!(unified("", "new\n"), "--- a\n+++ b\n@@ -0,0 +1 @@\n+new\n");
+    assert_eq!(
+        unified("x\ny", "x\nz"),
+        "--- a\n+++ b\n@@ -1,2 +1,2 @@\n x\n-y\n\\ No newline at end of file\n\
+         +z\n\\ No newline at end of file\n"
+    );
+}
@@ -0,0 +1,376 @@
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use crate::fsutil::{file_hash, file_id, plural};
+use crate::walk::{self, PathError, WalkOptions};
+
+/// 部分ハッシュで読み込む先頭のバイト数
+const PARTIAL_SIZE: u64 = 4096;
+
+/// dupes のコマンドライン引数
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    #[command(flatten)]
+    pub walk: WalkOptions,
+    /// --threads: ハッシュ値の計算に使うスレッド数 (0 なら CPU 数)
+    #[arg(
+        long,
+        value_name = "NUM",
+        default_value_t = 0,
+        hide_default_value = true,
+        help = "Number of threads to hash with (0 uses one per CPU)"
+    )]
+    pub threads: usize,
+    /// 空のファイルはすべて同じ内容なので、既定では対象にしない
+    #[arg(
+        long,
+        value_name = "BYTES",
+        default_value_t = 1,
+        help = "Ignore files smaller than BYTES"
+    )]
+    pub min_size: u64,
+    #[arg(long, help = "Print the duplicate groups as JSON")]
+    pub json: bool,
+    #[arg(
+        long,
+        help = "Replace duplicates with hard links to the first file of each group"
+    )]
+    pub link: bool,
+    #[arg(
+        short = 'n',
+        long,
+        requires = "link",
+        help = "With --link, only print what would be linked"
+    )]
+    pub dry_run: bool,
+    #[arg(default_value = ".", help = "Files or directories to search")]
+    pub paths: Vec<PathBuf>,
+}
+
+/// 同じ内容のファイルの組
+#[derive(Debug, PartialEq, Serialize)]
+pub struct DuplicateGroup {
+    pub size: u64,
+    /// 内容の BLAKE3 ハッシュ値 (16 進数)
+    pub hash: String,
+    /// 名前順に並べたパス
+    pub paths: Vec<PathBuf>,
+}
+
+impl DuplicateGroup {
+    /// 1 つを残して重複を消した場合に空く容量
+    pub fn wasted(&self) -> u64 {
+        self.size * (self.paths.len() as u64 - 1)
+    }
+}
+
+/// ファイルの先頭 PARTIAL_SIZE バイトのハッシュ値
+fn partial_hash(path: &Path) -> io::Result<blake3::Hash> {
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut File::open(path)?.take(PARTIAL_SIZE), &mut hasher)?;
+    Ok(hasher.finalize())
+}
+
+/// 各グループのファイルのハッシュ値を並列に計算し、ハッシュ値ごとに分け直す
+///
+/// 2 つ以上のファイルがあるグループだけを残す。読み込めなかったファイルは errors に加える。
+fn regroup<K, F>(
+    groups: Vec<(K, Vec<PathBuf>)>,
+    hash: F,
+    errors: &mut Vec<PathError>,
+) -> Vec<((K, blake3::Hash), Vec<PathBuf>)>
+where
+    K: Clone + Eq + std::hash::Hash + Send,
+    F: Fn(&Path) -> io::Result<blake3::Hash> + Sync,
+{
+    let hashed: Vec<(K, PathBuf, io::Result<blake3::Hash>)> = groups
+        .into_par_iter()
+        .flat_map_iter(|(key, paths)| paths.into_iter().map(move |p| (key.clone(), p)))
+        .map(|(key, path)| {
+            let h = hash(&path);
+            (key, path, h)
+        })
+        .collect();
+
+    let mut regrouped: HashMap<(K, blake3::Hash), Vec<PathBuf>> = HashMap::new();
+    for (key, path, h) in hashed {
+        match h {
+            Ok(h) => regrouped.entry((key, h)).or_default().push(path),
+            Err(error) => errors.push(PathError { path, error }),
+        }
+    }
+    regrouped
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .collect()
+}
+
+/// files の中から同じ内容のファイルを探す
+///
+/// サイズ、先頭部分のハッシュ値、内容全体のハッシュ値の順に絞り込むので、
+/// サイズが他と違うファイルは読み込まない。既に互いにハードリンクになっているパスは
+/// 1 つのファイルとみなし、最初のパスだけを使う。
+/// ハッシュ値の計算は呼び出し側のスレッドプールで並列に行う。
+/// 結果は無駄になっている容量の大きい順に並ぶ。
+pub fn find_duplicates(
+    files: Vec<PathBuf>,
+    min_size: u64,
+) -> (Vec<DuplicateGroup>, Vec<PathError>) {
+    let mut errors = vec![];
+    let mut seen = HashSet::new();
+    let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    for path in files {
+        let meta = match fs::metadata(&path) {
+            Ok(meta) => meta,
+            Err(error) => {
+                errors.push(PathError { path, error });
+                continue;
+            }
+        };
+        if !meta.is_file() || meta.len() < min_size {
+            continue;
+        }
+        if let Some(id) = file_id(&meta) {
+            if !seen.insert(id) {
+                continue;
+            }
+        }
+        by_size.entry(meta.len()).or_default().push(path);
+    }
+    let by_size: Vec<(u64, Vec<PathBuf>)> = by_size
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .collect();
+
+    let by_partial = regroup(by_size, partial_hash, &mut errors);
+    // 先頭部分だけで全体になるファイルは、部分ハッシュ値をそのまま使う
+    let (small, large): (Vec<_>, Vec<_>) = by_partial
+        .into_iter()
+        .partition(|((size, _), _)| *size <= PARTIAL_SIZE);
+    let large = large
+        .into_iter()
+        .map(|((size, _), paths)| (size, paths))
+        .collect();
+    let by_full = regroup(large, file_hash, &mut errors);
+
+    let mut groups: Vec<DuplicateGroup> = small
+        .into_iter()
+        .chain(by_full)
+        .map(|((size, hash), mut paths)| {
+            paths.sort();
+            DuplicateGroup {
+                size,
+                hash: hash.to_hex().to_string(),
+                paths,
+            }
+        })
+        .collect();
+    groups.sort_by(|a, b| b.wasted().cmp(&a.wasted()).then(a.paths.cmp(&b.paths)));
+    errors.sort_by(|a, b| a.path.cmp(&b.path));
+    (groups, errors)
+}
+
+/// duplicate を original へのハードリンクに置き換える
+///
+/// 一時的な名前でリンクを作ってから rename するので、失敗しても duplicate は残る。
+pub fn replace_with_link(original: &Path, duplicate: &Path) -> io::Result<()> {
+    let dir = duplicate.parent().unwrap_or_else(|| Path::new("."));
+    let file_name = duplicate.file_name().unwrap_or_default().to_string_lossy();
+    let tmp_path = dir.join(format!(".{}.{}.tmp", file_name, std::process::id()));
+    fs::hard_link(original, &tmp_path)?;
+    let result = fs::rename(&tmp_path, duplicate);
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// JSON で出力する結果
+#[derive(Serialize)]
+struct Report<'a> {
+    groups: &'a [DuplicateGroup],
+    /// 重複を消した場合に空く容量の合計
+    wasted: u64,
+    errors: Vec<String>,
+}
+
+/// 重複のグループを出力する
+pub fn write_groups<W: Write>(out: &mut W, groups: &[DuplicateGroup]) -> io::Result<()> {
+    for (i, group) in groups.iter().enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        writeln!(
+            out,
+            "{} bytes x {} ({})",
+            group.size,
+            group.paths.len(),
+            &group.hash[..16]
+        )?;
+        for path in &group.paths {
+            writeln!(out, "  {}", path.display())?;
+        }
+    }
+    let wasted: u64 = groups.iter().map(DuplicateGroup::wasted).sum();
+    let files: usize = groups.iter().map(|g| g.paths.len() - 1).sum();
+    if !groups.is_empty() {
+        writeln!(out)?;
+    }
+    writeln!(
+        out,
+        "{}, {}, {} wasted",
+        plural(groups.len() as u64, "group", "groups"),
+        plural(files as u64, "duplicate file", "duplicate files"),
+        plural(wasted, "byte", "bytes")
+    )
+}
+
+/// dupes コマンドのエントリポイント
+///
+/// 終了コードを返す (読み込めなかったファイルやリンクできなかったファイルがあれば 2)。
+pub fn dupes_main(args: Args) -> Result<i32, Box<dyn Error>> {
+    let mut errors = vec![];
+    let mut files = vec![];
+    for path in &args.paths {
+        for result in walk::walk(path, &args.walk) {
+            match result {
+                Ok(file) => files.push(file),
+                Err(e) => errors.push(e),
+            }
+        }
+    }
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.threads)
+        .build()?;
+    let (groups, hash_errors) = pool.install(|| find_duplicates(files, args.min_size));
+    errors.extend(hash_errors);
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if args.json {
+        let report = Report {
+            groups: &groups,
+            wasted: groups.iter().map(DuplicateGroup::wasted).sum(),
+            errors: errors.iter().map(|e| e.to_string()).collect(),
+        };
+        serde_json::to_writer(&mut out, &report)?;
+        writeln!(out)?;
+    } else {
+        write_groups(&mut out, &groups)?;
+    }
+
+    if args.link {
+        for group in &groups {
+            let (original, duplicates) = group.paths.split_first().unwrap();
+            for duplicate in duplicates {
+                if args.dry_run {
+                    // JSON では、各グループの先頭以外のファイルがリンクされることが分かるので出力しない
+                    if !args.json {
+                        writeln!(
+                            out,
+                            "would link {} => {}",
+                            duplicate.display(),
+                            original.display()
+                        )?;
+                    }
+                } else if let Err(error) = replace_with_link(original, duplicate) {
+                    errors.push(PathError {
+                        path: duplicate.clone(),
+                        error,
+                    });
+                }
+            }
+        }
+    }
+
+    for e in &errors {
+        eprintln!("dupes: {}", e);
+    }
+    Ok(if errors.is_empty() { 0 } else { 2 })
+}
+
+#[test]
+fn test_find_duplicates() {
+    let dir = tempfile::tempdir().unwrap();
+    let write = |name: &str, content: &[u8]| {
+        let path = dir.path().join(name);
+        fs::write(&path, content).unwrap();
+        path
+    };
+    // 先頭 PARTIAL_SIZE バイトが同じで、後ろが違う大きなファイル
+    let mut large = vec![b'x'; PARTIAL_SIZE as usize * 2];
+    let big1 = write("big1", &large);
+    let big2 = write("big2", &large);
+    *large.last_mut().unwrap() = b'y';
+    let big3 = write("big3", &large);
+    let a = write("a.txt", b"same");
+    let b = write("b.txt", b"same");
+    let c = write("c.txt", b"diff");
+    let empty1 = write("empty1", b"");
+    let empty2 = write("empty2", b"");
+    let missing = dir.path().join("missing");
+
+    let files = vec![
+        big3.clone(),
+        big2.clone(),
+        big1.clone(),
+        c,
+        b.clone(),
+        a.clone(),
+        empty1.clone(),
+        empty2.clone(),
+        missing.clone(),
+    ];
+    let (groups, errors) = find_duplicates(files.clone(), 1);
+    assert_eq!(groups.len(), 2);
+    assert_eq!(groups[0].paths, vec![big1.clone(), big2.clone()]);
+    assert_eq!(groups[0].size, PARTIAL_SIZE * 2);
+    assert_eq!(groups[0].wasted(), PARTIAL_SIZE * 2);
+    assert_eq!(
+        groups[0].hash,
+        file_hash(&big1).unwrap().to_hex().to_string()
+    );
+    assert_eq!(groups[1].paths, vec![a.clone(), b.clone()]);
+    assert_eq!(groups[1].hash, blake3::hash(b"same").to_hex().to_string());
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].path, missing);
+
+    let (groups, _) = find_duplicates(files, 0);
+    assert_eq!(groups.len(), 3);
+    assert_eq!(groups[2].paths, vec![empty1, empty2]);
+
+    // リンクした後は、同じファイルとみなされて重複として報告されない
+    replace_with_link(&a, &b).unwrap();
+    assert_eq!(fs::read(&b).unwrap(), b"same");
+    let (groups, _) = find_duplicates(vec![a, b], 1);
+    assert!(groups.is_empty());
+}
+
+#[test]
+fn test_write_groups() {
+    let groups = vec![DuplicateGroup {
+        size: 3,
+        hash: "0123456789abcdef0123".to_string(),
+        paths: vec![PathBuf::from("x"), PathBuf::from("y"), PathBuf::from("z")],
+    }];
+    let mut out = vec![];
+    write_groups(&mut out, &groups).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "3 bytes x 3 (0123456789abcdef)\n  x\n  y\n  z\n\n1 group, 2 duplicate files, 6 bytes wasted\n"
+    );
+
+    let mut out = vec![];
+    write_groups(&mut out, &[]).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "0 groups, 0 duplicate files, 0 bytes wasted\n"
+    );
+}
@@ -0,0 +1,856 @@
+use filetime::FileTime;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use crate::walk::PathError;
+
+/// ディレクトリツリーの中の 1 つの項目
+#[derive(Debug, Clone, PartialEq)]
+pub enum Entry {
+    Dir,
+    File {
+        len: u64,
+        mtime: FileTime,
+    },
+    /// シンボリックリンク (リンク先は辿らない)
+    Symlink(PathBuf),
+    /// FIFO やソケット、デバイスファイルなどの特殊ファイル
+    ///
+    /// 開くと読み込みが終わらないことがあるので、内容は読まずにコピーもしない。
+    Special,
+}
+
+/// ルートからの相対パスと項目の組
+///
+/// BTreeMap なので、ディレクトリは必ずその中身より先に並ぶ。
+pub type Tree = BTreeMap<PathBuf, Entry>;
+
+/// io::Error にパスを付けて PathError にする
+fn at(path: &Path) -> impl FnOnce(io::Error) -> PathError + '_ {
+    move |error| PathError {
+        path: path.to_path_buf(),
+        error,
+    }
+}
+
+/// root 以下の項目を再帰的に列挙する (root 自身は含まない)
+pub fn scan(root: &Path) -> Result<Tree, PathError> {
+    fn visit(root: &Path, rel: &Path, tree: &mut Tree) -> Result<(), PathError> {
+        let dir = root.join(rel);
+        for entry in fs::read_dir(&dir).map_err(at(&dir))? {
+            let entry = entry.map_err(at(&dir))?;
+            let rel = rel.join(entry.file_name());
+            let path = entry.path();
+            let meta = fs::symlink_metadata(&path).map_err(at(&path))?;
+            if meta.is_dir() {
+                tree.insert(rel.clone(), Entry::Dir);
+                visit(root, &rel, tree)?;
+            } else if meta.file_type().is_symlink() {
+                let target = fs::read_link(&path).map_err(at(&path))?;
+                tree.insert(rel, Entry::Symlink(target));
+            } else if meta.is_file() {
+                let mtime = FileTime::from_last_modification_time(&meta);
+                tree.insert(
+                    rel,
+                    Entry::File {
+                        len: meta.len(),
+                        mtime,
+                    },
+                );
+            } else {
+                tree.insert(rel, Entry::Special);
+            }
+        }
+        Ok(())
+    }
+
+    let mut tree = Tree::new();
+    visit(root, Path::new(""), &mut tree)?;
+    Ok(tree)
+}
+
+/// ファイルの内容のハッシュ値
+pub fn file_hash(path: &Path) -> io::Result<blake3::Hash> {
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize())
+}
+
+/// ファイルが変更されたかの判断方法
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Compare {
+    /// サイズと更新日時が同じなら変更なしとみなす
+    Metadata,
+    /// サイズと内容のハッシュ値を比べる
+    Checksum,
+}
+
+/// 2 つのツリーの違い
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// new にだけある
+    Added(PathBuf),
+    /// old にだけある
+    Removed(PathBuf),
+    /// 両方にあるが内容か種類が違う
+    Modified(PathBuf),
+}
+
+impl Change {
+    pub fn path(&self) -> &Path {
+        match self {
+            Change::Added(p) | Change::Removed(p) | Change::Modified(p) => p,
+        }
+    }
+}
+
+/// old と new のツリーの違いを相対パスの順に返す
+///
+/// ディレクトリ自体の更新日時は比べない。
+pub fn diff_trees(old: &Path, new: &Path, compare: Compare) -> Result<Vec<Change>, PathError> {
+    let old_tree = scan(old)?;
+    let new_tree = scan(new)?;
+    diff_scanned(old, &old_tree, new, &new_tree, compare)
+}
+
+fn diff_scanned(
+    old: &Path,
+    old_tree: &Tree,
+    new: &Path,
+    new_tree: &Tree,
+    compare: Compare,
+) -> Result<Vec<Change>, PathError> {
+    let mut changes = vec![];
+    for rel in old_tree.keys() {
+        if !new_tree.contains_key(rel) {
+            changes.push(Change::Removed(rel.clone()));
+        }
+    }
+    for (rel, new_entry) in new_tree {
+        let modified = match (old_tree.get(rel), new_entry) {
+            (None, _) => {
+                changes.push(Change::Added(rel.clone()));
+                continue;
+            }
+            // 特殊ファイルは内容を比べられないので、種類が同じなら変更なしとみなす
+            (Some(Entry::Dir), Entry::Dir) | (Some(Entry::Special), Entry::Special) => false,
+            (Some(Entry::Symlink(a)), Entry::Symlink(b)) => a != b,
+            (Some(Entry::File { len: a, mtime: t }), Entry::File { len: b, mtime: u }) => {
+                a != b
+                    || match compare {
+                        Compare::Metadata => t != u,
+                        Compare::Checksum => {
+                            let (a, b) = (old.join(rel), new.join(rel));
+                            file_hash(&a).map_err(at(&a))? != file_hash(&b).map_err(at(&b))?
+                        }
+                    }
+            }
+            _ => true,
+        };
+        if modified {
+            changes.push(Change::Modified(rel.clone()));
+        }
+    }
+    changes.sort_by(|a, b| a.path().cmp(b.path()));
+    Ok(changes)
+}
+
+/// dst にある項目を消す (ディレクトリなら中身ごと)
+fn remove(dst: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(dst) {
+        Ok(meta) if meta.is_dir() => {
+            // 読み取り専用のディレクトリの中身は消せないので、先に書き込めるようにする
+            make_tree_writable(dst)?;
+            fs::remove_dir_all(dst)
+        }
+        Ok(_) => fs::remove_file(dst),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// ファイルの実体を表す (デバイス, inode) の組
+///
+/// 同じ値のパスは、同じファイルを指すハードリンクか、名前を変えただけの同じファイル。
+#[cfg(unix)]
+pub fn file_id(meta: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+pub fn file_id(_meta: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// ディレクトリに書き込めるようにする
+///
+/// 元のパーミッションに戻すには set_dir_metadata を使う。
+#[cfg(unix)]
+fn make_writable(dir: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut perms = fs::metadata(dir)?.permissions();
+    if perms.mode() & 0o200 == 0 {
+        perms.set_mode(perms.mode() | 0o200);
+        fs::set_permissions(dir, perms)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn make_writable(dir: &Path) -> io::Result<()> {
+    let mut perms = fs::metadata(dir)?.permissions();
+    if perms.readonly() {
+        #[allow(clippy::permissions_set_readonly_false)]
+        perms.set_readonly(false);
+        fs::set_permissions(dir, perms)?;
+    }
+    Ok(())
+}
+
+/// dir とその中のディレクトリをすべて書き込めるようにする (シンボリックリンクは辿らない)
+fn make_tree_writable(dir: &Path) -> io::Result<()> {
+    make_writable(dir)?;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            make_tree_writable(&entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// シンボリックリンクを作る (Unix 以外ではエラー)
+#[cfg(unix)]
+pub fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(not(unix))]
+pub fn symlink(_target: &Path, _link: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "symbolic links are not supported",
+    ))
+}
+
+/// 1 つの項目を src から dst にコピーする
+///
+/// ファイルはパーミッションと更新日時も引き継ぐ。ディレクトリは作るだけで、
+/// パーミッションと日時は中身をコピーした後に set_dir_metadata で設定する。
+fn copy_entry(src: &Path, dst: &Path, entry: &Entry) -> io::Result<()> {
+    let existing = fs::symlink_metadata(dst).ok();
+    match entry {
+        Entry::Dir => {
+            if existing.is_some_and(|m| !m.is_dir()) {
+                fs::remove_file(dst)?;
+            }
+            if !dst.is_dir() {
+                fs::create_dir(dst)?;
+            }
+        }
+        Entry::File { .. } => {
+            if existing.is_some() {
+                remove(dst)?;
+            }
+            fs::copy(src, dst)?;
+            let meta = fs::metadata(src)?;
+            filetime::set_file_times(
+                dst,
+                FileTime::from_last_access_time(&meta),
+                FileTime::from_last_modification_time(&meta),
+            )?;
+        }
+        Entry::Special => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot copy special file",
+            ))
+        }
+        Entry::Symlink(target) => {
+            if existing.is_some() {
+                remove(dst)?;
+            }
+            symlink(target, dst)?;
+            let meta = fs::symlink_metadata(src)?;
+            filetime::set_symlink_file_times(
+                dst,
+                FileTime::from_last_access_time(&meta),
+                FileTime::from_last_modification_time(&meta),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// ディレクトリのパーミッションと日時を src に合わせる
+fn set_dir_metadata(src: &Path, dst: &Path) -> io::Result<()> {
+    let meta = fs::metadata(src)?;
+    fs::set_permissions(dst, meta.permissions())?;
+    filetime::set_file_times(
+        dst,
+        FileTime::from_last_access_time(&meta),
+        FileTime::from_last_modification_time(&meta),
+    )
+}
+
+/// src の rels の項目を dst にコピーする
+fn copy_entries(src: &Path, dst: &Path, tree: &Tree, rels: &[&Path]) -> Result<(), PathError> {
+    for rel in rels {
+        let (from, to) = (src.join(rel), dst.join(rel));
+        copy_entry(&from, &to, &tree[*rel]).map_err(at(&to))?;
+    }
+    // 中身を書き込んだ後でないと、日時が変わったり書き込めなくなったりする
+    for rel in rels.iter().rev() {
+        if tree[*rel] == Entry::Dir {
+            let (from, to) = (src.join(rel), dst.join(rel));
+            set_dir_metadata(&from, &to).map_err(at(&to))?;
+        }
+    }
+    Ok(())
+}
+
+/// src ディレクトリを中身ごと dst にコピーする
+///
+/// パーミッションと更新日時を引き継ぎ、シンボリックリンクはリンクのままコピーする。
+/// dst に既にある項目は上書きする。特殊ファイルはコピーせず、 skipped に入れて返す。
+pub fn copy_tree(src: &Path, dst: &Path) -> Result<SyncReport, PathError> {
+    let tree = scan(src)?;
+    fs::create_dir_all(dst).map_err(at(dst))?;
+    let (skipped, copied): (Vec<PathBuf>, Vec<PathBuf>) = tree
+        .keys()
+        .cloned()
+        .partition(|rel| tree[rel] == Entry::Special);
+    let rels: Vec<&Path> = copied.iter().map(PathBuf::as_path).collect();
+    copy_entries(src, dst, &tree, &rels)?;
+    set_dir_metadata(src, dst).map_err(at(dst))?;
+    Ok(SyncReport {
+        copied,
+        skipped,
+        ..SyncReport::default()
+    })
+}
+
+/// sync のオプション
+#[derive(Debug, Clone, Copy)]
+pub struct SyncOptions {
+    pub compare: Compare,
+    /// src にない項目を dst から消す
+    pub delete: bool,
+    /// 何をするかを報告するだけで、実際には変更しない
+    pub dry_run: bool,
+}
+
+/// sync と copy_tree の結果
+#[derive(Debug, Default, PartialEq)]
+pub struct SyncReport {
+    /// コピーした (する) 項目
+    pub copied: Vec<PathBuf>,
+    /// 消した (消す) 項目
+    pub deleted: Vec<PathBuf>,
+    /// 特殊ファイルなのでコピーしなかった項目
+    pub skipped: Vec<PathBuf>,
+}
+
+/// src の内容を dst に一方向に同期する
+///
+/// 追加や変更があった項目だけをコピーする。 dst のディレクトリがなければ作る。
+pub fn sync(src: &Path, dst: &Path, opts: SyncOptions) -> Result<SyncReport, PathError> {
+    let src_tree = scan(src)?;
+    let dst_tree = match fs::metadata(dst) {
+        Ok(_) => scan(dst)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Tree::new(),
+        Err(e) => return Err(at(dst)(e)),
+    };
+    let changes = diff_scanned(dst, &dst_tree, src, &src_tree, opts.compare)?;
+
+    let mut report = SyncReport::default();
+    for change in changes {
+        match change {
+            Change::Added(rel) | Change::Modified(rel) if src_tree[&rel] == Entry::Special => {
+                report.skipped.push(rel)
+            }
+            Change::Added(rel) | Change::Modified(rel) => report.copied.push(rel),
+            Change::Removed(rel) if opts.delete => {
+                // 消すディレクトリの中身は改めて消さない
+                if !report.deleted.iter().any(|d| rel.starts_with(d)) {
+                    report.deleted.push(rel);
+                }
+            }
+            Change::Removed(_) => {}
+        }
+    }
+    if opts.dry_run {
+        return Ok(report);
+    }
+
+    // 変更のないディレクトリは copied に含まれないので、前回の sync で src に合わせて
+    // 読み取り専用にしていると中身を書き換えられない。書き換える間だけ書き込めるようにして、
+    // 終わったら src に合わせて戻す。
+    let parents: BTreeSet<&Path> = report
+        .copied
+        .iter()
+        .chain(&report.deleted)
+        .filter_map(|rel| rel.parent())
+        .filter(|p| src_tree.get(*p) == Some(&Entry::Dir) && dst_tree.get(*p) == Some(&Entry::Dir))
+        .collect();
+    for rel in &parents {
+        let path = dst.join(rel);
+        make_writable(&path).map_err(at(&path))?;
+    }
+
+    fs::create_dir_all(dst).map_err(at(dst))?;
+    for rel in &report.deleted {
+        let path = dst.join(rel);
+        remove(&path).map_err(at(&path))?;
+    }
+    let rels: Vec<&Path> = report.copied.iter().map(PathBuf::as_path).collect();
+    copy_entries(src, dst, &src_tree, &rels)?;
+    // 深いディレクトリから戻す
+    for rel in parents.iter().rev() {
+        let (from, to) = (src.join(rel), dst.join(rel));
+        set_dir_metadata(&from, &to).map_err(at(&to))?;
+    }
+    Ok(report)
+}
+
+/// tree の表示のオプション
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TreeOptions {
+    /// `.` で始まる項目も表示する
+    pub all: bool,
+    /// 表示する深さの上限
+    pub max_depth: Option<usize>,
+}
+
+/// 数と、数に合わせて単数形か複数形にした名詞 ("1 file" 、 "2 files" など)
+pub fn plural(n: u64, one: &str, many: &str) -> String {
+    format!("{} {}", n, if n == 1 { one } else { many })
+}
+
+/// root 以下を tree コマンドのような形式で出力する
+///
+/// ディレクトリとファイル (シンボリックリンクを含む) の数を返す。
+pub fn write_tree<W: Write>(
+    out: &mut W,
+    root: &Path,
+    opts: TreeOptions,
+) -> Result<(usize, usize), Box<dyn Error>> {
+    fn visit<W: Write>(
+        out: &mut W,
+        dir: &Path,
+        prefix: &str,
+        depth: usize,
+        opts: TreeOptions,
+        counts: &mut (usize, usize),
+    ) -> Result<(), Box<dyn Error>> {
+        if opts.max_depth.is_some_and(|max| depth >= max) {
+            return Ok(());
+        }
+        let mut entries = fs::read_dir(dir)
+            .and_then(|it| it.collect::<io::Result<Vec<_>>>())
+            .map_err(at(dir))?;
+        entries.retain(|e| opts.all || !e.file_name().to_string_lossy().starts_with('.'));
+        entries.sort_by_key(|e| e.file_name());
+
+        for (i, entry) in entries.iter().enumerate() {
+            let last = i + 1 == entries.len();
+            let name = entry.file_name();
+            let path = entry.path();
+            let branch = if last { "└── " } else { "├── " };
+            let file_type = entry.file_type().map_err(at(&path))?;
+            if file_type.is_symlink() {
+                let target = fs::read_link(&path).map_err(at(&path))?;
+                let name = name.to_string_lossy();
+                writeln!(out, "{}{}{} -> {}", prefix, branch, name, target.display())?;
+                counts.1 += 1;
+            } else if file_type.is_dir() {
+                writeln!(out, "{}{}{}", prefix, branch, name.to_string_lossy())?;
+                counts.0 += 1;
+                let prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+                visit(out, &path, &prefix, depth + 1, opts, counts)?;
+            } else {
+                writeln!(out, "{}{}{}", prefix, branch, name.to_string_lossy())?;
+                counts.1 += 1;
+            }
+        }
+        Ok(())
+    }
+
+    writeln!(out, "{}", root.display())?;
+    let mut counts = (0, 0);
+    visit(out, root, "", 0, opts, &mut counts)?;
+    writeln!(
+        out,
+        "\n{}, {}",
+        plural(counts.0 as u64, "directory", "directories"),
+        plural(counts.1 as u64, "file", "files")
+    )?;
+    Ok(counts)
+}
+
+/// fs のコマンドライン引数
+#[derive(Debug, clap::Subcommand)]
+pub enum Args {
+    /// ディレクトリを中身ごとコピーする
+    #[command(about = "Copy a directory recursively, preserving permissions and timestamps")]
+    Copy { src: PathBuf, dst: PathBuf },
+    /// 変更があったファイルだけをコピーして同期する
+    #[command(about = "Copy only new and changed files from SRC to DST")]
+    Sync {
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = Compare::Metadata,
+            help = "How to detect changed files"
+        )]
+        compare: Compare,
+        #[arg(long, help = "Delete files in DST that are not in SRC")]
+        delete: bool,
+        #[arg(short = 'n', long, help = "Only print what would be done")]
+        dry_run: bool,
+        src: PathBuf,
+        dst: PathBuf,
+    },
+    /// 2 つのディレクトリの違いを出力する
+    #[command(about = "Report files added, removed or modified between two directories")]
+    Diff {
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = Compare::Metadata,
+            help = "How to detect changed files"
+        )]
+        compare: Compare,
+        old: PathBuf,
+        new: PathBuf,
+    },
+    /// ディレクトリの中身を木の形で出力する
+    #[command(about = "List the contents of a directory as a tree")]
+    Tree {
+        #[arg(short, long, help = "Show hidden files")]
+        all: bool,
+        #[arg(short = 'L', long, value_name = "N", help = "Descend at most N levels")]
+        level: Option<usize>,
+        #[arg(default_value = ".", help = "Directory to list")]
+        dir: PathBuf,
+    },
+}
+
+/// コピーしなかった特殊ファイルを標準エラー出力に知らせる
+fn write_skipped(report: &SyncReport) {
+    for rel in &report.skipped {
+        eprintln!("fs: skipping special file {}", rel.display());
+    }
+}
+
+/// fs コマンドのエントリポイント
+///
+/// 終了コードを返す (diff で違いがあれば 1)。
+pub fn fs_main(args: Args) -> Result<i32, Box<dyn Error>> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match args {
+        Args::Copy { src, dst } => {
+            let report = copy_tree(&src, &dst)?;
+            write_skipped(&report);
+            let n = report.copied.len() as u64;
+            writeln!(out, "copied {}", plural(n, "entry", "entries"))?;
+        }
+        Args::Sync {
+            compare,
+            delete,
+            dry_run,
+            src,
+            dst,
+        } => {
+            let opts = SyncOptions {
+                compare,
+                delete,
+                dry_run,
+            };
+            let report = sync(&src, &dst, opts)?;
+            write_skipped(&report);
+            for rel in &report.deleted {
+                writeln!(out, "delete {}", rel.display())?;
+            }
+            for rel in &report.copied {
+                writeln!(out, "copy {}", rel.display())?;
+            }
+        }
+        Args::Diff { compare, old, new } => {
+            let changes = diff_trees(&old, &new, compare)?;
+            for change in &changes {
+                let mark = match change {
+                    Change::Added(_) => 'A',
+                    Change::Removed(_) => 'D',
+                    Change::Modified(_) => 'M',
+                };
+                writeln!(out, "{} {}", mark, change.path().display())?;
+            }
+            return Ok(if changes.is_empty() { 0 } else { 1 });
+        }
+        Args::Tree { all, level, dir } => {
+            let opts = TreeOptions {
+                all,
+                max_depth: level,
+            };
+            write_tree(&mut out, &dir, opts)?;
+        }
+    }
+    Ok(0)
+}
+
+/// テスト用のディレクトリを作る
+///
+/// ```text
+/// src/a.txt, src/sub/b.txt, src/sub/deep/c.txt, src/.hidden
+/// ```
+#[cfg(test)]
+fn sample_tree() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("src");
+    fs::create_dir_all(src.join("sub/deep")).unwrap();
+    fs::write(src.join("a.txt"), "a").unwrap();
+    fs::write(src.join("sub/b.txt"), "bb").unwrap();
+    fs::write(src.join("sub/deep/c.txt"), "ccc").unwrap();
+    fs::write(src.join(".hidden"), "").unwrap();
+    let old = FileTime::from_unix_time(1_000_000_000, 0);
+    filetime::set_file_mtime(src.join("a.txt"), old).unwrap();
+    filetime::set_file_mtime(src.join("sub"), old).unwrap();
+    dir
+}
+
+#[cfg(test)]
+fn mtime(path: &Path) -> FileTime {
+    FileTime::from_last_modification_time(&fs::metadata(path).unwrap())
+}
+
+#[test]
+fn test_copy_tree() {
+    let dir = sample_tree();
+    let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(src.join("a.txt"), fs::Permissions::from_mode(0o640)).unwrap();
+        symlink(Path::new("sub/b.txt"), &src.join("link")).unwrap();
+    }
+
+    copy_tree(&src, &dst).unwrap();
+    assert_eq!(
+        fs::read_to_string(dst.join("sub/deep/c.txt")).unwrap(),
+        "ccc"
+    );
+    assert_eq!(mtime(&dst.join("a.txt")), mtime(&src.join("a.txt")));
+    // ディレクトリの日時は中身をコピーした後に設定する
+    assert_eq!(mtime(&dst.join("sub")), mtime(&src.join("sub")));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(dst.join("a.txt"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o640);
+        assert_eq!(
+            fs::read_link(dst.join("link")).unwrap(),
+            Path::new("sub/b.txt")
+        );
+    }
+    assert!(diff_trees(&src, &dst, Compare::Metadata)
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn test_diff_trees() {
+    let dir = sample_tree();
+    let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+    copy_tree(&src, &dst).unwrap();
+
+    fs::write(dst.join("sub/b.txt"), "BB").unwrap();
+    fs::remove_dir_all(dst.join("sub/deep")).unwrap();
+    fs::write(dst.join("new.txt"), "").unwrap();
+    // 内容が同じでも、更新日時が違えば Metadata では変更とみなす
+    filetime::set_file_mtime(dst.join("a.txt"), FileTime::from_unix_time(0, 0)).unwrap();
+
+    let changes = diff_trees(&src, &dst, Compare::Metadata).unwrap();
+    assert_eq!(
+        changes,
+        vec![
+            Change::Modified(PathBuf::from("a.txt")),
+            Change::Added(PathBuf::from("new.txt")),
+            Change::Modified(PathBuf::from("sub/b.txt")),
+            Change::Removed(PathBuf::from("sub/deep")),
+            Change::Removed(PathBuf::from("sub/deep/c.txt")),
+        ]
+    );
+    let changes = diff_trees(&src, &dst, Compare::Checksum).unwrap();
+    assert!(!changes.contains(&Change::Modified(PathBuf::from("a.txt"))));
+    assert!(changes.contains(&Change::Modified(PathBuf::from("sub/b.txt"))));
+
+    assert!(diff_trees(&src, &dir.path().join("missing"), Compare::Metadata).is_err());
+}
+
+#[test]
+fn test_sync() {
+    let dir = sample_tree();
+    let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+    let opts = SyncOptions {
+        compare: Compare::Metadata,
+        delete: false,
+        dry_run: false,
+    };
+
+    // 最初はすべてコピーし、2 回目は何もしない
+    let report = sync(&src, &dst, opts).unwrap();
+    assert_eq!(report.copied.len(), 6);
+    assert_eq!(sync(&src, &dst, opts).unwrap(), SyncReport::default());
+
+    fs::write(src.join("sub/b.txt"), "changed").unwrap();
+    fs::write(dst.join("extra.txt"), "").unwrap();
+    fs::create_dir_all(dst.join("old/nested")).unwrap();
+    let dry_run = SyncOptions {
+        delete: true,
+        dry_run: true,
+        ..opts
+    };
+    let expected = SyncReport {
+        copied: vec![PathBuf::from("sub/b.txt")],
+        deleted: vec![PathBuf::from("extra.txt"), PathBuf::from("old")],
+        skipped: vec![],
+    };
+    assert_eq!(sync(&src, &dst, dry_run).unwrap(), expected);
+    assert!(dst.join("extra.txt").exists());
+    assert_eq!(fs::read_to_string(dst.join("sub/b.txt")).unwrap(), "bb");
+
+    let delete = SyncOptions {
+        delete: true,
+        ..opts
+    };
+    assert_eq!(sync(&src, &dst, delete).unwrap(), expected);
+    assert!(!dst.join("old").exists());
+    assert_eq!(
+        fs::read_to_string(dst.join("sub/b.txt")).unwrap(),
+        "changed"
+    );
+    assert!(diff_trees(&src, &dst, Compare::Checksum)
+        .unwrap()
+        .is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_sync_read_only_dir() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = sample_tree();
+    let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+    let opts = SyncOptions {
+        compare: Compare::Metadata,
+        delete: true,
+        dry_run: false,
+    };
+    let set_mode =
+        |path: &Path, mode| fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+    let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+    fs::write(src.join("sub/gone.txt"), "").unwrap();
+    set_mode(&src.join("sub"), 0o555);
+    sync(&src, &dst, opts).unwrap();
+    assert_eq!(mode(&dst.join("sub")), 0o555);
+
+    // 読み取り専用になった dst/sub の中も書き換えられ、その後も読み取り専用のまま
+    fs::write(src.join("sub/b.txt"), "changed").unwrap();
+    set_mode(&src.join("sub"), 0o755);
+    fs::remove_file(src.join("sub/gone.txt")).unwrap();
+    set_mode(&src.join("sub"), 0o555);
+    let report = sync(&src, &dst, opts).unwrap();
+    assert_eq!(report.copied, vec![PathBuf::from("sub/b.txt")]);
+    assert_eq!(report.deleted, vec![PathBuf::from("sub/gone.txt")]);
+    assert_eq!(
+        fs::read_to_string(dst.join("sub/b.txt")).unwrap(),
+        "changed"
+    );
+    assert_eq!(mode(&dst.join("sub")), 0o555);
+    assert_eq!(mtime(&dst.join("sub")), mtime(&src.join("sub")));
+
+    // 読み取り専用のディレクトリも中身ごと消せる
+    set_mode(&src.join("sub"), 0o755);
+    fs::remove_dir_all(src.join("sub")).unwrap();
+    sync(&src, &dst, opts).unwrap();
+    assert!(!dst.join("sub").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_special_files() {
+    use std::os::unix::net::UnixListener;
+
+    let dir = sample_tree();
+    let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+    // ソケットファイルは特殊ファイルの例として手軽に作れる
+    let _listener = UnixListener::bind(src.join("sub/sock")).unwrap();
+    assert_eq!(scan(&src).unwrap()[Path::new("sub/sock")], Entry::Special);
+
+    let report = copy_tree(&src, &dst).unwrap();
+    assert_eq!(report.skipped, vec![PathBuf::from("sub/sock")]);
+    assert_eq!(report.copied.len(), 6);
+    assert!(!dst.join("sub/sock").exists());
+    // 特殊ファイルの内容は読まないので、 --checksum でも止まらない
+    assert_eq!(
+        diff_trees(&dst, &src, Compare::Checksum).unwrap(),
+        vec![Change::Added(PathBuf::from("sub/sock"))]
+    );
+    assert!(diff_trees(&src, &src, Compare::Checksum)
+        .unwrap()
+        .is_empty());
+
+    let opts = SyncOptions {
+        compare: Compare::Checksum,
+        delete: true,
+        dry_run: false,
+    };
+    let report = sync(&src, &dst, opts).unwrap();
+    assert_eq!(
+        report,
+        SyncReport {
+            skipped: vec![PathBuf::from("sub/sock")],
+            ..SyncReport::default()
+        }
+    );
+}
+
+#[test]
+fn test_write_tree() {
+    let dir = sample_tree();
+    let src = dir.path().join("src");
+    let mut out = vec![];
+    let counts = write_tree(&mut out, &src, TreeOptions::default()).unwrap();
+    assert_eq!(counts, (2, 3));
+    let text = String::from_utf8(out).unwrap();
+    assert_eq!(
+        text.split_once('\n').unwrap().1,
+        "├── a.txt\n\
+         └── sub\n\
+         \x20   ├── b.txt\n\
+         \x20   └── deep\n\
+         \x20       └── c.txt\n\
+         \n\
+         2 directories, 3 files\n"
+    );
+
+    let opts = TreeOptions {
+        all: true,
+        max_depth: Some(1),
+    };
+    let mut out = vec![];
+    assert_eq!(write_tree(&mut out, &src, opts).unwrap(), (1, 2));
+    let text = String::from_utf8(out).unwrap();
+    assert!(text.contains("├── .hidden\n├── a.txt\n└── sub\n\n1 directory, 2 files\n"));
+}
@@ -0,0 +1,1337 @@
+use memchr::memmem;
+use memmap2::Mmap;
+use regex::bytes::{Regex, RegexBuilder};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, VecDeque};
+use std::error::Error;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use crate::walk::{self, WalkOptions};
+
+/// 標準入力から読み込んだときに表示する名前
+pub static STDIN_NAME: &str = "(standard input)";
+
+/// これ以上の大きさのファイルはメモリマップして検索する
+const MMAP_THRESHOLD: u64 = 1 << 20;
+
+/// バイナリファイルかどうかを判定するために調べる先頭のバイト数
+const BINARY_CHECK_LEN: usize = 8 * 1024;
+
+/// grep の動作を切り替えるオプション
+///
+/// ヘルプに表示する説明は help 属性に英語で書く。
+#[derive(Debug, Default, Clone, PartialEq, clap::Args)]
+pub struct Options {
+    /// -i: 大文字と小文字を区別しない
+    #[arg(short = 'i', long, help = "Ignore case distinctions")]
+    pub ignore_case: bool,
+    /// -v: マッチしなかった行を出力する
+    #[arg(
+        short = 'v',
+        long = "invert-match",
+        help = "Print lines that do not match"
+    )]
+    pub invert: bool,
+    /// -n: 行番号を出力する
+    #[arg(short = 'n', long, help = "Prefix each line with its line number")]
+    pub line_number: bool,
+    /// -c: 行の代わりにマッチした行数を出力する
+    #[arg(
+        short = 'c',
+        long,
+        help = "Print only the number of matching lines per file"
+    )]
+    pub count: bool,
+    /// -l: 行の代わりにマッチしたファイル名を出力する
+    #[arg(short = 'l', long, help = "Print only the names of files with matches")]
+    pub files_with_matches: bool,
+    /// -B: マッチした行の前に出力する行数
+    #[arg(
+        short = 'B',
+        long,
+        value_name = "NUM",
+        default_value_t = 0,
+        hide_default_value = true,
+        help = "Print NUM lines of leading context"
+    )]
+    pub before_context: usize,
+    /// -A: マッチした行の後に出力する行数
+    #[arg(
+        short = 'A',
+        long,
+        value_name = "NUM",
+        default_value_t = 0,
+        hide_default_value = true,
+        help = "Print NUM lines of trailing context"
+    )]
+    pub after_context: usize,
+    /// 各行の先頭にファイル名を付ける (複数ファイルを検索するとき)
+    #[arg(skip)]
+    pub with_filename: bool,
+    /// --json: 結果を 1 行 1 レコードの JSON で出力する
+    #[arg(long, help = "Print one JSON record per match and a summary record")]
+    pub json: bool,
+}
+
+impl Options {
+    /// オプションに合わせてパターンをコンパイルする
+    pub fn build_matcher(&self, pattern: &str) -> Result<Matcher, regex::Error> {
+        // バッファ全体を検索するときに `^` と `$` が各行の先頭と末尾にマッチするようにする
+        let re = RegexBuilder::new(pattern)
+            .case_insensitive(self.ignore_case)
+            .multi_line(true)
+            .build()?;
+        let is_literal = !pattern.is_empty() && regex::escape(pattern) == pattern;
+        let literal = if is_literal && !self.ignore_case {
+            Some(memmem::Finder::new(pattern.as_bytes()).into_owned())
+        } else {
+            None
+        };
+        // `(?` は複数行モードを外しているかもしれないので、安全側に倒して 1 行ずつ検索する
+        let buffer_search =
+            !(pattern.contains("\\A") || pattern.contains("\\z") || pattern.contains("(?"));
+        Ok(Matcher {
+            re,
+            literal,
+            buffer_search,
+        })
+    }
+
+    fn has_context(&self) -> bool {
+        self.before_context > 0 || self.after_context > 0
+    }
+}
+
+/// 検索パターン
+///
+/// テキストは UTF-8 とは限らないので、バイト列に対して検索する。
+#[derive(Debug)]
+pub struct Matcher {
+    re: Regex,
+    /// 正規表現の記号を含まないパターンは、 memmem の部分文字列検索で探す
+    literal: Option<memmem::Finder<'static>>,
+    /// バッファ全体を一度に検索しても、 1 行ずつ検索したときと同じ行が見つかるか
+    ///
+    /// `\A` や `\z` 、 `(?-m)` で複数行モードを外した `^` と `$` は、
+    /// バッファ全体の先頭と末尾にしかマッチしなくなるので、 1 行ずつ検索する。
+    buffer_search: bool,
+}
+
+impl Matcher {
+    /// 1 行 (改行を含まない) がパターンにマッチするか
+    pub fn is_match(&self, line: &[u8]) -> bool {
+        match &self.literal {
+            Some(finder) => finder.find(line).is_some(),
+            None => self.re.is_match(line),
+        }
+    }
+
+    /// haystack の start 以降で最初にマッチする位置
+    fn find_at(&self, haystack: &[u8], start: usize) -> Option<usize> {
+        match &self.literal {
+            Some(finder) => finder.find(&haystack[start..]).map(|i| start + i),
+            None => self.re.find_at(haystack, start).map(|m| m.start()),
+        }
+    }
+
+    /// 1 行の中でマッチした箇所をすべて取り出す
+    fn submatches(&self, line: &[u8]) -> Vec<Submatch> {
+        self.re
+            .captures_iter(line)
+            .map(|caps| {
+                let whole = caps.get(0).unwrap();
+                Submatch {
+                    text: String::from_utf8_lossy(whole.as_bytes()).into_owned(),
+                    start: whole.start(),
+                    end: whole.end(),
+                    groups: caps.iter().skip(1).map(|g| g.map(Span::from)).collect(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// grep のコマンドライン引数
+///
+/// `-in` のように 1 文字のフラグはまとめて指定でき、
+/// `-A 2` と `-A2` のどちらの書き方でも数値を受け付ける。
+/// 解釈した後に resolve で -C などを各オプションに反映する。
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    #[command(flatten)]
+    pub options: Options,
+    /// -C: マッチした行の前後に出力する行数 (-A と -B をまとめて指定する)
+    #[arg(
+        short = 'C',
+        long = "context",
+        value_name = "NUM",
+        help = "Print NUM lines of context around each match"
+    )]
+    pub context: Option<usize>,
+    /// -r: ディレクトリを再帰的に検索する
+    #[arg(short = 'r', long, help = "Search directories recursively")]
+    pub recursive: bool,
+    /// -r で辿るファイルの絞り込み
+    #[command(flatten)]
+    pub walk: WalkOptions,
+    /// --threads: 検索に使うスレッド数 (0 なら CPU 数)
+    #[arg(
+        long,
+        value_name = "NUM",
+        default_value_t = 0,
+        hide_default_value = true,
+        help = "Number of threads to search with (0 uses one per CPU)"
+    )]
+    pub threads: usize,
+    #[arg(help = "Regular expression to search for")]
+    pub pattern: String,
+    #[arg(help = "Files to search (standard input if omitted)")]
+    pub files: Vec<PathBuf>,
+}
+
+impl Args {
+    /// 他の引数によって決まるオプションを設定する
+    pub fn resolve(mut self) -> Args {
+        if let Some(n) = self.context {
+            self.options.before_context = n;
+            self.options.after_context = n;
+        }
+        if self.recursive && self.files.is_empty() {
+            // -r でファイルが省略された場合はカレントディレクトリを検索する
+            self.files.push(PathBuf::from("."));
+        }
+        self.options.with_filename = self.files.len() > 1 || self.recursive;
+        self
+    }
+}
+
+/// grep のコマンドライン引数を解釈する (args にコマンド名は含めない)
+pub fn parse_args<I>(args: I) -> Result<Args, clap::Error>
+where
+    I: IntoIterator<Item = String>,
+{
+    use clap::{Args as _, FromArgMatches};
+
+    let command = Args::augment_args(clap::Command::new("grep"));
+    let matches = command.try_get_matches_from(std::iter::once("grep".to_string()).chain(args))?;
+    Ok(Args::from_arg_matches(&matches)?.resolve())
+}
+
+/// 行末の改行 (`\n` または `\r\n`) を取り除く
+fn trim_newline(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+/// マッチした箇所の位置 (行頭からのバイトオフセット) と内容
+#[derive(Debug, Serialize)]
+struct Span {
+    text: String,
+    start: usize,
+    end: usize,
+}
+
+impl From<regex::bytes::Match<'_>> for Span {
+    fn from(m: regex::bytes::Match) -> Span {
+        Span {
+            text: String::from_utf8_lossy(m.as_bytes()).into_owned(),
+            start: m.start(),
+            end: m.end(),
+        }
+    }
+}
+
+/// 1 つのマッチと、そのキャプチャグループ (マッチしなかったグループは null)
+#[derive(Debug, Serialize)]
+struct Submatch {
+    #[serde(rename = "match")]
+    text: String,
+    start: usize,
+    end: usize,
+    groups: Vec<Option<Span>>,
+}
+
+/// --json で出力するレコード
+///
+/// `absolute_offset` はファイルの先頭から行頭までのバイト数。
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Record<'a> {
+    Match {
+        path: &'a str,
+        line_number: usize,
+        absolute_offset: usize,
+        line: Cow<'a, str>,
+        submatches: Vec<Submatch>,
+    },
+    Context {
+        path: &'a str,
+        line_number: usize,
+        absolute_offset: usize,
+        line: Cow<'a, str>,
+    },
+    Summary {
+        files: &'a [FileCount],
+        matched_lines: u64,
+        files_matched: usize,
+        elapsed_secs: f64,
+    },
+}
+
+/// レコードを 1 行の JSON として出力する
+fn write_record<W: Write>(out: &mut W, record: &Record) -> io::Result<()> {
+    serde_json::to_writer(&mut *out, record)?;
+    writeln!(out)
+}
+
+/// 1 つのファイルの検索結果の出力先
+///
+/// オプションに合わせて、テキストか JSON のどちらかの形式で書き出す。
+struct Printer<'a, W> {
+    out: &'a mut W,
+    m: &'a Matcher,
+    name: &'a str,
+    opts: &'a Options,
+}
+
+impl<W: Write> Printer<'_, W> {
+    /// 1 行分を出力する
+    ///
+    /// テキストの場合は `path:line:` の形式の接頭辞を付け、
+    /// マッチした行は区切りに `:` を、前後の文脈行は `-` を使う。
+    /// UTF-8 として不正なバイト列は U+FFFD に置き換えて出力する。
+    fn line(
+        &mut self,
+        line_no: usize,
+        offset: usize,
+        line: &[u8],
+        matched: bool,
+    ) -> io::Result<()> {
+        let text = String::from_utf8_lossy(line);
+        if self.opts.json {
+            let record = if matched {
+                Record::Match {
+                    path: self.name,
+                    line_number: line_no,
+                    absolute_offset: offset,
+                    line: text,
+                    submatches: self.m.submatches(line),
+                }
+            } else {
+                Record::Context {
+                    path: self.name,
+                    line_number: line_no,
+                    absolute_offset: offset,
+                    line: text,
+                }
+            };
+            return write_record(self.out, &record);
+        }
+
+        let sep = if matched { ':' } else { '-' };
+        if self.opts.with_filename {
+            write!(self.out, "{}{}", self.name, sep)?;
+        }
+        if self.opts.line_number {
+            write!(self.out, "{}{}", line_no, sep)?;
+        }
+        writeln!(self.out, "{}", text)
+    }
+
+    /// 文脈行のグループの区切りを出力する (JSON では行番号から分かるので出力しない)
+    fn separator(&mut self) -> io::Result<()> {
+        if self.opts.json {
+            return Ok(());
+        }
+        writeln!(self.out, "--")
+    }
+
+    /// 検索結果の件数を -c や -l の形式で出力する
+    ///
+    /// JSON の場合、件数は最後のまとめのレコードに含める。
+    fn count(&mut self, count: u64) -> io::Result<()> {
+        if self.opts.json {
+            return Ok(());
+        }
+        if self.opts.files_with_matches {
+            if count > 0 {
+                writeln!(self.out, "{}", self.name)?;
+            }
+        } else if self.opts.count {
+            if self.opts.with_filename {
+                writeln!(self.out, "{}:{}", self.name, count)?;
+            } else {
+                writeln!(self.out, "{}", count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// reader から読み込んだ各行を m で検索し、結果を out に出力する
+///
+/// name は出力の接頭辞に使うファイル名。
+/// 返り値はマッチした行数 (-v の場合はマッチしなかった行数)。
+/// -l の場合は最初にマッチした時点で読み込みをやめる。
+pub fn grep<R, W>(
+    m: &Matcher,
+    mut reader: R,
+    name: &str,
+    opts: &Options,
+    out: &mut W,
+) -> io::Result<u64>
+where
+    R: BufRead,
+    W: Write,
+{
+    let mut printer = Printer { out, m, name, opts };
+    let quiet = opts.count || opts.files_with_matches;
+    let mut count = 0;
+    // まだ出力していない直前の行と、その行番号と位置 (-B 用)
+    let mut before: VecDeque<(usize, usize, Vec<u8>)> =
+        VecDeque::with_capacity(opts.before_context);
+    // あと何行を文脈として出力するか (-A 用)
+    let mut after_left = 0;
+    let mut last_printed: Option<usize> = None;
+
+    // lines() は行ごとに String を確保し、 UTF-8 でない行でエラーになるので、
+    // バイト列のまま 1 つのバッファを使い回して読み込む
+    let mut buf = vec![];
+    let mut line_no = 0;
+    let mut next_offset = 0;
+    loop {
+        buf.clear();
+        let len = reader.read_until(b'\n', &mut buf)?;
+        if len == 0 {
+            break;
+        }
+        line_no += 1;
+        let offset = next_offset;
+        next_offset += len;
+        let line = trim_newline(&buf);
+
+        if m.is_match(line) != opts.invert {
+            count += 1;
+            if opts.files_with_matches {
+                break;
+            }
+            if quiet {
+                continue;
+            }
+
+            // 前回出力した行と離れていれば、グループの区切りを出力する
+            let first = before.front().map_or(line_no, |(n, _, _)| *n);
+            if let Some(last) = last_printed {
+                if opts.has_context() && first > last + 1 {
+                    printer.separator()?;
+                }
+            }
+            for (n, o, l) in before.drain(..) {
+                printer.line(n, o, &l, false)?;
+            }
+            printer.line(line_no, offset, line, true)?;
+            last_printed = Some(line_no);
+            after_left = opts.after_context;
+        } else if quiet {
+            continue;
+        } else if after_left > 0 {
+            printer.line(line_no, offset, line, false)?;
+            last_printed = Some(line_no);
+            after_left -= 1;
+        } else if opts.before_context > 0 {
+            if before.len() == opts.before_context {
+                before.pop_front();
+            }
+            before.push_back((line_no, offset, line.to_vec()));
+        }
+    }
+
+    printer.count(count)?;
+    Ok(count)
+}
+
+/// メモリ上のテキスト全体を m で検索し、結果を out に出力する
+///
+/// 行ごとに区切らずにバッファ全体からマッチする位置を探し、
+/// マッチした行だけを取り出す。行番号は必要なときにだけ改行を数えて求める。
+/// -v や文脈行の出力ではすべての行を調べる必要があるので、 grep と同じ方法で検索する。
+/// 行の先頭と末尾以外にマッチするアンカーを含むパターンも同様。
+pub fn grep_buffer<W: Write>(
+    m: &Matcher,
+    haystack: &[u8],
+    name: &str,
+    opts: &Options,
+    out: &mut W,
+) -> io::Result<u64> {
+    if opts.invert || opts.has_context() || !m.buffer_search {
+        return grep(m, haystack, name, opts, out);
+    }
+
+    let mut printer = Printer { out, m, name, opts };
+    let quiet = opts.count || opts.files_with_matches;
+    let mut count = 0;
+    // pos は常に行の先頭を指す
+    let mut pos = 0;
+    // counted_to より前の改行は line_no に数え済み
+    let mut line_no = 1;
+    let mut counted_to = 0;
+
+    while pos < haystack.len() {
+        let start = match m.find_at(haystack, pos) {
+            Some(start) => start,
+            None => break,
+        };
+        // 最後の改行の後ろは行ではない (空のパターンはそこにもマッチする)
+        if start == haystack.len() && haystack.ends_with(b"\n") {
+            break;
+        }
+        let line_start = memchr::memrchr(b'\n', &haystack[pos..start]).map_or(pos, |i| pos + i + 1);
+        let line_end =
+            memchr::memchr(b'\n', &haystack[start..]).map_or(haystack.len(), |i| start + i);
+        let line = trim_newline(&haystack[line_start..line_end]);
+
+        // `\s` などは改行をまたいでマッチすることがあるので、行単位でマッチするか確かめる
+        if m.is_match(line) {
+            count += 1;
+            if opts.files_with_matches {
+                break;
+            }
+            if !quiet {
+                if opts.line_number || opts.json {
+                    line_no +=
+                        memchr::memchr_iter(b'\n', &haystack[counted_to..line_start]).count();
+                    counted_to = line_start;
+                }
+                printer.line(line_no, line_start, line, true)?;
+            }
+        }
+        pos = line_end + 1;
+    }
+
+    printer.count(count)?;
+    Ok(count)
+}
+
+/// 先頭に NUL バイトが含まれていればバイナリとみなす
+pub(crate) fn looks_binary(data: &[u8]) -> bool {
+    data[..data.len().min(BINARY_CHECK_LEN)].contains(&0)
+}
+
+/// 1 つのファイルを検索する
+///
+/// 小さいファイルは一度に読み込み、大きいファイルはメモリマップしてから検索する。
+/// バイナリファイルは検索せずに 0 を返す。
+fn grep_file<W: Write>(m: &Matcher, path: &Path, opts: &Options, out: &mut W) -> io::Result<u64> {
+    let mut file = File::open(path)?;
+    let metadata = file.metadata()?;
+    if metadata.is_dir() {
+        return Err(io::Error::other("Is a directory"));
+    }
+
+    let name = path.to_string_lossy();
+    if metadata.len() >= MMAP_THRESHOLD {
+        // SAFETY: マップしている間にファイルが変更されないことを前提にしている。
+        // 検索中に他のプロセスがファイルを切り詰めると、なくなった範囲を読んだ時点で
+        // SIGBUS が発生してプロセスごと終了する。書き換えられた場合は、 &[u8] の中身が
+        // 途中で変わることになり、未定義動作になる。 grep はこの危険を受け入れて、
+        // 大きいファイルを読み込む時間とメモリを節約している (GNU grep や ripgrep も同じ)。
+        let map = unsafe { Mmap::map(&file)? };
+        if looks_binary(&map) {
+            return Ok(0);
+        }
+        grep_buffer(m, &map, &name, opts, out)
+    } else {
+        let mut data = Vec::with_capacity(metadata.len() as usize);
+        file.read_to_end(&mut data)?;
+        if looks_binary(&data) {
+            return Ok(0);
+        }
+        grep_buffer(m, &data, &name, opts, out)
+    }
+}
+
+/// 1 つのファイルでマッチした行数
+#[derive(Debug, PartialEq, Serialize)]
+pub struct FileCount {
+    pub path: String,
+    pub matches: u64,
+    /// 読み込めなかった場合のエラー
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// 検索全体の結果
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    /// 1 行でもマッチしたか
+    pub matched: bool,
+    /// 読み込めなかったファイルがあったか
+    pub errors: bool,
+    /// 検索したファイルごとの結果 (出力した順)
+    pub files: Vec<FileCount>,
+}
+
+impl Summary {
+    /// --json の最後に出力するまとめのレコードを書き出す
+    pub fn write_json<W: Write>(&self, out: &mut W, elapsed: Duration) -> io::Result<()> {
+        let record = Record::Summary {
+            files: &self.files,
+            matched_lines: self.files.iter().map(|f| f.matches).sum(),
+            files_matched: self.files.iter().filter(|f| f.matches > 0).count(),
+            elapsed_secs: elapsed.as_secs_f64(),
+        };
+        write_record(out, &record)
+    }
+
+    /// grep と同じ終了コード (マッチすれば 0 、しなければ 1 、エラーがあれば 2)
+    pub fn exit_code(&self) -> i32 {
+        if self.errors {
+            2
+        } else if self.matched {
+            0
+        } else {
+            1
+        }
+    }
+}
+
+/// 1 つのファイルの検索結果
+///
+/// 並列に検索している間は出力をバッファにためておき、ファイル単位でまとめて書き出す。
+struct FileResult {
+    output: Vec<u8>,
+    /// 検索したファイルと、マッチした行数
+    result: Result<(PathBuf, u64), walk::PathError>,
+}
+
+/// args.files のファイルを並列に検索する
+///
+/// 検索は args.threads 個 (0 ならCPU数) のスレッドを持つ rayon のスレッドプールで行うが、
+/// 出力はファイルごとにまとめ、引数で指定された順 (-r の場合はその中の名前順) に書き出す。
+/// 先頭のファイルの検索が遅くても、順番待ちで溜める出力はスレッド数分までに抑える。
+/// 読み込めないファイルがあっても、エラーを err に出力して残りのファイルの検索を続ける。
+pub fn grep_files<W, E>(m: &Matcher, args: &Args, out: &mut W, err: &mut E) -> io::Result<Summary>
+where
+    W: Write,
+    E: Write,
+{
+    let targets: Vec<Result<PathBuf, walk::PathError>> = args
+        .files
+        .iter()
+        .flat_map(|file| {
+            if args.recursive {
+                walk::walk(file, &args.walk)
+            } else {
+                vec![Ok(file.clone())]
+            }
+        })
+        .collect();
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.threads)
+        .build()
+        .map_err(io::Error::other)?;
+    let threads = pool.current_num_threads();
+    let mut writer = InOrder {
+        out,
+        err,
+        summary: Summary::default(),
+        pending: BTreeMap::new(),
+        next: 0,
+    };
+
+    // in_place_scope はこのスレッドで動くので、検索を待つ間もプールのスレッドを占有しない
+    pool.in_place_scope(|scope| -> io::Result<()> {
+        let (sender, receiver) = mpsc::channel();
+        for (i, target) in targets.into_iter().enumerate() {
+            // 書き出しが threads 個以上遅れているあいだは、次のファイルの検索を始めない
+            while i >= writer.next + threads {
+                let (j, file_result) = receiver.recv().unwrap();
+                writer.push(j, file_result)?;
+            }
+            let sender = sender.clone();
+            scope.spawn(move |_| {
+                let mut output = vec![];
+                let result =
+                    target.and_then(
+                        |path| match grep_file(m, &path, &args.options, &mut output) {
+                            Ok(count) => Ok((path, count)),
+                            Err(error) => Err(walk::PathError { path, error }),
+                        },
+                    );
+                // 書き出しに失敗して受信側が先に終了していれば、結果は捨てる
+                let _ = sender.send((i, FileResult { output, result }));
+            });
+        }
+        drop(sender);
+        for (i, file_result) in receiver {
+            writer.push(i, file_result)?;
+        }
+        Ok(())
+    })?;
+
+    Ok(writer.summary)
+}
+
+/// 終わった順に届く検索結果を、ファイルの順に書き出す
+struct InOrder<'a, W, E> {
+    out: &'a mut W,
+    err: &'a mut E,
+    summary: Summary,
+    /// 次に出力すべきものが届くまで手元に置いておく結果
+    pending: BTreeMap<usize, FileResult>,
+    /// 次に書き出すファイルの番号
+    next: usize,
+}
+
+impl<W: Write, E: Write> InOrder<'_, W, E> {
+    /// i 番目のファイルの結果を受け取り、書き出せるところまで書き出す
+    fn push(&mut self, i: usize, file_result: FileResult) -> io::Result<()> {
+        self.pending.insert(i, file_result);
+        while let Some(FileResult { output, result }) = self.pending.remove(&self.next) {
+            self.out.write_all(&output)?;
+            let file_count = match result {
+                Ok((path, count)) => {
+                    self.summary.matched |= count > 0;
+                    FileCount {
+                        path: path.to_string_lossy().into_owned(),
+                        matches: count,
+                        error: None,
+                    }
+                }
+                Err(e) => {
+                    writeln!(self.err, "grep: {}", e)?;
+                    self.summary.errors = true;
+                    FileCount {
+                        path: e.path.to_string_lossy().into_owned(),
+                        matches: 0,
+                        error: Some(e.error.to_string()),
+                    }
+                }
+            };
+            self.summary.files.push(file_count);
+            self.next += 1;
+        }
+        Ok(())
+    }
+}
+
+/// grep コマンドのエントリポイント
+///
+/// args は resolve 済みのものを受け取る。
+pub fn grep_main(args: Args) -> Result<Summary, Box<dyn Error>> {
+    let m = args.options.build_matcher(&args.pattern)?;
+
+    let started = Instant::now();
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    let summary = if args.files.is_empty() {
+        // 第2引数以降が省略されていた場合、標準入力を受け付ける
+        let stdin = io::stdin();
+        let count = grep(&m, stdin.lock(), STDIN_NAME, &args.options, &mut out)?;
+        Summary {
+            matched: count > 0,
+            errors: false,
+            files: vec![FileCount {
+                path: STDIN_NAME.to_string(),
+                matches: count,
+                error: None,
+            }],
+        }
+    } else {
+        grep_files(&m, &args, &mut out, &mut io::stderr())?
+    };
+
+    if args.options.json {
+        summary.write_json(&mut out, started.elapsed())?;
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+fn run(pattern: &str, input: &str, opts: &Options) -> (u64, String) {
+    let m = opts.build_matcher(pattern).unwrap();
+    let mut out = vec![];
+    let count = grep(&m, io::Cursor::new(input), "file", opts, &mut out).unwrap();
+    let result = (count, String::from_utf8(out).unwrap());
+
+    // バッファ全体を検索する方法でも同じ結果になる
+    let mut out = vec![];
+    let count = grep_buffer(&m, input.as_bytes(), "file", opts, &mut out).unwrap();
+    assert_eq!(
+        result,
+        (count, String::from_utf8(out).unwrap()),
+        "{:?}",
+        pattern
+    );
+
+    result
+}
+
+#[cfg(test)]
+static POEM: &str = "The quick brown fox\n\
+                     jumps over\n\
+                     the lazy dog.\n\
+                     A QUICK end\n\
+                     to the story\n";
+
+#[test]
+fn test_grep_regex() {
+    let (count, out) = run(r"qu\w+k", POEM, &Options::default());
+    assert_eq!(count, 1);
+    assert_eq!(out, "The quick brown fox\n");
+
+    let (count, out) = run(r"^\w+ \w+$", POEM, &Options::default());
+    assert_eq!(count, 1);
+    assert_eq!(out, "jumps over\n");
+}
+
+#[test]
+fn test_grep_ignore_case_and_line_number() {
+    let opts = Options {
+        ignore_case: true,
+        line_number: true,
+        ..Options::default()
+    };
+    let (count, out) = run("quick", POEM, &opts);
+    assert_eq!(count, 2);
+    assert_eq!(out, "1:The quick brown fox\n4:A QUICK end\n");
+}
+
+#[test]
+fn test_grep_invert() {
+    let opts = Options {
+        invert: true,
+        ..Options::default()
+    };
+    let (count, out) = run("(?i)the", POEM, &opts);
+    assert_eq!(count, 2);
+    assert_eq!(out, "jumps over\nA QUICK end\n");
+}
+
+#[test]
+fn test_grep_count_and_files_with_matches() {
+    let opts = Options {
+        count: true,
+        with_filename: true,
+        ..Options::default()
+    };
+    assert_eq!(run("o", POEM, &opts), (4, "file:4\n".to_string()));
+
+    let opts = Options {
+        count: true,
+        ..Options::default()
+    };
+    assert_eq!(run("zzz", POEM, &opts), (0, "0\n".to_string()));
+
+    let opts = Options {
+        files_with_matches: true,
+        ..Options::default()
+    };
+    // 最初にマッチした時点で読み込みをやめる
+    assert_eq!(run("o", POEM, &opts), (1, "file\n".to_string()));
+    assert_eq!(run("zzz", POEM, &opts), (0, "".to_string()));
+}
+
+#[test]
+fn test_grep_with_filename() {
+    let opts = Options {
+        with_filename: true,
+        line_number: true,
+        ..Options::default()
+    };
+    let (_, out) = run("dog", POEM, &opts);
+    assert_eq!(out, "file:3:the lazy dog.\n");
+}
+
+#[test]
+fn test_grep_context() {
+    let input = "1\n2\nmatch a\n4\n5\n6\n7\nmatch b\nmatch c\n10\n";
+    let opts = Options {
+        line_number: true,
+        before_context: 1,
+        after_context: 1,
+        ..Options::default()
+    };
+    let (count, out) = run("match", input, &opts);
+    assert_eq!(count, 3);
+    assert_eq!(
+        out,
+        "2-2\n3:match a\n4-4\n--\n7-7\n8:match b\n9:match c\n10-10\n"
+    );
+
+    // 文脈が重なったり隣接したりする場合は区切りを出力しない
+    let opts = Options {
+        after_context: 2,
+        ..Options::default()
+    };
+    let (_, out) = run("^[13]$", "1\n2\n3\n4\n5\n6\n", &opts);
+    assert_eq!(out, "1\n2\n3\n4\n5\n");
+
+    let opts = Options {
+        before_context: 5,
+        ..Options::default()
+    };
+    let (_, out) = run("3", "1\n2\n3\n", &opts);
+    assert_eq!(out, "1\n2\n3\n");
+}
+
+#[test]
+fn test_grep_invalid_utf8() {
+    let m = Options::default().build_matcher("a").unwrap();
+    let input: &[u8] = b"a\n\xff\n\xfe a\n";
+    let mut out = vec![];
+    assert_eq!(
+        grep(&m, input, "file", &Options::default(), &mut out).unwrap(),
+        2
+    );
+    assert_eq!(out, "a\n\u{fffd} a\n".as_bytes());
+
+    let mut out = vec![];
+    let count = grep_buffer(&m, input, "file", &Options::default(), &mut out).unwrap();
+    assert_eq!(count, 2);
+    assert_eq!(out, "a\n\u{fffd} a\n".as_bytes());
+}
+
+#[test]
+fn test_grep_buffer_line_boundaries() {
+    let opts = Options {
+        line_number: true,
+        ..Options::default()
+    };
+    // 改行をまたぐマッチは行としてはマッチしない
+    assert_eq!(run(r"fox\sjumps", POEM, &opts), (0, "".to_string()));
+    assert_eq!(
+        run("^t", POEM, &opts),
+        (2, "3:the lazy dog.\n5:to the story\n".to_string())
+    );
+    assert_eq!(
+        run("x$", POEM, &opts),
+        (1, "1:The quick brown fox\n".to_string())
+    );
+    // バッファ全体の先頭と末尾にしかマッチしないアンカーも、各行の先頭と末尾にマッチする
+    assert_eq!(
+        run(r"\Athe", POEM, &opts),
+        (1, "3:the lazy dog.\n".to_string())
+    );
+    assert_eq!(
+        run(r"dog\.\z", POEM, &opts),
+        (1, "3:the lazy dog.\n".to_string())
+    );
+    assert_eq!(
+        run("(?-m)^t", POEM, &opts),
+        (2, "3:the lazy dog.\n5:to the story\n".to_string())
+    );
+    assert_eq!(run("", "a\n\nb", &opts), (3, "1:a\n2:\n3:b\n".to_string()));
+    assert_eq!(run("b", "a\r\nb\r\nc", &opts), (1, "2:b\n".to_string()));
+    assert_eq!(run("c", "a\r\nb\r\nc", &opts), (1, "3:c\n".to_string()));
+    assert_eq!(run("a", "", &opts), (0, "".to_string()));
+    // 最後の改行の後ろに空の行はない
+    assert_eq!(run("^$", "a\n", &opts), (0, "".to_string()));
+    assert_eq!(run("^$", "", &opts), (0, "".to_string()));
+    assert_eq!(run("^$", "a\n\nb\n", &opts), (1, "2:\n".to_string()));
+    assert_eq!(run("^$", "a\n\n", &opts), (1, "2:\n".to_string()));
+}
+
+#[test]
+fn test_grep_file_mmap() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("big");
+    let mut content = "filler line\n".repeat(MMAP_THRESHOLD as usize / 12 + 1);
+    let mut bytes = content.into_bytes();
+    bytes.extend_from_slice(b"needle \xff\n\xffneedle\n");
+    std::fs::write(&path, &bytes).unwrap();
+    assert!(bytes.len() as u64 >= MMAP_THRESHOLD);
+
+    let opts = Options {
+        line_number: true,
+        ..Options::default()
+    };
+    let m = opts.build_matcher("needle").unwrap();
+    let mut out = vec![];
+    assert_eq!(grep_file(&m, &path, &opts, &mut out).unwrap(), 2);
+    let lines = MMAP_THRESHOLD as usize / 12 + 1;
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        format!(
+            "{}:needle \u{fffd}\n{}:\u{fffd}needle\n",
+            lines + 1,
+            lines + 2
+        )
+    );
+
+    // メモリマップで検索しても、 BufRead で 1 行ずつ検索しても同じ結果になる
+    for pattern in ["needle", "^$", "", "line$", "^f"] {
+        let m = opts.build_matcher(pattern).unwrap();
+        let mut mapped = vec![];
+        let mapped_count = grep_file(&m, &path, &opts, &mut mapped).unwrap();
+        let mut read = vec![];
+        let reader = io::BufReader::new(File::open(&path).unwrap());
+        let read_count = grep(&m, reader, &path.to_string_lossy(), &opts, &mut read).unwrap();
+        assert_eq!((mapped_count, mapped), (read_count, read), "{:?}", pattern);
+    }
+}
+
+/// 以前の実装 (行ごとに String を確保する lines() と &str 用の正規表現) との速度比較
+///
+/// cargo test --release -- --ignored bench_grep --nocapture
+#[test]
+#[ignore]
+fn bench_grep() {
+    use std::time::Instant;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bench.txt");
+    let mut content = String::new();
+    for i in 0..1_000_000 {
+        content.push_str(&format!(
+            "line {} of some moderately long text to search through\n",
+            i
+        ));
+        if i % 1000 == 0 {
+            content.push_str("here is the needle we want\n");
+        }
+    }
+    std::fs::write(&path, &content).unwrap();
+
+    for pattern in ["needle", r"ne+dle\s+we", "(?i)NEEDLE"] {
+        let started = Instant::now();
+        let old = regex::Regex::new(pattern).unwrap();
+        let reader = BufReader::new(File::open(&path).unwrap());
+        let mut old_count = 0;
+        for line in reader.lines() {
+            let line = line.unwrap();
+            if old.is_match(&line) {
+                old_count += 1;
+                writeln!(io::sink(), "{}", line).unwrap();
+            }
+        }
+        let old_time = started.elapsed();
+
+        let started = Instant::now();
+        let opts = Options::default();
+        let m = opts.build_matcher(pattern).unwrap();
+        let count = grep_file(&m, &path, &opts, &mut io::sink()).unwrap();
+        let new_time = started.elapsed();
+
+        assert_eq!(count, old_count);
+        println!(
+            "{:<14} lines(): {:>8.2?}  buffer: {:>8.2?}  ({:.1}x)",
+            pattern,
+            old_time,
+            new_time,
+            old_time.as_secs_f64() / new_time.as_secs_f64()
+        );
+    }
+}
+
+#[test]
+fn test_parse_args() {
+    let to_args = |s: &str| s.split_whitespace().map(String::from).collect::<Vec<_>>();
+
+    let args = parse_args(to_args("-in -A2 -B 3 foo a.txt b.txt")).unwrap();
+    assert_eq!(
+        args.options,
+        Options {
+            ignore_case: true,
+            line_number: true,
+            after_context: 2,
+            before_context: 3,
+            with_filename: true,
+            ..Options::default()
+        }
+    );
+    assert_eq!(args.pattern, "foo");
+    assert_eq!(
+        args.files,
+        vec![PathBuf::from("a.txt"), PathBuf::from("b.txt")]
+    );
+
+    let args = parse_args(to_args("-C1 -c -- -v")).unwrap();
+    assert_eq!(args.options.after_context, 1);
+    assert_eq!(args.options.before_context, 1);
+    assert!(args.options.count);
+    assert_eq!(args.pattern, "-v");
+    assert!(args.files.is_empty());
+
+    assert!(parse_args(to_args("-x foo")).is_err());
+    assert!(parse_args(to_args("-A foo")).is_err());
+    assert!(parse_args(to_args("-A")).is_err());
+    assert!(parse_args(to_args("-n")).is_err());
+
+    let args = parse_args(to_args(
+        "-r --include *.rs --exclude=target --no-ignore foo",
+    ))
+    .unwrap();
+    assert!(args.recursive);
+    assert!(args.options.with_filename);
+    assert_eq!(args.files, vec![PathBuf::from(".")]);
+    assert_eq!(args.walk.include.len(), 1);
+    assert_eq!(args.walk.exclude.len(), 1);
+    assert!(!args.walk.gitignore);
+    assert!(parse_args(to_args("--include")).is_err());
+    assert!(parse_args(to_args("--color foo")).is_err());
+
+    assert_eq!(parse_args(to_args("--threads 4 foo")).unwrap().threads, 4);
+    assert_eq!(parse_args(to_args("--threads=1 foo")).unwrap().threads, 1);
+    assert!(parse_args(to_args("--json foo")).unwrap().options.json);
+    assert!(parse_args(to_args("--threads many foo")).is_err());
+}
+
+#[test]
+fn test_looks_binary() {
+    assert!(!looks_binary(b"hello\nworld\n"));
+    assert!(looks_binary(b"\x7fELF\x02\x01\x01\x00\x00"));
+    // 先頭より後ろの NUL は見ない
+    let mut late = vec![b'a'; BINARY_CHECK_LEN];
+    late.push(0);
+    assert!(!looks_binary(&late));
+}
+
+#[test]
+fn test_grep_files_recursive() {
+    use std::fs;
+
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::create_dir_all(root.join("src/nested")).unwrap();
+    fs::write(root.join("src/a.rs"), "fn main() {}\n").unwrap();
+    fs::write(root.join("src/nested/b.rs"), "// main\n").unwrap();
+    fs::write(root.join("src/notes.txt"), "main\n").unwrap();
+    fs::write(root.join("src/bin.rs"), b"main\x00\x01").unwrap();
+
+    let root_arg = root.to_string_lossy().to_string();
+    let to_args = |extra: &[&str]| -> Args {
+        let mut v: Vec<String> = extra.iter().map(|s| s.to_string()).collect();
+        v.push("main".to_string());
+        v.push(root_arg.clone());
+        v.push(root.join("missing").to_string_lossy().to_string());
+        parse_args(v).unwrap()
+    };
+
+    let args = to_args(&["-r", "-l", "--include=*.rs"]);
+    let m = args.options.build_matcher(&args.pattern).unwrap();
+    let mut out = vec![];
+    let mut err = vec![];
+    let summary = grep_files(&m, &args, &mut out, &mut err).unwrap();
+
+    // バイナリファイルは読み飛ばし、存在しないファイルは報告して続ける
+    let out = String::from_utf8(out).unwrap();
+    let expected = format!(
+        "{}\n{}\n",
+        root.join("src/a.rs").display(),
+        root.join("src/nested/b.rs").display()
+    );
+    assert_eq!(out, expected);
+    let err = String::from_utf8(err).unwrap();
+    assert!(err.starts_with("grep: "));
+    assert!(err.contains("missing"));
+    assert!(summary.matched);
+    assert!(summary.errors);
+    assert_eq!(summary.exit_code(), 2);
+    let counts: Vec<(u64, bool)> = summary
+        .files
+        .iter()
+        .map(|f| (f.matches, f.error.is_some()))
+        .collect();
+    // a.rs, bin.rs, nested/b.rs, missing
+    assert_eq!(counts, vec![(1, false), (0, false), (1, false), (0, true)]);
+
+    // -r なしではディレクトリはエラー
+    let args = to_args(&[]);
+    let mut out = vec![];
+    let mut err = vec![];
+    let summary = grep_files(&m, &args, &mut out, &mut err).unwrap();
+    assert!(out.is_empty());
+    assert!(String::from_utf8(err).unwrap().contains("Is a directory"));
+    assert_eq!(summary.exit_code(), 2);
+}
+
+#[test]
+fn test_grep_files_parallel_order() {
+    use std::fs;
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut files = vec![];
+    for i in 0..40 {
+        let path = dir.path().join(format!("file_{:02}", i));
+        // 大きさの違うファイルにして、終わる順番をばらばらにする
+        let content = format!("match {}\nno\n", i).repeat((40 - i) * 50);
+        fs::write(&path, content).unwrap();
+        files.push(path.to_string_lossy().to_string());
+    }
+    files.insert(20, dir.path().join("missing").to_string_lossy().to_string());
+
+    let search = |threads: &str| {
+        let mut v = vec![
+            "--threads".to_string(),
+            threads.to_string(),
+            "-c".to_string(),
+        ];
+        v.push("match".to_string());
+        v.extend(files.iter().cloned());
+        let args = parse_args(v).unwrap();
+        let m = args.options.build_matcher(&args.pattern).unwrap();
+        let mut out = vec![];
+        let mut err = vec![];
+        let summary = grep_files(&m, &args, &mut out, &mut err).unwrap();
+        (
+            summary,
+            String::from_utf8(out).unwrap(),
+            String::from_utf8(err).unwrap(),
+        )
+    };
+
+    let (summary, out, err) = search("4");
+    assert_eq!((summary, out.clone(), err.clone()), search("1"));
+
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 40);
+    for (i, line) in lines.iter().enumerate() {
+        assert!(line.ends_with(&format!("file_{:02}:{}", i, (40 - i) * 50)));
+    }
+    assert!(err.contains("missing"));
+
+    // 書き出しに失敗しても、順番待ちのワーカーを残さずに終了する
+    struct Broken;
+    impl Write for Broken {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::from(io::ErrorKind::BrokenPipe))
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    let mut v = vec!["--threads=2".to_string(), "match".to_string()];
+    v.extend(files.iter().cloned());
+    let args = parse_args(v).unwrap();
+    let m = args.options.build_matcher(&args.pattern).unwrap();
+    let result = grep_files(&m, &args, &mut Broken, &mut io::sink());
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+}
+
+#[test]
+fn test_grep_json() {
+    let opts = Options {
+        json: true,
+        before_context: 1,
+        ..Options::default()
+    };
+    let m = opts.build_matcher(r"(o)(x)?").unwrap();
+    let mut out = vec![];
+    let count = grep(&m, "a\nfox\nno\n".as_bytes(), "f.txt", &opts, &mut out).unwrap();
+    assert_eq!(count, 2);
+
+    let records: Vec<serde_json::Value> = String::from_utf8(out)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(
+        records,
+        vec![
+            serde_json::json!({
+                "type": "context", "path": "f.txt", "line_number": 1,
+                "absolute_offset": 0, "line": "a",
+            }),
+            serde_json::json!({
+                "type": "match", "path": "f.txt", "line_number": 2,
+                "absolute_offset": 2, "line": "fox",
+                "submatches": [{
+                    "match": "ox", "start": 1, "end": 3,
+                    "groups": [
+                        {"text": "o", "start": 1, "end": 2},
+                        {"text": "x", "start": 2, "end": 3},
+                    ],
+                }],
+            }),
+            serde_json::json!({
+                "type": "match", "path": "f.txt", "line_number": 3,
+                "absolute_offset": 6, "line": "no",
+                "submatches": [{"match": "o", "start": 1, "end": 2, "groups": [{"text": "o", "start": 1, "end": 2}, null]}],
+            }),
+        ]
+    );
+
+    // バッファ全体を検索する方法でも、 -n がなくても行番号を数える
+    let opts = Options {
+        json: true,
+        ..Options::default()
+    };
+    let mut out = vec![];
+    grep_buffer(&m, b"a\nfox\nno\n", "f.txt", &opts, &mut out).unwrap();
+    let numbers: Vec<(u64, u64)> = String::from_utf8(out)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let v: serde_json::Value = serde_json::from_str(line).unwrap();
+            (
+                v["line_number"].as_u64().unwrap(),
+                v["absolute_offset"].as_u64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(numbers, vec![(2, 2), (3, 6)]);
+
+    // -c と一緒に指定した場合、件数はまとめのレコードにだけ含まれる
+    let opts = Options {
+        json: true,
+        count: true,
+        ..Options::default()
+    };
+    let mut out = vec![];
+    grep_buffer(&m, b"fox\n", "f.txt", &opts, &mut out).unwrap();
+    assert!(out.is_empty());
+}
+
+#[test]
+fn test_summary_json() {
+    let summary = Summary {
+        matched: true,
+        errors: true,
+        files: vec![
+            FileCount {
+                path: "a".to_string(),
+                matches: 3,
+                error: None,
+            },
+            FileCount {
+                path: "b".to_string(),
+                matches: 0,
+                error: None,
+            },
+            FileCount {
+                path: "c".to_string(),
+                matches: 0,
+                error: Some("not found".to_string()),
+            },
+        ],
+    };
+    let mut out = vec![];
+    summary
+        .write_json(&mut out, Duration::from_millis(1500))
+        .unwrap();
+    let record: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(
+        record,
+        serde_json::json!({
+            "type": "summary",
+            "files": [
+                {"path": "a", "matches": 3},
+                {"path": "b", "matches": 0},
+                {"path": "c", "matches": 0, "error": "not found"},
+            ],
+            "matched_lines": 3,
+            "files_matched": 1,
+            "elapsed_secs": 1.5,
+        })
+    );
+}
@@ -0,0 +1,632 @@
+use encoding_rs::{DecoderResult, EncoderResult, SHIFT_JIS};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::ops::Range;
+use std::path::PathBuf;
+
+/// 扱える文字エンコーディング
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Encoding {
+    #[value(name = "utf-8", alias = "utf8")]
+    Utf8,
+    #[value(name = "utf-16le", alias = "utf16le")]
+    Utf16Le,
+    #[value(name = "utf-16be", alias = "utf16be")]
+    Utf16Be,
+    #[value(name = "latin1", alias = "iso-8859-1")]
+    Latin1,
+    #[value(name = "shift_jis", alias = "sjis")]
+    ShiftJis,
+}
+
+impl Encoding {
+    /// バイトオーダーマーク (BOM がないエンコーディングは None)
+    pub fn bom(self) -> Option<&'static [u8]> {
+        match self {
+            Encoding::Utf8 => Some(b"\xef\xbb\xbf"),
+            Encoding::Utf16Le => Some(b"\xff\xfe"),
+            Encoding::Utf16Be => Some(b"\xfe\xff"),
+            Encoding::Latin1 | Encoding::ShiftJis => None,
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf16Le => "UTF-16LE",
+            Encoding::Utf16Be => "UTF-16BE",
+            Encoding::Latin1 => "ISO-8859-1",
+            Encoding::ShiftJis => "Shift_JIS",
+        };
+        f.write_str(name)
+    }
+}
+
+/// 不正なバイト列や、変換先で表せない文字の扱い
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ErrorMode {
+    /// エラーにして変換をやめる
+    #[default]
+    Strict,
+    /// U+FFFD (変換先で表せない文字は `?`) に置き換える
+    Lossy,
+    /// `\xHH` (変換先で表せない文字は `\u{HHHH}`) の形式で書き出す
+    Escape,
+}
+
+/// 変換の失敗
+#[derive(Debug, PartialEq)]
+pub enum TranscodeError {
+    /// 入力に不正なバイト列があった (offset は入力の先頭からのバイト数)
+    InvalidSequence { encoding: Encoding, offset: usize },
+    /// 変換先のエンコーディングで表せない文字があった
+    /// (offset はデコードした UTF-8 のテキストの先頭からのバイト数)
+    Unmappable {
+        encoding: Encoding,
+        ch: char,
+        offset: usize,
+    },
+    /// BOM のないエンコーディングに BOM を付けようとした
+    NoBom(Encoding),
+}
+
+impl fmt::Display for TranscodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TranscodeError::InvalidSequence { encoding, offset } => {
+                write!(f, "invalid {} sequence at byte offset {}", encoding, offset)
+            }
+            TranscodeError::Unmappable {
+                encoding,
+                ch,
+                offset,
+            } => write!(
+                f,
+                "U+{:04X} cannot be represented in {} (byte offset {} of the decoded text)",
+                *ch as u32, encoding, offset
+            ),
+            TranscodeError::NoBom(encoding) => write!(f, "{} has no byte order mark", encoding),
+        }
+    }
+}
+
+impl Error for TranscodeError {}
+
+/// strict 以外のモードで置き換えた箇所
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Problems {
+    pub count: usize,
+    /// 最初に見つかった箇所のバイトオフセット
+    pub first_offset: Option<usize>,
+}
+
+impl Problems {
+    fn record(&mut self, offset: usize) {
+        self.count += 1;
+        self.first_offset.get_or_insert(offset);
+    }
+}
+
+/// デコードした結果を書き込むバッファ
+///
+/// 不正なバイト列はモードに合わせて置き換えるか、エラーにする。
+struct TextSink<'a> {
+    input: &'a [u8],
+    encoding: Encoding,
+    mode: ErrorMode,
+    text: String,
+    invalid: Problems,
+}
+
+impl TextSink<'_> {
+    /// input[range] が不正なバイト列だった
+    fn invalid(&mut self, range: Range<usize>) -> Result<(), TranscodeError> {
+        self.invalid.record(range.start);
+        match self.mode {
+            ErrorMode::Strict => {
+                return Err(TranscodeError::InvalidSequence {
+                    encoding: self.encoding,
+                    offset: range.start,
+                })
+            }
+            ErrorMode::Lossy => self.text.push(char::REPLACEMENT_CHARACTER),
+            ErrorMode::Escape => {
+                for b in &self.input[range] {
+                    self.text.push_str(&format!("\\x{:02X}", b));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 入力の先頭の BOM からエンコーディングを推測する
+fn sniff_bom(input: &[u8]) -> Option<Encoding> {
+    [Encoding::Utf8, Encoding::Utf16Le, Encoding::Utf16Be]
+        .into_iter()
+        .find(|enc| enc.bom().is_some_and(|bom| input.starts_with(bom)))
+}
+
+/// 入力のエンコーディングを推測する
+///
+/// BOM があればそれに従う。なければ UTF-8 として正しいか、
+/// NUL バイトの位置から BOM のない UTF-16 らしいか、 Shift_JIS として正しいかを順に調べ、
+/// どれにも当てはまらなければ (すべてのバイト列が正しい) Latin-1 とみなす。
+pub fn detect(input: &[u8]) -> Encoding {
+    if let Some(enc) = sniff_bom(input) {
+        return enc;
+    }
+    if std::str::from_utf8(input).is_ok() {
+        return Encoding::Utf8;
+    }
+    if input.len().is_multiple_of(2) {
+        // ASCII の多いテキストを UTF-16 にすると、上位バイトの 0 が交互に並ぶ
+        let pairs = input.len() / 2;
+        let zeros_at = |parity: usize| {
+            input
+                .iter()
+                .skip(parity)
+                .step_by(2)
+                .filter(|&&b| b == 0)
+                .count()
+        };
+        if zeros_at(1) * 4 > pairs && zeros_at(0) == 0 {
+            return Encoding::Utf16Le;
+        }
+        if zeros_at(0) * 4 > pairs && zeros_at(1) == 0 {
+            return Encoding::Utf16Be;
+        }
+    }
+    if decode(input, Encoding::ShiftJis, ErrorMode::Strict).is_ok() {
+        return Encoding::ShiftJis;
+    }
+    Encoding::Latin1
+}
+
+/// input を encoding のテキストとしてデコードする
+///
+/// 先頭に encoding の BOM があれば取り除く。エラーのオフセットは BOM を含めた入力の先頭から数える。
+pub fn decode(
+    input: &[u8],
+    encoding: Encoding,
+    mode: ErrorMode,
+) -> Result<(String, Problems), TranscodeError> {
+    let start = match encoding.bom() {
+        Some(bom) if input.starts_with(bom) => bom.len(),
+        _ => 0,
+    };
+    let mut sink = TextSink {
+        input,
+        encoding,
+        mode,
+        text: String::with_capacity(input.len()),
+        invalid: Problems::default(),
+    };
+
+    match encoding {
+        Encoding::Utf8 => decode_utf8(&mut sink, start)?,
+        Encoding::Utf16Le => decode_utf16(&mut sink, start, u16::from_le_bytes)?,
+        Encoding::Utf16Be => decode_utf16(&mut sink, start, u16::from_be_bytes)?,
+        // Latin-1 の各バイトは、同じ番号の Unicode の文字になる
+        Encoding::Latin1 => sink.text.extend(input.iter().map(|&b| b as char)),
+        Encoding::ShiftJis => decode_shift_jis(&mut sink)?,
+    }
+    Ok((sink.text, sink.invalid))
+}
+
+fn decode_utf8(sink: &mut TextSink, mut pos: usize) -> Result<(), TranscodeError> {
+    let input = sink.input;
+    while pos < input.len() {
+        match std::str::from_utf8(&input[pos..]) {
+            Ok(s) => {
+                sink.text.push_str(s);
+                break;
+            }
+            Err(e) => {
+                let valid = e.valid_up_to();
+                // valid_up_to までは正しい UTF-8 であることが保証されている
+                sink.text
+                    .push_str(std::str::from_utf8(&input[pos..pos + valid]).unwrap());
+                // error_len が None なら、入力の最後で文字が途切れている
+                let len = e.error_len().unwrap_or(input.len() - pos - valid);
+                sink.invalid(pos + valid..pos + valid + len)?;
+                pos += valid + len;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn decode_utf16(
+    sink: &mut TextSink,
+    start: usize,
+    from_bytes: fn([u8; 2]) -> u16,
+) -> Result<(), TranscodeError> {
+    let input = sink.input;
+    let unit = |i: usize| from_bytes([input[i], input[i + 1]]);
+    let mut pos = start;
+    while pos + 1 < input.len() {
+        let u = unit(pos);
+        match u {
+            0xd800..=0xdbff
+                if pos + 3 < input.len() && (0xdc00..=0xdfff).contains(&unit(pos + 2)) =>
+            {
+                let c = 0x10000 + ((u as u32 - 0xd800) << 10) + (unit(pos + 2) as u32 - 0xdc00);
+                sink.text.push(char::from_u32(c).unwrap());
+                pos += 4;
+            }
+            // 対になっていないサロゲート
+            0xd800..=0xdfff => {
+                sink.invalid(pos..pos + 2)?;
+                pos += 2;
+            }
+            _ => {
+                sink.text.push(char::from_u32(u as u32).unwrap());
+                pos += 2;
+            }
+        }
+    }
+    if pos < input.len() {
+        // 奇数バイトの入力の最後の 1 バイト
+        sink.invalid(pos..input.len())?;
+    }
+    Ok(())
+}
+
+fn decode_shift_jis(sink: &mut TextSink) -> Result<(), TranscodeError> {
+    let input = sink.input;
+    let mut decoder = SHIFT_JIS.new_decoder_without_bom_handling();
+    let mut pos = 0;
+    loop {
+        let needed = decoder
+            .max_utf8_buffer_length_without_replacement(input.len() - pos)
+            .unwrap_or(usize::MAX);
+        sink.text.reserve(needed);
+        let (result, read) =
+            decoder.decode_to_string_without_replacement(&input[pos..], &mut sink.text, true);
+        pos += read;
+        match result {
+            DecoderResult::InputEmpty => return Ok(()),
+            DecoderResult::OutputFull => continue,
+            DecoderResult::Malformed(bad, good_after) => {
+                // 不正なバイト列の後ろの good_after バイトも読み込み済みになっている
+                // (その部分はデコーダが覚えていて、続きを渡すと出力される)
+                let end = pos - good_after as usize;
+                sink.invalid(end - bad as usize..end)?;
+            }
+        }
+    }
+}
+
+/// text を encoding でエンコードする
+///
+/// bom が true なら先頭に BOM を付ける。
+pub fn encode(
+    text: &str,
+    encoding: Encoding,
+    mode: ErrorMode,
+    bom: bool,
+) -> Result<(Vec<u8>, Problems), TranscodeError> {
+    let mut bytes = Vec::with_capacity(text.len());
+    if bom {
+        bytes.extend_from_slice(encoding.bom().ok_or(TranscodeError::NoBom(encoding))?);
+    }
+    let mut unmappable = Problems::default();
+    // 表せない文字をモードに合わせて置き換える
+    let mut replace = |bytes: &mut Vec<u8>, ch: char, offset: usize| {
+        unmappable.record(offset);
+        match mode {
+            ErrorMode::Strict => Err(TranscodeError::Unmappable {
+                encoding,
+                ch,
+                offset,
+            }),
+            ErrorMode::Lossy => {
+                bytes.push(b'?');
+                Ok(())
+            }
+            ErrorMode::Escape => {
+                bytes.extend_from_slice(format!("\\u{{{:X}}}", ch as u32).as_bytes());
+                Ok(())
+            }
+        }
+    };
+
+    match encoding {
+        Encoding::Utf8 => bytes.extend_from_slice(text.as_bytes()),
+        Encoding::Utf16Le => bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes)),
+        Encoding::Utf16Be => bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes)),
+        Encoding::Latin1 => {
+            for (offset, ch) in text.char_indices() {
+                match u8::try_from(ch) {
+                    Ok(b) => bytes.push(b),
+                    Err(_) => replace(&mut bytes, ch, offset)?,
+                }
+            }
+        }
+        Encoding::ShiftJis => {
+            let mut encoder = SHIFT_JIS.new_encoder();
+            let mut pos = 0;
+            loop {
+                let needed = encoder
+                    .max_buffer_length_from_utf8_without_replacement(text.len() - pos)
+                    .unwrap_or(usize::MAX);
+                bytes.reserve(needed);
+                let (result, read) = encoder.encode_from_utf8_to_vec_without_replacement(
+                    &text[pos..],
+                    &mut bytes,
+                    true,
+                );
+                pos += read;
+                match result {
+                    EncoderResult::InputEmpty => break,
+                    EncoderResult::OutputFull => continue,
+                    EncoderResult::Unmappable(ch) => replace(&mut bytes, ch, pos - ch.len_utf8())?,
+                }
+            }
+        }
+    }
+    Ok((bytes, unmappable))
+}
+
+/// 変換した結果
+#[derive(Debug, PartialEq)]
+pub struct Transcoded {
+    pub bytes: Vec<u8>,
+    /// 入力のエンコーディング (推測した場合はその結果)
+    pub from: Encoding,
+    /// 置き換えた不正なバイト列
+    pub invalid: Problems,
+    /// 置き換えた変換先で表せない文字
+    pub unmappable: Problems,
+}
+
+impl Transcoded {
+    /// 終了コード (置き換えた箇所がなければ 0 、あれば 1)
+    pub fn exit_code(&self) -> i32 {
+        if self.invalid.count + self.unmappable.count == 0 {
+            0
+        } else {
+            1
+        }
+    }
+}
+
+/// input を from (None なら推測する) から to に変換する
+pub fn transcode(
+    input: &[u8],
+    from: Option<Encoding>,
+    to: Encoding,
+    mode: ErrorMode,
+    bom: bool,
+) -> Result<Transcoded, TranscodeError> {
+    let from = from.unwrap_or_else(|| detect(input));
+    let (text, invalid) = decode(input, from, mode)?;
+    let (bytes, unmappable) = encode(&text, to, mode, bom)?;
+    Ok(Transcoded {
+        bytes,
+        from,
+        invalid,
+        unmappable,
+    })
+}
+
+/// iconv のコマンドライン引数
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// -f: 入力のエンコーディング (省略した場合は推測する)
+    #[arg(
+        short = 'f',
+        long,
+        value_enum,
+        help = "Encoding of the input (detected if omitted)"
+    )]
+    pub from: Option<Encoding>,
+    #[arg(
+        short = 't',
+        long,
+        value_enum,
+        default_value_t = Encoding::Utf8,
+        help = "Encoding of the output"
+    )]
+    pub to: Encoding,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = ErrorMode::Strict,
+        help = "How to handle invalid input and unmappable characters"
+    )]
+    pub errors: ErrorMode,
+    #[arg(long, help = "Write a byte order mark (UTF-8 and UTF-16 only)")]
+    pub bom: bool,
+    #[arg(long, help = "Only print the detected encoding of the input")]
+    pub detect: bool,
+    #[arg(
+        short = 'o',
+        long,
+        value_name = "FILE",
+        help = "Write to FILE instead of standard output"
+    )]
+    pub output: Option<PathBuf>,
+    #[arg(help = "File to convert (standard input if omitted)")]
+    pub file: Option<PathBuf>,
+}
+
+/// iconv コマンドのエントリポイント
+///
+/// 終了コードを返す。置き換えた箇所があれば、最初の位置を標準エラー出力に報告する。
+pub fn iconv_main(args: Args) -> Result<i32, Box<dyn Error>> {
+    let input = match &args.file {
+        Some(path) => fs::read(path)?,
+        None => {
+            let mut buf = vec![];
+            io::stdin().lock().read_to_end(&mut buf)?;
+            buf
+        }
+    };
+
+    if args.detect {
+        println!("{}", detect(&input));
+        return Ok(0);
+    }
+
+    let result = transcode(&input, args.from, args.to, args.errors, args.bom)?;
+    match &args.output {
+        Some(path) => fs::write(path, &result.bytes)?,
+        None => io::stdout().lock().write_all(&result.bytes)?,
+    }
+
+    if let Some(offset) = result.invalid.first_offset {
+        eprintln!(
+            "iconv: replaced {} invalid {} sequences (first at byte offset {})",
+            result.invalid.count, result.from, offset
+        );
+    }
+    if let Some(offset) = result.unmappable.first_offset {
+        eprintln!(
+            "iconv: replaced {} characters not representable in {} (first at byte offset {} of the decoded text)",
+            result.unmappable.count, args.to, offset
+        );
+    }
+    Ok(result.exit_code())
+}
+
+#[test]
+fn test_decode_utf8() {
+    let input = b"ab\xffc\xe3\x81";
+    assert_eq!(
+        decode(input, Encoding::Utf8, ErrorMode::Strict),
+        Err(TranscodeError::InvalidSequence {
+            encoding: Encoding::Utf8,
+            offset: 2
+        })
+    );
+
+    let (text, invalid) = decode(input, Encoding::Utf8, ErrorMode::Lossy).unwrap();
+    assert_eq!(text, "ab\u{fffd}c\u{fffd}");
+    assert_eq!(
+        invalid,
+        Problems {
+            count: 2,
+            first_offset: Some(2)
+        }
+    );
+
+    let (text, _) = decode(input, Encoding::Utf8, ErrorMode::Escape).unwrap();
+    assert_eq!(text, "ab\\xFFc\\xE3\\x81");
+
+    // BOM は取り除く
+    let (text, _) = decode(b"\xef\xbb\xbfhi", Encoding::Utf8, ErrorMode::Strict).unwrap();
+    assert_eq!(text, "hi");
+}
+
+#[test]
+fn test_decode_utf16() {
+    let le = b"\xff\xfeA\x00B\x30=\xd8\x00\xde";
+    let (text, _) = decode(le, Encoding::Utf16Le, ErrorMode::Strict).unwrap();
+    assert_eq!(text, "A\u{3042}\u{1f600}");
+    let be = b"\xfe\xff\x00A\x30B\xd8=\xde\x00";
+    let (text, _) = decode(be, Encoding::Utf16Be, ErrorMode::Strict).unwrap();
+    assert_eq!(text, "A\u{3042}\u{1f600}");
+
+    // 対になっていないサロゲートと、半端な最後のバイト
+    let input = b"A\x00\x00\xdcB\x00C";
+    assert_eq!(
+        decode(input, Encoding::Utf16Le, ErrorMode::Strict),
+        Err(TranscodeError::InvalidSequence {
+            encoding: Encoding::Utf16Le,
+            offset: 2
+        })
+    );
+    let (text, invalid) = decode(input, Encoding::Utf16Le, ErrorMode::Escape).unwrap();
+    assert_eq!(text, "A\\x00\\xDCB\\x43");
+    assert_eq!(invalid.count, 2);
+}
+
+#[test]
+fn test_shift_jis() {
+    let sjis = b"\x82\xb1\x82\xf1\x82\xc9\x82\xbf\x82\xcdA";
+    let (text, _) = decode(sjis, Encoding::ShiftJis, ErrorMode::Strict).unwrap();
+    assert_eq!(text, "こんにちはA");
+    let (bytes, _) = encode(&text, Encoding::ShiftJis, ErrorMode::Strict, false).unwrap();
+    assert_eq!(bytes, sjis);
+
+    // 0x82 の後に 2 バイト目として使えない 0x20 が続く
+    let (text, invalid) = decode(b"A\x82 B", Encoding::ShiftJis, ErrorMode::Lossy).unwrap();
+    assert_eq!(text, "A\u{fffd} B");
+    assert_eq!(invalid.first_offset, Some(1));
+
+    assert_eq!(
+        encode("a\u{1f600}", Encoding::ShiftJis, ErrorMode::Strict, false),
+        Err(TranscodeError::Unmappable {
+            encoding: Encoding::ShiftJis,
+            ch: '\u{1f600}',
+            offset: 1
+        })
+    );
+    let (bytes, _) = encode("a\u{1f600}b", Encoding::ShiftJis, ErrorMode::Escape, false).unwrap();
+    assert_eq!(bytes, b"a\\u{1F600}b");
+}
+
+#[test]
+fn test_latin1() {
+    let (text, _) = decode(b"caf\xe9", Encoding::Latin1, ErrorMode::Strict).unwrap();
+    assert_eq!(text, "caf\u{e9}");
+    let (bytes, _) = encode(&text, Encoding::Latin1, ErrorMode::Strict, false).unwrap();
+    assert_eq!(bytes, b"caf\xe9");
+
+    let (bytes, unmappable) =
+        encode("a\u{3042}b", Encoding::Latin1, ErrorMode::Lossy, false).unwrap();
+    assert_eq!(bytes, b"a?b");
+    assert_eq!(
+        unmappable,
+        Problems {
+            count: 1,
+            first_offset: Some(1)
+        }
+    );
+    assert_eq!(
+        encode("x", Encoding::Latin1, ErrorMode::Strict, true),
+        Err(TranscodeError::NoBom(Encoding::Latin1))
+    );
+}
+
+#[test]
+fn test_detect_and_transcode() {
+    assert_eq!(detect(b"plain"), Encoding::Utf8);
+    assert_eq!(detect(b"\xff\xfeA\x00"), Encoding::Utf16Le);
+    assert_eq!(detect(b"\xfe\xff\x00A"), Encoding::Utf16Be);
+    assert_eq!(detect(b"A\x00B\x00C\x00\xff\x00"), Encoding::Utf16Le);
+    assert_eq!(detect(b"\x82\xb1\x82\xf1"), Encoding::ShiftJis);
+    assert_eq!(detect(b"caf\xe9!"), Encoding::Latin1);
+
+    let result = transcode(
+        b"\x82\xb1A",
+        None,
+        Encoding::Utf16Be,
+        ErrorMode::Strict,
+        true,
+    )
+    .unwrap();
+    assert_eq!(result.from, Encoding::ShiftJis);
+    assert_eq!(result.bytes, b"\xfe\xff\x30\x53\x00A");
+    assert_eq!(result.exit_code(), 0);
+
+    let result = transcode(
+        b"a\xffb",
+        Some(Encoding::Utf8),
+        Encoding::Latin1,
+        ErrorMode::Lossy,
+        false,
+    )
+    .unwrap();
+    // U+FFFD は Latin-1 では表せないので、さらに ? になる
+    assert_eq!(result.bytes, b"a?b");
+    assert_eq!(result.invalid.first_offset, Some(1));
+    assert_eq!(result.exit_code(), 1);
+}
@@ -1,94 +1,133 @@
 #![allow(unused)]
+use clap::{CommandFactory, Parser, Subcommand};
 use std::error::Error;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::{self, BufReader};
 use std::path::PathBuf;
 
+mod adventure;
+mod diff;
+mod dupes;
+mod fsutil;
+mod grep;
+mod iconv;
+mod pipeline;
+mod replace;
+mod rooms;
+mod stats;
+mod tar;
+mod unicode;
+mod walk;
+mod watch;
+
+/// 終了コード (grep と同じく、エラーの場合は 2 で終了する)
+///
+/// 正常に終了した場合、 grep はマッチした行がなければ 1 、 replace は置換した箇所がなければ 1 、
+/// iconv は不正なバイト列などを置き換えた場合に 1 を返す。
+/// 引数が正しくない場合は clap が 2 で終了する。
+const EXIT_SUCCESS: i32 = 0;
+const EXIT_ERROR: i32 = 2;
+
 /// 入出力のサンプル
-/// cargo run COMMAND
-///     COMMAND: unicode, grep
-fn main() {
-    static UNI: &str = "unicode";
-    static GREP: &str = "grep";
-
-    let mut args = std::env::args().skip(1);
-    match args.next() {
-        // command
-        Some(c) => {
-            if c == UNI {
-                // 標準入力から受け取った文字列の
-                // 各文字の unicode 表現とともに出力する
-                let res = unicode();
-                if let Err(err) = res {
-                    eprintln!("{}", err);
-                    std::process::exit(1);
-                }
-            } else if c == GREP {
-                // grep のサンプル
-                let result = grep_main();
-                if let Err(err) = result {
-                    eprintln!("{}", err);
-                    std::process::exit(1);
-                }
-            } else {
-                eprintln!("invalid command");
-                std::process::exit(1);
-            }
-        }
-        None => {
-            eprintln!("command is required");
-            std::process::exit(1);
-        }
-    }
+/// cargo run COMMAND [OPTIONS]
+///     COMMAND: unicode, grep, replace, iconv, stats, map, play, fs, dupes, watch, run, tar, completions
+#[derive(Debug, Parser)]
+#[command(version, about = "Input and output samples", long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
 }
 
-fn unicode() -> io::Result<()> {
-    let si = io::stdin();
-    for l in si.lock().lines() {
-        let line = l?;
-        for c in line.chars() {
-            println!("{}: \\u{:x}", &c, c as u32);
-        }
-    }
-
-    Ok(())
+/// サブコマンド
+///
+/// コマンドを追加するときは、ここにバリアントを追加して run で処理する。
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// 文字列の各文字のエンコーディングやカテゴリ、正規化形式などを出力する
+    #[command(about = "Show encodings, categories and normalization forms of each character")]
+    Unicode(unicode::Args),
+    /// grep のサンプル
+    #[command(about = "Search files for lines matching a regular expression")]
+    Grep(grep::Args),
+    /// 正規表現でファイルの内容を置換する
+    #[command(about = "Replace regular expression matches in files")]
+    Replace(replace::Args),
+    /// 文字エンコーディングを変換する
+    #[command(about = "Convert text between character encodings")]
+    Iconv(iconv::Args),
+    /// 行数や単語数などの統計と、よく使われている単語を出力する
+    #[command(about = "Count lines, words, characters and frequent words")]
+    Stats(stats::Args),
+    /// 部屋のマップ (JSON) を調べる
+    #[command(subcommand, about = "Validate room maps and find paths between rooms")]
+    Map(rooms::Args),
+    /// 部屋のマップでテキストアドベンチャーを遊ぶ
+    #[command(about = "Play a text adventure on a room map")]
+    Play(adventure::Args),
+    /// ディレクトリツリーのコピーや同期、比較をする
+    #[command(subcommand, about = "Copy, sync, compare and list directory trees")]
+    Fs(fsutil::Args),
+    /// 同じ内容のファイルを探す
+    #[command(about = "Find files with identical contents")]
+    Dupes(dupes::Args),
+    /// ディレクトリの変更を監視する
+    #[command(about = "Watch a directory and report or act on changes")]
+    Watch(watch::Args),
+    /// コマンドをパイプでつないで実行する
+    #[command(about = "Run a pipeline of commands connected by pipes")]
+    Run(pipeline::Args),
+    /// ustar 形式のアーカイブを作成、展開する
+    #[command(subcommand, about = "Create, extract and list tar archives")]
+    Tar(tar::Args),
+    /// シェルの補完スクリプトを出力する
+    #[command(about = "Print a shell completion script")]
+    Completions {
+        #[arg(help = "Shell to generate the completion script for")]
+        shell: clap_complete::Shell,
+    },
 }
 
-fn grep<R>(target: &str, reader: R) -> io::Result<()>
-where
-    R: BufRead,
-{
-    for line_result in reader.lines() {
-        let line = line_result?;
-        if line.contains(target) {
-            println!("{}", line);
+fn main() {
+    let cli = Cli::parse();
+    let code = match run(cli.command) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("{}", err);
+            EXIT_ERROR
         }
-    }
-    Ok(())
+    };
+    std::process::exit(code);
 }
 
-fn grep_main() -> Result<(), Box<dyn Error>> {
-    let mut args = std::env::args().skip(2);
-    let target = match args.next() {
-        Some(s) => s,
-        None => Err("usage: grep PATTERN FILE ...")?,
-    };
-    let files: Vec<PathBuf> = args.map(PathBuf::from).collect();
-
-    if files.is_empty() {
-        // 第2引数以降が省略されていた場合、標準入力を受け付ける
-        let stdin = io::stdin();
-        grep(&target, stdin.lock())?;
-    } else {
-        for file in files {
-            println!("in {:?} ...", file.file_name());
-            let f = File::open(file)?;
-            grep(&target, BufReader::new(f))?;
+/// サブコマンドを実行し、終了コードを返す
+fn run(command: Command) -> Result<i32, Box<dyn Error>> {
+    match command {
+        Command::Unicode(args) => {
+            unicode::unicode_main(args)?;
+            Ok(EXIT_SUCCESS)
+        }
+        Command::Grep(args) => Ok(grep::grep_main(args.resolve())?.exit_code()),
+        Command::Replace(args) => Ok(replace::replace_main(args)?.exit_code()),
+        Command::Iconv(args) => iconv::iconv_main(args),
+        Command::Stats(args) => stats::stats_main(args),
+        Command::Map(args) => rooms::map_main(args),
+        Command::Play(args) => adventure::play_main(args),
+        Command::Fs(args) => fsutil::fs_main(args),
+        Command::Dupes(args) => dupes::dupes_main(args),
+        Command::Watch(args) => watch::watch_main(args),
+        Command::Run(args) => pipeline::run_main(args),
+        Command::Tar(args) => tar::tar_main(args),
+        Command::Completions { shell } => {
+            let mut cli = Cli::command();
+            let name = cli.get_name().to_string();
+            // generate は書き込みに失敗すると panic するので、一度バッファに出力する
+            let mut script = vec![];
+            clap_complete::generate(shell, &mut cli, name, &mut script);
+            io::stdout().write_all(&script)?;
+            Ok(EXIT_SUCCESS)
         }
     }
-
-    Ok(())
 }
 
 #[test]
@@ -122,6 +161,7 @@ fn test_repeat() {
 }
 
 #[test]
+#[allow(clippy::single_match)]
 fn test_serialize() {
     use std::collections::HashMap;
 
@@ -240,3 +280,40 @@ fn test_file_system() {
     }
     assert_eq!(c, 5);
 }
+
+#[test]
+fn test_cli() {
+    use clap::error::ErrorKind;
+
+    Cli::command().debug_assert();
+
+    let cli = Cli::try_parse_from(["chap18", "grep", "-n", "-C2", "foo", "a.txt"]).unwrap();
+    match cli.command {
+        Command::Grep(args) => {
+            assert!(args.options.line_number);
+            assert_eq!(args.context, Some(2));
+            assert_eq!(args.files, vec![PathBuf::from("a.txt")]);
+        }
+        other => panic!("unexpected command: {:?}", other),
+    }
+    assert!(matches!(
+        Cli::try_parse_from(["chap18", "completions", "bash"])
+            .unwrap()
+            .command,
+        Command::Completions {
+            shell: clap_complete::Shell::Bash
+        }
+    ));
+
+    // 引数の誤りは 2 、ヘルプとバージョンの表示は 0 で終了する
+    let err = Cli::try_parse_from(["chap18", "frobnicate"]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidSubcommand);
+    assert_eq!(err.exit_code(), EXIT_ERROR);
+    let err = Cli::try_parse_from(["chap18", "replace", "foo"]).unwrap_err();
+    assert_eq!(err.exit_code(), EXIT_ERROR);
+    let err = Cli::try_parse_from(["chap18", "grep", "--help"]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::DisplayHelp);
+    assert_eq!(err.exit_code(), EXIT_SUCCESS);
+    let err = Cli::try_parse_from(["chap18", "--version"]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::DisplayVersion);
+}
@@ -0,0 +1,639 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// タイムアウトを待つ間に子プロセスの状態を確かめる間隔
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// タイムアウトで終了させた場合の終了コード (timeout コマンドと同じ)
+pub const EXIT_TIMEOUT: i32 = 124;
+
+/// パイプラインの 1 つのコマンド
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stage {
+    pub program: String,
+    pub args: Vec<String>,
+    /// このコマンドにだけ設定する環境変数
+    pub env: Vec<(String, String)>,
+}
+
+impl Stage {
+    pub fn new(program: &str) -> Stage {
+        Stage {
+            program: program.to_string(),
+            args: vec![],
+            env: vec![],
+        }
+    }
+
+    pub fn arg(mut self, arg: &str) -> Stage {
+        self.args.push(arg.to_string());
+        self
+    }
+
+    pub fn args(mut self, args: &[&str]) -> Stage {
+        self.args.extend(args.iter().map(|a| a.to_string()));
+        self
+    }
+
+    pub fn env(mut self, key: &str, value: &str) -> Stage {
+        self.env.push((key.to_string(), value.to_string()));
+        self
+    }
+}
+
+/// 最初のコマンドの標準入力
+#[derive(Debug, Clone, PartialEq)]
+pub enum Input {
+    /// 親プロセスの標準入力をそのまま使う
+    Inherit,
+    Null,
+    /// 与えたバイト列を書き込む
+    Bytes(Vec<u8>),
+}
+
+/// 標準入出力をパイプでつないだコマンドの列 (`a | b | c`)
+///
+/// std::process::Command と同じように、メソッドをつなげて組み立てる。
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    pub stages: Vec<Stage>,
+    /// すべてのコマンドに設定する環境変数 (Stage の env が優先される)
+    pub env: Vec<(String, String)>,
+    pub stdin: Input,
+    /// 最後のコマンドの標準出力を Output に集めるか (false なら親の標準出力に出す)
+    pub capture_stdout: bool,
+    /// 各コマンドの標準エラー出力を Output に集めるか (false なら親の標準エラー出力に出す)
+    pub capture_stderr: bool,
+    /// これを過ぎても終わらないコマンドは kill する
+    ///
+    /// Unix では、 timeout コマンドと同じくパイプラインを専用のプロセスグループで動かし、
+    /// コマンドが起動した子プロセスもまとめて kill する。
+    /// そのため端末のフォアグラウンドではなくなり、端末から読み込むコマンドは止まってしまう。
+    pub timeout: Option<Duration>,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Pipeline {
+            stages: vec![],
+            env: vec![],
+            stdin: Input::Null,
+            capture_stdout: true,
+            capture_stderr: true,
+            timeout: None,
+        }
+    }
+}
+
+/// 各コマンドの結果
+#[derive(Debug)]
+pub struct StageOutput {
+    pub program: String,
+    pub status: ExitStatus,
+    /// capture_stderr が false なら空
+    pub stderr: Vec<u8>,
+}
+
+/// パイプライン全体の結果
+#[derive(Debug)]
+pub struct Output {
+    /// 最後のコマンドの標準出力 (capture_stdout が false なら空)
+    pub stdout: Vec<u8>,
+    pub stages: Vec<StageOutput>,
+    /// タイムアウトでコマンドを終了させたか
+    pub timed_out: bool,
+}
+
+/// 終了ステータスを終了コードにする (シグナルで終了した場合はシェルと同じく 128 + シグナル番号)
+fn status_code(status: &ExitStatus) -> i32 {
+    if let Some(code) = status.code() {
+        return code;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    1
+}
+
+impl Output {
+    /// すべてのコマンドが成功したか
+    pub fn success(&self) -> bool {
+        !self.timed_out && self.stages.iter().all(|s| s.status.success())
+    }
+
+    /// パイプライン全体の終了コード
+    ///
+    /// bash の pipefail と同じく、失敗したコマンドのうち最も右のものの終了コードになる。
+    /// タイムアウトした場合は EXIT_TIMEOUT 。
+    pub fn exit_code(&self) -> i32 {
+        if self.timed_out {
+            return EXIT_TIMEOUT;
+        }
+        self.stages
+            .iter()
+            .rev()
+            .find(|s| !s.status.success())
+            .map_or(0, |s| status_code(&s.status))
+    }
+}
+
+/// パイプラインの文字列の誤り
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// 引用符が閉じていない
+    UnterminatedQuote(char),
+    /// `|` の前後にコマンドがない
+    EmptyStage,
+    /// 最後の `\` の後に文字がない
+    TrailingBackslash,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::UnterminatedQuote(q) => write!(f, "unterminated {} quote", q),
+            ParseError::EmptyStage => write!(f, "empty command in pipeline"),
+            ParseError::TrailingBackslash => write!(f, "trailing backslash"),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+/// `KEY=VALUE` の形の単語なら、キーと値に分ける
+fn env_assignment(word: &str) -> Option<(String, String)> {
+    let (key, value) = word.split_once('=')?;
+    let mut chars = key.chars();
+    let first = chars.next()?;
+    if (first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        Some((key.to_string(), value.to_string()))
+    } else {
+        None
+    }
+}
+
+impl Pipeline {
+    pub fn new() -> Pipeline {
+        Pipeline::default()
+    }
+
+    /// シェルのような文字列からパイプラインを作る
+    ///
+    /// `|` でコマンドを区切り、空白で引数を区切る。引用符 (`'` と `"`) と `\` によるエスケープ、
+    /// コマンドの前の `KEY=VALUE` による環境変数の設定に対応する。
+    /// 変数の展開やリダイレクトはしない。
+    pub fn parse(s: &str) -> Result<Pipeline, ParseError> {
+        let mut stages: Vec<Vec<String>> = vec![vec![]];
+        // 引用符だけの空の単語も 1 つの単語にするため、 Option で区別する
+        let mut word: Option<String> = None;
+        let mut chars = s.chars();
+
+        fn finish(word: &mut Option<String>, stages: &mut [Vec<String>]) {
+            if let Some(w) = word.take() {
+                stages.last_mut().unwrap().push(w);
+            }
+        }
+
+        while let Some(c) = chars.next() {
+            match c {
+                '|' => {
+                    finish(&mut word, &mut stages);
+                    stages.push(vec![]);
+                }
+                c if c.is_whitespace() => finish(&mut word, &mut stages),
+                '\\' => {
+                    let e = chars.next().ok_or(ParseError::TrailingBackslash)?;
+                    word.get_or_insert_with(String::new).push(e);
+                }
+                '\'' | '"' => {
+                    let w = word.get_or_insert_with(String::new);
+                    loop {
+                        match chars.next() {
+                            None => return Err(ParseError::UnterminatedQuote(c)),
+                            Some(q) if q == c => break,
+                            // ダブルクォートの中では " と \ だけをエスケープできる
+                            Some('\\') if c == '"' => match chars.next() {
+                                Some(e @ ('"' | '\\')) => w.push(e),
+                                Some(e) => {
+                                    w.push('\\');
+                                    w.push(e);
+                                }
+                                None => return Err(ParseError::UnterminatedQuote(c)),
+                            },
+                            Some(q) => w.push(q),
+                        }
+                    }
+                }
+                c => word.get_or_insert_with(String::new).push(c),
+            }
+        }
+        finish(&mut word, &mut stages);
+
+        let mut pipeline = Pipeline::new();
+        for words in stages {
+            let mut words = words.into_iter().peekable();
+            let mut env = vec![];
+            while let Some(assignment) = words.peek().and_then(|w| env_assignment(w)) {
+                env.push(assignment);
+                words.next();
+            }
+            let program = words.next().ok_or(ParseError::EmptyStage)?;
+            pipeline.stages.push(Stage {
+                program,
+                args: words.collect(),
+                env,
+            });
+        }
+        Ok(pipeline)
+    }
+
+    pub fn stage(mut self, stage: Stage) -> Pipeline {
+        self.stages.push(stage);
+        self
+    }
+
+    pub fn env(mut self, key: &str, value: &str) -> Pipeline {
+        self.env.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn stdin(mut self, input: Input) -> Pipeline {
+        self.stdin = input;
+        self
+    }
+
+    pub fn capture_stdout(mut self, capture: bool) -> Pipeline {
+        self.capture_stdout = capture;
+        self
+    }
+
+    pub fn capture_stderr(mut self, capture: bool) -> Pipeline {
+        self.capture_stderr = capture;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Pipeline {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// すべてのコマンドを起動する
+    ///
+    /// 途中で起動に失敗した場合は、それまでに起動したコマンドを終了させる。
+    fn spawn(&self) -> io::Result<Vec<Child>> {
+        let mut children: Vec<Child> = vec![];
+        let last = self.stages.len() - 1;
+        for (i, stage) in self.stages.iter().enumerate() {
+            let stdin = match children.last_mut() {
+                Some(prev) => Stdio::from(prev.stdout.take().unwrap()),
+                None => match self.stdin {
+                    Input::Inherit => Stdio::inherit(),
+                    Input::Null => Stdio::null(),
+                    Input::Bytes(_) => Stdio::piped(),
+                },
+            };
+            let stdout = if i < last || self.capture_stdout {
+                Stdio::piped()
+            } else {
+                Stdio::inherit()
+            };
+            let stderr = if self.capture_stderr {
+                Stdio::piped()
+            } else {
+                Stdio::inherit()
+            };
+            let mut command = Command::new(&stage.program);
+            command
+                .args(&stage.args)
+                .envs(self.env.iter().cloned())
+                .envs(stage.env.iter().cloned())
+                .stdin(stdin)
+                .stdout(stdout)
+                .stderr(stderr);
+            #[cfg(unix)]
+            if self.timeout.is_some() {
+                use std::os::unix::process::CommandExt;
+                // 最初のコマンドが新しいプロセスグループを作り、残りはそこに入る
+                command.process_group(children.first().map_or(0, |c| c.id() as i32));
+            }
+            let spawned = command.spawn();
+            match spawned {
+                Ok(child) => children.push(child),
+                Err(e) => {
+                    for mut child in children {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                    }
+                    return Err(io::Error::new(
+                        e.kind(),
+                        format!("{}: {}", stage.program, e),
+                    ));
+                }
+            }
+        }
+        Ok(children)
+    }
+
+    /// すべてのコマンドの終了を待つ
+    ///
+    /// timeout を過ぎたら残りのコマンドを kill し、タイムアウトしたかどうかも返す。
+    fn wait_all(&self, children: &mut [Child]) -> io::Result<(Vec<ExitStatus>, bool)> {
+        let Some(timeout) = self.timeout else {
+            let statuses = children
+                .iter_mut()
+                .map(Child::wait)
+                .collect::<io::Result<_>>()?;
+            return Ok((statuses, false));
+        };
+
+        let deadline = Instant::now() + timeout;
+        let mut statuses: Vec<Option<ExitStatus>> = vec![None; children.len()];
+        let mut timed_out = false;
+        while statuses.iter().any(Option::is_none) {
+            for (child, status) in children.iter_mut().zip(&mut statuses) {
+                if status.is_none() {
+                    *status = child.try_wait()?;
+                }
+            }
+            if statuses.iter().all(Option::is_some) {
+                break;
+            }
+            if Instant::now() >= deadline {
+                timed_out = true;
+                kill_all(children);
+                for (child, status) in children.iter_mut().zip(&mut statuses) {
+                    if status.is_none() {
+                        *status = Some(child.wait()?);
+                    }
+                }
+                break;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        Ok((
+            statuses.into_iter().map(Option::unwrap).collect(),
+            timed_out,
+        ))
+    }
+
+    /// パイプラインを実行し、すべてのコマンドが終わるまで待つ
+    ///
+    /// 入力の書き込みと出力の読み込みは別のスレッドで行うので、
+    /// パイプのバッファがいっぱいになって止まることはない。
+    pub fn run(&self) -> io::Result<Output> {
+        if self.stages.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                ParseError::EmptyStage,
+            ));
+        }
+        let mut children = self.spawn()?;
+
+        thread::scope(|scope| {
+            let stdin = children[0].stdin.take();
+            if let (Some(mut stdin), Input::Bytes(bytes)) = (stdin, &self.stdin) {
+                // 途中で読むのをやめるコマンドもあるので、書き込みのエラーは無視する
+                scope.spawn(move || {
+                    let _ = stdin.write_all(bytes);
+                });
+            }
+            let read_all = |pipe: Option<Box<dyn Read + Send>>| {
+                scope.spawn(move || {
+                    let mut buf = vec![];
+                    if let Some(mut pipe) = pipe {
+                        pipe.read_to_end(&mut buf)?;
+                    }
+                    Ok::<_, io::Error>(buf)
+                })
+            };
+            let stderr_readers: Vec<_> = children
+                .iter_mut()
+                .map(|c| read_all(c.stderr.take().map(|p| Box::new(p) as Box<dyn Read + Send>)))
+                .collect();
+            let stdout = children
+                .last_mut()
+                .unwrap()
+                .stdout
+                .take()
+                .map(|p| Box::new(p) as Box<dyn Read + Send>);
+            let stdout_reader = read_all(stdout);
+
+            let (statuses, timed_out) = self.wait_all(&mut children)?;
+            let stdout = stdout_reader.join().unwrap()?;
+            let mut stages = vec![];
+            for ((stage, status), reader) in self.stages.iter().zip(statuses).zip(stderr_readers) {
+                stages.push(StageOutput {
+                    program: stage.program.clone(),
+                    status,
+                    stderr: reader.join().unwrap()?,
+                });
+            }
+            Ok(Output {
+                stdout,
+                stages,
+                timed_out,
+            })
+        })
+    }
+}
+
+/// タイムアウトしたパイプラインを終了させる
+///
+/// Unix ではプロセスグループごと kill するので、 `sh -c 'sleep 100'` の sleep のように
+/// コマンドが起動した子プロセスも終わり、出力のパイプが閉じられる。
+#[cfg(unix)]
+fn kill_all(children: &mut [Child]) {
+    let group = children[0].id() as libc::pid_t;
+    // SAFETY: kill はシグナルを送るだけで、このプロセスのメモリには触れない
+    unsafe {
+        libc::kill(-group, libc::SIGKILL);
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_all(children: &mut [Child]) {
+    for child in children {
+        let _ = child.kill();
+    }
+}
+
+/// `KEY=VALUE` の形の引数を解釈する
+fn parse_env(s: &str) -> Result<(String, String), String> {
+    env_assignment(s).ok_or_else(|| format!("expected KEY=VALUE, got {:?}", s))
+}
+
+/// run のコマンドライン引数
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    #[arg(
+        long,
+        value_name = "SECS",
+        help = "Kill the pipeline if it runs longer than SECS seconds"
+    )]
+    pub timeout: Option<f64>,
+    #[arg(
+        short = 'e',
+        long = "env",
+        value_name = "KEY=VALUE",
+        value_parser = parse_env,
+        help = "Set an environment variable for every command"
+    )]
+    pub env: Vec<(String, String)>,
+    #[arg(help = "Pipeline to run, e.g. \"ls | grep rs | wc -l\"")]
+    pub pipeline: String,
+}
+
+/// run コマンドのエントリポイント
+///
+/// 標準入出力はそのまま子プロセスに渡し、パイプライン全体の終了コードを返す。
+pub fn run_main(args: Args) -> Result<i32, Box<dyn Error>> {
+    let mut pipeline = Pipeline::parse(&args.pipeline)?
+        .stdin(Input::Inherit)
+        .capture_stdout(false)
+        .capture_stderr(false);
+    for (key, value) in &args.env {
+        pipeline = pipeline.env(key, value);
+    }
+    if let Some(secs) = args.timeout {
+        pipeline = pipeline.timeout(Duration::try_from_secs_f64(secs)?);
+    }
+
+    let output = pipeline.run()?;
+    if output.timed_out {
+        eprintln!("run: timed out");
+    }
+    for (i, stage) in output.stages.iter().enumerate() {
+        if !stage.status.success() {
+            eprintln!("run: stage {} ({}): {}", i + 1, stage.program, stage.status);
+        }
+    }
+    Ok(output.exit_code())
+}
+
+#[test]
+fn test_parse() {
+    let p = Pipeline::parse(r#"LANG=C grep -e 'a b' | tr "\"x\\" \y|wc  -l"#).unwrap();
+    assert_eq!(
+        p.stages,
+        vec![
+            Stage::new("grep").args(&["-e", "a b"]).env("LANG", "C"),
+            Stage::new("tr").args(&["\"x\\", "y"]),
+            Stage::new("wc").arg("-l"),
+        ]
+    );
+    assert_eq!(
+        Pipeline::parse("echo '' a=b").unwrap().stages,
+        vec![Stage::new("echo").args(&["", "a=b"])]
+    );
+    assert_eq!(
+        Pipeline::parse("echo 'oops").unwrap_err(),
+        ParseError::UnterminatedQuote('\'')
+    );
+    assert_eq!(
+        Pipeline::parse("echo a || wc").unwrap_err(),
+        ParseError::EmptyStage
+    );
+    assert_eq!(Pipeline::parse("").unwrap_err(), ParseError::EmptyStage);
+    assert_eq!(Pipeline::parse("A=1").unwrap_err(), ParseError::EmptyStage);
+    assert_eq!(
+        Pipeline::parse("echo a\\").unwrap_err(),
+        ParseError::TrailingBackslash
+    );
+}
+
+#[test]
+fn test_run() {
+    // test_process_command の grep の後に、さらにコマンドをつなぐ
+    let output = Pipeline::parse("grep -e o | tr a-z A-Z | sort -r")
+        .unwrap()
+        .stdin(Input::Bytes(b"hello\napple\nchildren\nworld\n".to_vec()))
+        .run()
+        .unwrap();
+    assert!(output.success());
+    assert_eq!(output.exit_code(), 0);
+    assert_eq!(output.stdout, b"WORLD\nHELLO\n");
+    assert_eq!(output.stages.len(), 3);
+
+    // パイプのバッファより大きい入力でも止まらない
+    let input = vec![b'x'; 1 << 20];
+    let output = Pipeline::new()
+        .stage(Stage::new("cat"))
+        .stage(Stage::new("wc").arg("-c"))
+        .stdin(Input::Bytes(input))
+        .run()
+        .unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap().trim(), "1048576");
+}
+
+#[test]
+fn test_run_status_and_env() {
+    let output =
+        Pipeline::parse("sh -c 'echo $GREETING $NAME; echo oops >&2; exit 3' | NAME=stage cat")
+            .unwrap()
+            .env("GREETING", "hi")
+            .env("NAME", "all")
+            .run()
+            .unwrap();
+    assert_eq!(output.stdout, b"hi all\n");
+    assert_eq!(output.stages[0].stderr, b"oops\n");
+    assert!(output.stages[1].stderr.is_empty());
+    // 最後のコマンドが成功しても、途中の失敗が終了コードになる
+    assert!(!output.success());
+    assert_eq!(output.exit_code(), 3);
+
+    let output = Pipeline::parse("sh -c 'exit 2' | sh -c 'exit 5' | true")
+        .unwrap()
+        .run()
+        .unwrap();
+    assert_eq!(output.exit_code(), 5);
+
+    let err = Pipeline::parse("cat | no-such-command-xyz")
+        .unwrap()
+        .run()
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    assert!(err.to_string().starts_with("no-such-command-xyz: "));
+}
+
+#[test]
+fn test_run_timeout() {
+    let start = Instant::now();
+    let output = Pipeline::parse("sleep 10 | cat")
+        .unwrap()
+        .timeout(Duration::from_millis(100))
+        .run()
+        .unwrap();
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert!(output.timed_out);
+    assert_eq!(output.exit_code(), EXIT_TIMEOUT);
+
+    // コマンドが起動した子プロセスが出力のパイプを持っていても待ち続けない
+    let start = Instant::now();
+    let output = Pipeline::parse("sh -c 'sleep 10; echo late' | cat")
+        .unwrap()
+        .timeout(Duration::from_millis(100))
+        .run()
+        .unwrap();
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert!(output.timed_out);
+    assert!(output.stdout.is_empty());
+
+    let output = Pipeline::parse("echo fast")
+        .unwrap()
+        .timeout(Duration::from_secs(10))
+        .run()
+        .unwrap();
+    assert!(!output.timed_out);
+    assert_eq!(output.stdout, b"fast\n");
+}
@@ -0,0 +1,365 @@
+use regex::bytes::{Regex, RegexBuilder};
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use crate::diff;
+use crate::fsutil::plural;
+use crate::grep;
+use crate::walk::{self, WalkOptions};
+
+/// --dry-run で出力する差分の前後の行数
+const DIFF_CONTEXT: usize = 3;
+
+/// replace の動作を切り替えるオプション
+#[derive(Debug, Default, Clone, PartialEq, clap::Args)]
+pub struct Options {
+    /// -i: 大文字と小文字を区別しない
+    #[arg(short = 'i', long, help = "Ignore case distinctions")]
+    pub ignore_case: bool,
+    /// --dry-run: ファイルは書き換えずに、変更内容を unified diff で出力する
+    #[arg(long, help = "Print a unified diff instead of writing the files")]
+    pub dry_run: bool,
+}
+
+/// replace のコマンドライン引数
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    #[command(flatten)]
+    pub options: Options,
+    /// -r: ディレクトリを再帰的に処理する
+    #[arg(short = 'r', long, help = "Process directories recursively")]
+    pub recursive: bool,
+    /// -r で辿るファイルの絞り込み
+    #[command(flatten)]
+    pub walk: WalkOptions,
+    #[arg(help = "Regular expression to replace")]
+    pub pattern: String,
+    /// 置換後の文字列 (`$1` や `${name}` でキャプチャグループを参照できる)
+    #[arg(help = "Replacement text; $1 or ${name} refers to a capture group")]
+    pub replacement: String,
+    #[arg(required = true, help = "Files to rewrite")]
+    pub files: Vec<PathBuf>,
+}
+
+/// replace のコマンドライン引数を解釈する (args にコマンド名は含めない)
+pub fn parse_args<I>(args: I) -> Result<Args, clap::Error>
+where
+    I: IntoIterator<Item = String>,
+{
+    use clap::{Args as _, FromArgMatches};
+
+    let command = Args::augment_args(clap::Command::new("replace"));
+    let matches =
+        command.try_get_matches_from(std::iter::once("replace".to_string()).chain(args))?;
+    Args::from_arg_matches(&matches)
+}
+
+/// オプションに合わせてパターンをコンパイルする
+pub fn build_regex(pattern: &str, opts: &Options) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(opts.ignore_case)
+        .build()
+}
+
+/// content の中で re にマッチする箇所をすべて replacement に置き換える
+///
+/// 返り値は置換後の内容と置換した箇所の数。
+/// replacement の `$1` や `${name}` は、対応するキャプチャグループの内容になる。
+pub fn replace_all(re: &Regex, replacement: &[u8], content: &[u8]) -> (Vec<u8>, usize) {
+    let mut result = Vec::with_capacity(content.len());
+    let mut last = 0;
+    let mut count = 0;
+    for caps in re.captures_iter(content) {
+        let m = caps.get(0).unwrap();
+        result.extend_from_slice(&content[last..m.start()]);
+        caps.expand(replacement, &mut result);
+        last = m.end();
+        count += 1;
+    }
+    result.extend_from_slice(&content[last..]);
+    (result, count)
+}
+
+/// path の内容を content に置き換える
+///
+/// 同じディレクトリの一時ファイルに書き込んでから rename するので、
+/// 途中で失敗しても元のファイルが中途半端な内容になることはない。
+/// 一時ファイルには元のファイルのパーミッションを設定しておく。
+pub fn write_atomic(path: &Path, content: &[u8]) -> io::Result<()> {
+    // シンボリックリンクの場合は、リンク先のファイルを書き換える
+    let path = fs::canonicalize(path)?;
+    let permissions = fs::metadata(&path)?.permissions();
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp_path = dir.join(format!(".{}.{}.tmp", file_name, std::process::id()));
+
+    let result = (|| {
+        let mut tmp = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp_path)?;
+        tmp.write_all(content)?;
+        tmp.set_permissions(permissions)?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// 1 つのファイルの内容を置換する
+///
+/// 返り値は置換した箇所の数。バイナリファイルは置換せずに 0 を返す。
+/// dry_run の場合はファイルを書き換えずに、変更内容を unified diff で out に出力する。
+pub fn replace_file<W: Write>(
+    re: &Regex,
+    replacement: &[u8],
+    path: &Path,
+    opts: &Options,
+    out: &mut W,
+) -> io::Result<usize> {
+    if path.is_dir() {
+        return Err(io::Error::other("Is a directory"));
+    }
+    let content = fs::read(path)?;
+    if grep::looks_binary(&content) {
+        return Ok(0);
+    }
+    let (replaced, count) = replace_all(re, replacement, &content);
+    if count == 0 {
+        return Ok(0);
+    }
+
+    if opts.dry_run {
+        let name = path.to_string_lossy();
+        diff::write_unified(out, &name, &name, &content, &replaced, DIFF_CONTEXT)?;
+    } else {
+        write_atomic(path, &replaced)?;
+    }
+    Ok(count)
+}
+
+/// 置換全体の結果
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    /// 置換した (--dry-run の場合は置換する) ファイルの数
+    pub files_changed: usize,
+    /// 置換した箇所の数
+    pub replacements: usize,
+    /// 処理できなかったファイルがあったか
+    pub errors: bool,
+}
+
+impl Summary {
+    /// grep と同じ終了コード (置換すれば 0 、しなければ 1 、エラーがあれば 2)
+    pub fn exit_code(&self) -> i32 {
+        if self.errors {
+            2
+        } else if self.replacements > 0 {
+            0
+        } else {
+            1
+        }
+    }
+}
+
+/// args.files のファイルを順に置換する
+///
+/// 置換したファイルごとに `path: N replacements` を out に出力する (--dry-run では差分を出力する)。
+/// 処理できないファイルがあっても、エラーを err に出力して残りのファイルの処理を続ける。
+pub fn replace_files<W, E>(re: &Regex, args: &Args, out: &mut W, err: &mut E) -> io::Result<Summary>
+where
+    W: Write,
+    E: Write,
+{
+    let replacement = args.replacement.as_bytes();
+    let mut summary = Summary::default();
+
+    for file in &args.files {
+        let targets = if args.recursive {
+            walk::walk(file, &args.walk)
+        } else {
+            vec![Ok(file.clone())]
+        };
+        for target in targets {
+            let result = target.and_then(|path| {
+                match replace_file(re, replacement, &path, &args.options, out) {
+                    Ok(count) => Ok((path, count)),
+                    Err(error) => Err(walk::PathError { path, error }),
+                }
+            });
+            match result {
+                Ok((_, 0)) => {}
+                Ok((path, count)) => {
+                    summary.files_changed += 1;
+                    summary.replacements += count;
+                    if !args.options.dry_run {
+                        let replacements = plural(count as u64, "replacement", "replacements");
+                        writeln!(out, "{}: {}", path.display(), replacements)?;
+                    }
+                }
+                Err(e) => {
+                    writeln!(err, "replace: {}", e)?;
+                    summary.errors = true;
+                }
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+/// replace コマンドのエントリポイント
+pub fn replace_main(args: Args) -> Result<Summary, Box<dyn Error>> {
+    let re = build_regex(&args.pattern, &args.options)?;
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    Ok(replace_files(&re, &args, &mut out, &mut io::stderr())?)
+}
+
+#[test]
+fn test_replace_all() {
+    let re = Regex::new(r"(\w+)@(?P<host>\w+)").unwrap();
+    let (result, count) = replace_all(&re, b"${host}:$1", b"alice@example, bob@test.");
+    assert_eq!(result, b"example:alice, test:bob.");
+    assert_eq!(count, 2);
+
+    let (result, count) = replace_all(&re, b"x", b"nothing here");
+    assert_eq!(result, b"nothing here");
+    assert_eq!(count, 0);
+
+    // UTF-8 でない部分はそのまま残す
+    let re = Regex::new("a").unwrap();
+    let (result, _) = replace_all(&re, b"b", b"\xffa\xfe");
+    assert_eq!(result, b"\xffb\xfe");
+}
+
+#[test]
+fn test_parse_args() {
+    let to_args = |s: &str| s.split_whitespace().map(String::from).collect::<Vec<_>>();
+
+    let args = parse_args(to_args("-ir --dry-run --include=*.rs foo bar src")).unwrap();
+    assert_eq!(
+        args.options,
+        Options {
+            ignore_case: true,
+            dry_run: true,
+        }
+    );
+    assert!(args.recursive);
+    assert_eq!(args.walk.include.len(), 1);
+    assert_eq!(args.pattern, "foo");
+    assert_eq!(args.replacement, "bar");
+    assert_eq!(args.files, vec![PathBuf::from("src")]);
+
+    assert!(parse_args(to_args("foo bar")).is_err());
+    assert!(parse_args(to_args("-x foo bar a")).is_err());
+    assert!(parse_args(to_args("--force foo bar a")).is_err());
+}
+
+#[test]
+fn test_replace_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let a = dir.path().join("a.txt");
+    let b = dir.path().join("b.txt");
+    fs::write(&a, "one\ntwo\nthree\n").unwrap();
+    fs::write(&b, "none\n").unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&a, fs::Permissions::from_mode(0o640)).unwrap();
+    }
+
+    let to_args = |extra: &[&str]| -> Args {
+        let mut v: Vec<String> = extra.iter().map(|s| s.to_string()).collect();
+        v.push("t(\\w)".to_string());
+        v.push("T${1}$1".to_string());
+        v.push(a.to_string_lossy().to_string());
+        v.push(b.to_string_lossy().to_string());
+        v.push(dir.path().join("missing").to_string_lossy().to_string());
+        parse_args(v).unwrap()
+    };
+
+    // --dry-run ではファイルを書き換えずに差分を出力する
+    let args = to_args(&["--dry-run"]);
+    let re = build_regex(&args.pattern, &args.options).unwrap();
+    let mut out = vec![];
+    let mut err = vec![];
+    let summary = replace_files(&re, &args, &mut out, &mut err).unwrap();
+    let name = a.display();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        format!(
+            "--- {name}\n+++ {name}\n@@ -1,3 +1,3 @@\n one\n-two\n-three\n+Twwo\n+Thhree\n",
+            name = name
+        )
+    );
+    assert!(String::from_utf8(err).unwrap().starts_with("replace: "));
+    assert_eq!(
+        summary,
+        Summary {
+            files_changed: 1,
+            replacements: 2,
+            errors: true,
+        }
+    );
+    assert_eq!(fs::read_to_string(&a).unwrap(), "one\ntwo\nthree\n");
+
+    let args = to_args(&[]);
+    let mut out = vec![];
+    let mut err = vec![];
+    let summary = replace_files(&re, &args, &mut out, &mut err).unwrap();
+    assert_eq!(summary.exit_code(), 2);
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        format!("{}: 2 replacements\n", name)
+    );
+    assert_eq!(fs::read_to_string(&a).unwrap(), "one\nTwwo\nThhree\n");
+    assert_eq!(fs::read_to_string(&b).unwrap(), "none\n");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(&a).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+    }
+
+    // 一時ファイルは残らない
+    let mut names: Vec<_> = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|e| e.unwrap().file_name())
+        .collect();
+    names.sort();
+    assert_eq!(names, vec!["a.txt", "b.txt"]);
+
+    fs::write(&b, "to\n").unwrap();
+    let mut out = vec![];
+    replace_files(&re, &args, &mut out, &mut io::sink()).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        format!("{}: 1 replacement\n", b.display())
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_write_atomic_symlink() {
+    let dir = tempfile::tempdir().unwrap();
+    let target = dir.path().join("target.txt");
+    let link = dir.path().join("link.txt");
+    fs::write(&target, "old").unwrap();
+    std::os::unix::fs::symlink(&target, &link).unwrap();
+
+    write_atomic(&link, b"new").unwrap();
+    // リンクはそのままで、リンク先の内容が変わる
+    assert!(fs::symlink_metadata(&link)
+        .unwrap()
+        .file_type()
+        .is_symlink());
+    assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+}
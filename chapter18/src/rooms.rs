@@ -0,0 +1,443 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+/// 部屋の名前
+pub type RoomId = String;
+/// 部屋の出口 (方向と行き先の部屋)
+pub type RoomExits = Vec<(char, RoomId)>;
+/// 部屋ごとの出口
+///
+/// JSON では `{"Room 1": [["A", "Room 2"]], ...}` の形式になる。
+pub type RoomMap = HashMap<RoomId, RoomExits>;
+
+/// マップの誤り
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Problem {
+    /// 出口の行き先の部屋が存在しない
+    UnknownTarget {
+        room: RoomId,
+        direction: char,
+        target: RoomId,
+    },
+    /// 同じ部屋に同じ方向の出口が複数ある
+    DuplicateExit { room: RoomId, direction: char },
+    /// 存在しない部屋を参照している (開始位置や、アイテムの置き場所など)
+    UnknownRoom { room: RoomId },
+    /// 鍵のかかった出口が存在しない
+    LockWithoutExit { room: RoomId, direction: char },
+}
+
+impl Problem {
+    /// 誤りのある部屋
+    pub fn room(&self) -> &RoomId {
+        match self {
+            Problem::UnknownTarget { room, .. }
+            | Problem::DuplicateExit { room, .. }
+            | Problem::UnknownRoom { room }
+            | Problem::LockWithoutExit { room, .. } => room,
+        }
+    }
+}
+
+/// 誤りを部屋の名前順に並べ (同じ部屋の中では種類の順) 、重複を取り除く
+pub fn sort_problems(problems: &mut Vec<Problem>) {
+    problems.sort_by(|a, b| a.room().cmp(b.room()).then_with(|| a.cmp(b)));
+    problems.dedup();
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::UnknownTarget {
+                room,
+                direction,
+                target,
+            } => write!(
+                f,
+                "exit {:?} of room {:?} leads to unknown room {:?}",
+                direction, room, target
+            ),
+            Problem::DuplicateExit { room, direction } => {
+                write!(f, "room {:?} has more than one exit {:?}", room, direction)
+            }
+            Problem::UnknownRoom { room } => write!(f, "unknown room {:?}", room),
+            Problem::LockWithoutExit { room, direction } => {
+                write!(f, "room {:?} has a lock but no exit {:?}", room, direction)
+            }
+        }
+    }
+}
+
+/// マップの読み込みや保存の失敗
+#[derive(Debug)]
+pub enum MapError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Invalid(Vec<Problem>),
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapError::Io(e) => write!(f, "{}", e),
+            MapError::Json(e) => write!(f, "invalid map: {}", e),
+            MapError::Invalid(problems) => {
+                write!(f, "invalid map:")?;
+                for p in problems {
+                    write!(f, "\n  {}", p)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for MapError {}
+
+impl From<io::Error> for MapError {
+    fn from(e: io::Error) -> Self {
+        MapError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for MapError {
+    fn from(e: serde_json::Error) -> Self {
+        MapError::Json(e)
+    }
+}
+
+/// マップの誤りを部屋の名前順に列挙する
+pub fn validate(map: &RoomMap) -> Vec<Problem> {
+    let mut problems = vec![];
+    for (room, exits) in map {
+        let mut seen = BTreeSet::new();
+        for (direction, target) in exits {
+            if !seen.insert(*direction) {
+                problems.push(Problem::DuplicateExit {
+                    room: room.clone(),
+                    direction: *direction,
+                });
+            }
+            if !map.contains_key(target) {
+                problems.push(Problem::UnknownTarget {
+                    room: room.clone(),
+                    direction: *direction,
+                    target: target.clone(),
+                });
+            }
+        }
+    }
+    sort_problems(&mut problems);
+    problems
+}
+
+/// JSON からマップを読み込み、誤りがないか確かめる
+pub fn load<R: Read>(reader: R) -> Result<RoomMap, MapError> {
+    let map: RoomMap = serde_json::from_reader(reader)?;
+    let problems = validate(&map);
+    if !problems.is_empty() {
+        return Err(MapError::Invalid(problems));
+    }
+    Ok(map)
+}
+
+/// マップを JSON で書き出す
+///
+/// HashMap の順序は実行ごとに変わるので、部屋の名前順に並べてから書き出す。
+pub fn save<W: Write>(map: &RoomMap, writer: W) -> Result<(), MapError> {
+    serde_json::to_writer_pretty(writer, &Sorted(map))?;
+    Ok(())
+}
+
+/// HashMap をキーの順に並べてシリアライズする
+///
+/// `#[serde(serialize_with = "rooms::sorted")]` のように使う。
+pub fn sorted<K, V, S>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    K: Ord + serde::Serialize,
+    V: serde::Serialize,
+    S: serde::Serializer,
+{
+    serializer.collect_map(map.iter().collect::<BTreeMap<_, _>>())
+}
+
+/// キーの順に並べてシリアライズされる HashMap
+struct Sorted<'a>(&'a RoomMap);
+
+impl serde::Serialize for Sorted<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        sorted(self.0, serializer)
+    }
+}
+
+/// ファイルからマップを読み込む
+pub fn load_file(path: &Path) -> Result<RoomMap, MapError> {
+    load(BufReader::new(File::open(path)?))
+}
+
+/// マップをファイルに保存する
+pub fn save_file(map: &RoomMap, path: &Path) -> Result<(), MapError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    save(map, &mut writer)?;
+    writeln!(writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// start から辿り着ける部屋 (start を含む)
+pub fn reachable<'a>(map: &'a RoomMap, start: &str) -> BTreeSet<&'a str> {
+    let mut seen = BTreeSet::new();
+    let Some((start, _)) = map.get_key_value(start) else {
+        return seen;
+    };
+    seen.insert(start.as_str());
+    let mut queue = VecDeque::from([start.as_str()]);
+    while let Some(room) = queue.pop_front() {
+        for (_, target) in &map[room] {
+            if map.contains_key(target) && seen.insert(target.as_str()) {
+                queue.push_back(target.as_str());
+            }
+        }
+    }
+    seen
+}
+
+/// from から to までの最短の道順 (通る出口の方向と、その先の部屋の列)
+///
+/// 同じ長さの道順が複数あれば、各部屋の出口の並び順で先に見つかったものを返す。
+/// 辿り着けなければ None 、 from と to が同じなら空の道順を返す。
+pub fn shortest_path<'a>(map: &'a RoomMap, from: &str, to: &str) -> Option<Vec<(char, &'a str)>> {
+    let (from, _) = map.get_key_value(from)?;
+    // 各部屋に最初に辿り着いたときの、直前の部屋と方向
+    let mut previous: HashMap<&str, (&str, char)> = HashMap::new();
+    let mut queue = VecDeque::from([from.as_str()]);
+    let mut found = from == to;
+    while let Some(room) = queue.pop_front() {
+        if found {
+            break;
+        }
+        for (direction, target) in &map[room] {
+            let target = target.as_str();
+            if target == from || previous.contains_key(target) || !map.contains_key(target) {
+                continue;
+            }
+            previous.insert(target, (room, *direction));
+            if target == to {
+                found = true;
+                break;
+            }
+            queue.push_back(target);
+        }
+    }
+    if !found {
+        return None;
+    }
+
+    let mut path = vec![];
+    let mut room = map.get_key_value(to)?.0.as_str();
+    while let Some(&(prev, direction)) = previous.get(room) {
+        path.push((direction, room));
+        room = prev;
+    }
+    path.reverse();
+    Some(path)
+}
+
+/// 出口のない (入ったら出られない) 部屋を名前順に返す
+pub fn dead_ends(map: &RoomMap) -> Vec<&str> {
+    let mut rooms: Vec<&str> = map
+        .iter()
+        .filter(|(_, exits)| exits.iter().all(|(_, target)| !map.contains_key(target)))
+        .map(|(room, _)| room.as_str())
+        .collect();
+    rooms.sort();
+    rooms
+}
+
+/// start から辿り着けない部屋を名前順に返す
+pub fn unreachable<'a>(map: &'a RoomMap, start: &str) -> Vec<&'a str> {
+    let reachable = reachable(map, start);
+    let mut rooms: Vec<&str> = map
+        .keys()
+        .map(String::as_str)
+        .filter(|room| !reachable.contains(room))
+        .collect();
+    rooms.sort();
+    rooms
+}
+
+/// map のコマンドライン引数
+#[derive(Debug, clap::Subcommand)]
+pub enum Args {
+    /// マップの誤りと、行き止まりや辿り着けない部屋を報告する
+    #[command(about = "Validate a map and report dead ends and unreachable rooms")]
+    Check {
+        #[arg(help = "Map file (JSON)")]
+        file: PathBuf,
+        #[arg(long, value_name = "ROOM", help = "Report rooms unreachable from ROOM")]
+        start: Option<String>,
+    },
+    /// 2 つの部屋の間の最短の道順を出力する
+    #[command(about = "Print the shortest path between two rooms")]
+    Path {
+        #[arg(help = "Map file (JSON)")]
+        file: PathBuf,
+        from: String,
+        to: String,
+    },
+}
+
+/// map コマンドのエントリポイント
+///
+/// 終了コードを返す (行き止まりや辿り着けない部屋、道順がない場合は 1)。
+pub fn map_main(args: Args) -> Result<i32, Box<dyn Error>> {
+    match args {
+        Args::Check { file, start } => {
+            let map = load_file(&file)?;
+            if let Some(start) = &start {
+                if !map.contains_key(start) {
+                    return Err(format!("unknown room {:?}", start).into());
+                }
+            }
+            println!("{} rooms", map.len());
+            let dead_ends = dead_ends(&map);
+            for room in &dead_ends {
+                println!("dead end: {}", room);
+            }
+            let unreachable = start.map_or(vec![], |start| unreachable(&map, &start));
+            for room in &unreachable {
+                println!("unreachable: {}", room);
+            }
+            Ok(if dead_ends.is_empty() && unreachable.is_empty() {
+                0
+            } else {
+                1
+            })
+        }
+        Args::Path { file, from, to } => {
+            let map = load_file(&file)?;
+            for room in [&from, &to] {
+                if !map.contains_key(room) {
+                    return Err(format!("unknown room {:?}", room).into());
+                }
+            }
+            match shortest_path(&map, &from, &to) {
+                Some(path) => {
+                    println!("{}", from);
+                    for (direction, room) in path {
+                        println!("{} -> {}", direction, room);
+                    }
+                    Ok(0)
+                }
+                None => {
+                    println!("no path from {} to {}", from, to);
+                    Ok(1)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn sample_map() -> RoomMap {
+    // Hall <-> Kitchen -> Cellar (出口なし)
+    //   \-> Library -> Hall
+    // Attic -> Hall (どこからも来られない)
+    let rooms: &[(&str, &[(char, &str)])] = &[
+        ("Hall", &[('N', "Kitchen"), ('E', "Library")]),
+        ("Kitchen", &[('S', "Hall"), ('D', "Cellar")]),
+        ("Library", &[('W', "Hall")]),
+        ("Cellar", &[]),
+        ("Attic", &[('D', "Hall")]),
+    ];
+    rooms
+        .iter()
+        .map(|(room, exits)| {
+            let exits = exits.iter().map(|(d, t)| (*d, t.to_string())).collect();
+            (room.to_string(), exits)
+        })
+        .collect()
+}
+
+#[test]
+fn test_load_and_save() {
+    let map = sample_map();
+    let mut json = vec![];
+    save(&map, &mut json).unwrap();
+    let text = String::from_utf8(json.clone()).unwrap();
+    // 部屋の名前順に並ぶ
+    assert!(text.find("Attic").unwrap() < text.find("Cellar").unwrap());
+    assert_eq!(load(&json[..]).unwrap(), map);
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("map.json");
+    save_file(&map, &path).unwrap();
+    assert_eq!(load_file(&path).unwrap(), map);
+
+    assert!(matches!(load(&b"{\"A\": 1}"[..]), Err(MapError::Json(_))));
+    assert!(matches!(
+        load_file(&dir.path().join("missing.json")),
+        Err(MapError::Io(_))
+    ));
+}
+
+#[test]
+fn test_validate() {
+    let json = r#"{"A": [["N", "B"], ["N", "A"]], "B": [["S", "Nowhere"]]}"#;
+    match load(json.as_bytes()) {
+        Err(MapError::Invalid(problems)) => assert_eq!(
+            problems,
+            vec![
+                Problem::DuplicateExit {
+                    room: "A".to_string(),
+                    direction: 'N'
+                },
+                Problem::UnknownTarget {
+                    room: "B".to_string(),
+                    direction: 'S',
+                    target: "Nowhere".to_string()
+                },
+            ]
+        ),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(validate(&sample_map()).is_empty());
+}
+
+#[test]
+fn test_reachability() {
+    let map = sample_map();
+    assert_eq!(
+        reachable(&map, "Hall").into_iter().collect::<Vec<_>>(),
+        vec!["Cellar", "Hall", "Kitchen", "Library"]
+    );
+    assert_eq!(
+        reachable(&map, "Cellar").into_iter().collect::<Vec<_>>(),
+        vec!["Cellar"]
+    );
+    assert!(reachable(&map, "Nowhere").is_empty());
+    assert_eq!(unreachable(&map, "Hall"), vec!["Attic"]);
+    assert_eq!(dead_ends(&map), vec!["Cellar"]);
+}
+
+#[test]
+fn test_shortest_path() {
+    let map = sample_map();
+    assert_eq!(
+        shortest_path(&map, "Attic", "Cellar"),
+        Some(vec![('D', "Hall"), ('N', "Kitchen"), ('D', "Cellar")])
+    );
+    assert_eq!(
+        shortest_path(&map, "Library", "Kitchen"),
+        Some(vec![('W', "Hall"), ('N', "Kitchen")])
+    );
+    assert_eq!(shortest_path(&map, "Hall", "Hall"), Some(vec![]));
+    assert_eq!(shortest_path(&map, "Cellar", "Hall"), None);
+    assert_eq!(shortest_path(&map, "Hall", "Attic"), None);
+    assert_eq!(shortest_path(&map, "Nowhere", "Hall"), None);
+}
@@ -0,0 +1,325 @@
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::PathBuf;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::grep::STDIN_NAME;
+use crate::walk::PathError;
+
+/// stats のコマンドライン引数
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 10,
+        help = "Number of most frequent words to show"
+    )]
+    pub top: usize,
+    #[arg(long, help = "Print the statistics as JSON")]
+    pub json: bool,
+    #[arg(help = "Files to read (standard input if omitted)")]
+    pub files: Vec<PathBuf>,
+}
+
+/// テキストの統計
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Stats {
+    /// 改行の数 (wc と同じく、最後の行に改行がなければ数えない)
+    pub lines: usize,
+    /// 空白で区切られた単語の数
+    pub words: usize,
+    pub bytes: usize,
+    /// 文字 (コードポイント) の数 (UTF-8 として不正なバイト列は 1 文字と数える)
+    pub chars: usize,
+    /// 書記素クラスタの数
+    pub graphemes: usize,
+    /// 最も長い行の文字数
+    pub longest_line: usize,
+    /// 単語ごとの出現回数 (句読点を除いて小文字にしたもの)
+    #[serde(skip)]
+    pub word_counts: HashMap<String, usize>,
+}
+
+impl Stats {
+    /// data の統計をとる
+    pub fn from_bytes(data: &[u8]) -> Stats {
+        let text = String::from_utf8_lossy(data);
+        let mut word_counts = HashMap::new();
+        for word in text.unicode_words() {
+            *word_counts.entry(word.to_lowercase()).or_insert(0) += 1;
+        }
+
+        Stats {
+            lines: memchr::memchr_iter(b'\n', data).count(),
+            words: text.split_whitespace().count(),
+            bytes: data.len(),
+            chars: text.chars().count(),
+            graphemes: text.graphemes(true).count(),
+            longest_line: text
+                .lines()
+                .map(|line| line.chars().count())
+                .max()
+                .unwrap_or(0),
+            word_counts,
+        }
+    }
+
+    /// 他のテキストの統計を足し合わせる
+    pub fn merge(&mut self, other: &Stats) {
+        self.lines += other.lines;
+        self.words += other.words;
+        self.bytes += other.bytes;
+        self.chars += other.chars;
+        self.graphemes += other.graphemes;
+        self.longest_line = self.longest_line.max(other.longest_line);
+        for (word, count) in &other.word_counts {
+            *self.word_counts.entry(word.clone()).or_insert(0) += count;
+        }
+    }
+
+    /// 出現回数の多い順に n 個の単語を返す (回数が同じなら辞書順)
+    pub fn top_words(&self, n: usize) -> Vec<WordCount<'_>> {
+        let mut words: Vec<WordCount> = self
+            .word_counts
+            .iter()
+            .map(|(word, &count)| WordCount { word, count })
+            .collect();
+        words.sort_by(|a, b| b.count.cmp(&a.count).then(a.word.cmp(b.word)));
+        words.truncate(n);
+        words
+    }
+}
+
+/// 単語とその出現回数
+#[derive(Debug, PartialEq, Serialize)]
+pub struct WordCount<'a> {
+    pub word: &'a str,
+    pub count: usize,
+}
+
+/// files の統計をファイルごとに並列にとる
+///
+/// 結果は files と同じ順に並ぶ。読み込めなかったファイルは Err になる。
+pub fn collect_stats(files: &[PathBuf]) -> Vec<Result<Stats, PathError>> {
+    files
+        .par_iter()
+        .map(|path| {
+            fs::read(path)
+                .map(|data| Stats::from_bytes(&data))
+                .map_err(|error| PathError {
+                    path: path.clone(),
+                    error,
+                })
+        })
+        .collect()
+}
+
+/// JSON で出力するファイルごとの統計
+#[derive(Serialize)]
+struct FileReport<'a> {
+    path: &'a str,
+    #[serde(flatten)]
+    stats: &'a Stats,
+    top_words: Vec<WordCount<'a>>,
+}
+
+/// JSON で出力する統計全体
+#[derive(Serialize)]
+struct Report<'a> {
+    files: Vec<FileReport<'a>>,
+    total: FileReport<'a>,
+}
+
+/// 名前と統計の組の合計
+fn total(results: &[(String, Stats)]) -> Stats {
+    let mut total = Stats::default();
+    for (_, stats) in results {
+        total.merge(stats);
+    }
+    total
+}
+
+/// 各ファイルの統計を表の形式で出力する
+///
+/// 複数のファイルがあれば合計の行を加える。最後に (合計の) 頻出単語を top 個出力する。
+pub fn write_table<W: Write>(
+    out: &mut W,
+    results: &[(String, Stats)],
+    top: usize,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "{:>8} {:>8} {:>8} {:>8} {:>9} {:>8}  file",
+        "lines", "words", "chars", "bytes", "graphemes", "longest"
+    )?;
+    let row = |out: &mut W, name: &str, s: &Stats| {
+        writeln!(
+            out,
+            "{:>8} {:>8} {:>8} {:>8} {:>9} {:>8}  {}",
+            s.lines, s.words, s.chars, s.bytes, s.graphemes, s.longest_line, name
+        )
+    };
+    for (name, stats) in results {
+        row(out, name, stats)?;
+    }
+    let total = total(results);
+    if results.len() > 1 {
+        row(out, "total", &total)?;
+    }
+
+    let words = total.top_words(top);
+    if !words.is_empty() {
+        writeln!(out)?;
+        writeln!(out, "top words:")?;
+        for WordCount { word, count } in words {
+            writeln!(out, "{:>8}  {}", count, word)?;
+        }
+    }
+    Ok(())
+}
+
+/// 各ファイルと合計の統計を 1 つの JSON で出力する
+pub fn write_json<W: Write>(
+    out: &mut W,
+    results: &[(String, Stats)],
+    top: usize,
+) -> io::Result<()> {
+    let total = total(results);
+    let report = Report {
+        files: results
+            .iter()
+            .map(|(name, stats)| FileReport {
+                path: name,
+                stats,
+                top_words: stats.top_words(top),
+            })
+            .collect(),
+        total: FileReport {
+            path: "total",
+            stats: &total,
+            top_words: total.top_words(top),
+        },
+    };
+    serde_json::to_writer(&mut *out, &report)?;
+    writeln!(out)
+}
+
+/// stats コマンドのエントリポイント
+///
+/// 終了コードを返す (読み込めなかったファイルがあれば 2)。
+pub fn stats_main(args: Args) -> Result<i32, Box<dyn Error>> {
+    let mut results = vec![];
+    let mut code = 0;
+    if args.files.is_empty() {
+        let mut data = vec![];
+        io::stdin().lock().read_to_end(&mut data)?;
+        results.push((STDIN_NAME.to_string(), Stats::from_bytes(&data)));
+    } else {
+        for (path, result) in args.files.iter().zip(collect_stats(&args.files)) {
+            match result {
+                Ok(stats) => results.push((path.to_string_lossy().into_owned(), stats)),
+                Err(e) => {
+                    eprintln!("stats: {}", e);
+                    code = 2;
+                }
+            }
+        }
+    }
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if args.json {
+        write_json(&mut out, &results, args.top)?;
+    } else {
+        write_table(&mut out, &results, args.top)?;
+    }
+    Ok(code)
+}
+
+#[test]
+fn test_stats_from_bytes() {
+    let mut data = "The cat, the dog.\nCafe\u{301} \u{3042}\n"
+        .as_bytes()
+        .to_vec();
+    data.push(0xff);
+    let stats = Stats::from_bytes(&data);
+    assert_eq!(stats.lines, 2);
+    assert_eq!(stats.words, 7);
+    assert_eq!(stats.bytes, 30);
+    assert_eq!(stats.chars, 27);
+    // e と結合文字は 1 つの書記素クラスタになる
+    assert_eq!(stats.graphemes, 26);
+    assert_eq!(stats.longest_line, 17);
+    assert_eq!(
+        stats.top_words(2),
+        vec![
+            WordCount {
+                word: "the",
+                count: 2
+            },
+            WordCount {
+                word: "cafe\u{301}",
+                count: 1
+            },
+        ]
+    );
+
+    let empty = Stats::from_bytes(b"");
+    assert_eq!(empty, Stats::default());
+    // 最後の行に改行がなければ行数に数えない
+    assert_eq!(Stats::from_bytes(b"a\nb").lines, 1);
+}
+
+#[test]
+fn test_collect_stats() {
+    let dir = tempfile::tempdir().unwrap();
+    let a = dir.path().join("a.txt");
+    let b = dir.path().join("b.txt");
+    fs::write(&a, "one two\ntwo\n").unwrap();
+    fs::write(&b, "three two\n").unwrap();
+    let files = vec![a, dir.path().join("missing"), b];
+
+    let results = collect_stats(&files);
+    assert_eq!(results.len(), 3);
+    assert!(results[1].is_err());
+    let results: Vec<(String, Stats)> = files
+        .iter()
+        .zip(results)
+        .filter_map(|(path, r)| Some((path.file_name()?.to_string_lossy().into_owned(), r.ok()?)))
+        .collect();
+
+    let mut out = vec![];
+    write_table(&mut out, &results, 2).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "   lines    words    chars    bytes graphemes  longest  file\n\
+         \x20      2        3       12       12        12        7  a.txt\n\
+         \x20      1        2       10       10        10        9  b.txt\n\
+         \x20      3        5       22       22        22        9  total\n\
+         \n\
+         top words:\n\
+         \x20      3  two\n\
+         \x20      1  one\n"
+    );
+
+    let mut out = vec![];
+    write_json(&mut out, &results, 1).unwrap();
+    let report: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(report["files"][0]["path"], "a.txt");
+    assert_eq!(report["files"][1]["words"], 2);
+    assert_eq!(
+        report["files"][1]["top_words"],
+        serde_json::json!([{"word": "three", "count": 1}])
+    );
+    assert_eq!(report["total"]["lines"], 3);
+    assert_eq!(
+        report["total"]["top_words"],
+        serde_json::json!([{"word": "two", "count": 3}])
+    );
+}
@@ -0,0 +1,814 @@
+use filetime::FileTime;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::path::{Component, Path, PathBuf};
+
+use crate::fsutil;
+
+/// tar のブロックの大きさ (ヘッダーもデータもこの単位で並ぶ)
+const BLOCK_SIZE: usize = 512;
+
+/// ustar 形式の magic と version
+const MAGIC: &[u8; 8] = b"ustar\x0000";
+
+/// 標準入力や標準出力を表すファイル名
+const STDIO_NAME: &str = "-";
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// 項目の種類
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EntryType {
+    File,
+    Dir,
+    Symlink,
+    /// 対応していない種類 (データは読み飛ばす)
+    Other(u8),
+}
+
+impl EntryType {
+    fn flag(self) -> u8 {
+        match self {
+            EntryType::File => b'0',
+            EntryType::Dir => b'5',
+            EntryType::Symlink => b'2',
+            EntryType::Other(flag) => flag,
+        }
+    }
+
+    fn from_flag(flag: u8) -> EntryType {
+        match flag {
+            b'0' | b'\0' | b'7' => EntryType::File,
+            b'5' => EntryType::Dir,
+            b'2' => EntryType::Symlink,
+            flag => EntryType::Other(flag),
+        }
+    }
+}
+
+/// 1 つの項目のヘッダー
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    /// アーカイブの中のパス (`/` 区切り。ディレクトリは `/` で終わる)
+    pub path: String,
+    pub mode: u32,
+    pub uid: u64,
+    pub gid: u64,
+    pub size: u64,
+    /// 更新日時 (UNIX 時間の秒)
+    pub mtime: u64,
+    pub kind: EntryType,
+    /// シンボリックリンクのリンク先
+    pub link: String,
+}
+
+impl Header {
+    pub fn new(path: &str, kind: EntryType) -> Header {
+        Header {
+            path: path.to_string(),
+            mode: if kind == EntryType::Dir { 0o755 } else { 0o644 },
+            uid: 0,
+            gid: 0,
+            size: 0,
+            mtime: 0,
+            kind,
+            link: String::new(),
+        }
+    }
+
+    /// 512 バイトのブロックにする
+    fn to_block(&self) -> io::Result<[u8; BLOCK_SIZE]> {
+        let mut block = [0; BLOCK_SIZE];
+        let (prefix, name) = split_path(&self.path)
+            .ok_or_else(|| invalid(format!("{}: path too long", self.path)))?;
+        if self.link.len() > 100 {
+            return Err(invalid(format!("{}: link target too long", self.path)));
+        }
+        block[..name.len()].copy_from_slice(name.as_bytes());
+        write_octal(&mut block[100..108], self.mode as u64)?;
+        write_octal(&mut block[108..116], self.uid)?;
+        write_octal(&mut block[116..124], self.gid)?;
+        write_octal(&mut block[124..136], self.size)
+            .map_err(|_| invalid(format!("{}: file too large", self.path)))?;
+        write_octal(&mut block[136..148], self.mtime)?;
+        block[156] = self.kind.flag();
+        block[157..157 + self.link.len()].copy_from_slice(self.link.as_bytes());
+        block[257..265].copy_from_slice(MAGIC);
+        block[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+        // チェックサムは、チェックサムの欄を空白で埋めた状態で計算する
+        block[148..156].fill(b' ');
+        let sum: u32 = block.iter().map(|&b| b as u32).sum();
+        write_octal(&mut block[148..155], sum as u64)?;
+        Ok(block)
+    }
+
+    /// 512 バイトのブロックを読む
+    fn from_block(block: &[u8; BLOCK_SIZE]) -> io::Result<Header> {
+        let stored = parse_number(&block[148..156])?;
+        let sum: u64 = block
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| if (148..156).contains(&i) { b' ' } else { b } as u64)
+            .sum();
+        if stored != sum {
+            return Err(invalid("tar header checksum mismatch".to_string()));
+        }
+
+        let mut path = field_str(&block[..100]);
+        if &block[257..262] == b"ustar" {
+            let prefix = field_str(&block[345..500]);
+            if !prefix.is_empty() {
+                path = format!("{}/{}", prefix, path);
+            }
+        }
+        Ok(Header {
+            path,
+            mode: parse_number(&block[100..108])? as u32,
+            uid: parse_number(&block[108..116])?,
+            gid: parse_number(&block[116..124])?,
+            size: parse_number(&block[124..136])?,
+            mtime: parse_number(&block[136..148])?,
+            kind: EntryType::from_flag(block[156]),
+            link: field_str(&block[157..257]),
+        })
+    }
+}
+
+/// パスを ustar の prefix (155 バイトまで) と name (100 バイトまで) に分ける
+fn split_path(path: &str) -> Option<(&str, &str)> {
+    if path.len() <= 100 {
+        return Some(("", path));
+    }
+    // prefix と name は `/` で区切る (区切りの `/` はどちらにも含めない)
+    path.char_indices()
+        .filter(|&(i, c)| c == '/' && i <= 155 && path.len() - i - 1 <= 100 && i + 1 < path.len())
+        .map(|(i, _)| (&path[..i], &path[i + 1..]))
+        .next()
+}
+
+/// NUL で終わる (か欄いっぱいの) 文字列
+fn field_str(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// 数値を欄の大きさより 1 桁少ない 8 進数で書き、最後を NUL にする
+fn write_octal(field: &mut [u8], n: u64) -> io::Result<()> {
+    let digits = field.len() - 1;
+    let s = format!("{:0width$o}", n, width = digits);
+    if s.len() > digits {
+        return Err(invalid(format!("{} does not fit in a tar header", n)));
+    }
+    field[..digits].copy_from_slice(s.as_bytes());
+    field[digits] = 0;
+    Ok(())
+}
+
+/// 8 進数の欄を読む (GNU tar が大きな値に使う base-256 形式にも対応する)
+fn parse_number(field: &[u8]) -> io::Result<u64> {
+    if field.first().is_some_and(|&b| b & 0x80 != 0) {
+        let n = field[1..].iter().fold(0u64, |n, &b| (n << 8) | b as u64);
+        return Ok(n);
+    }
+    let s = field_str(field);
+    let s = s.trim_matches(|c| c == ' ' || c == '\0');
+    if s.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(s, 8).map_err(|_| invalid(format!("invalid number {:?} in tar header", s)))
+}
+
+/// ブロックの大きさにそろえるために足すバイト数
+fn padding(size: u64) -> u64 {
+    (BLOCK_SIZE as u64 - size % BLOCK_SIZE as u64) % BLOCK_SIZE as u64
+}
+
+/// tar アーカイブを書き出す
+pub struct Builder<W: Write> {
+    out: W,
+}
+
+impl<W: Write> Builder<W> {
+    pub fn new(out: W) -> Builder<W> {
+        Builder { out }
+    }
+
+    /// ヘッダーと、 header.size バイトのデータを書き出す
+    pub fn append<R: Read>(&mut self, header: &Header, data: R) -> io::Result<()> {
+        self.out.write_all(&header.to_block()?)?;
+        let written = io::copy(&mut data.take(header.size), &mut self.out)?;
+        if written != header.size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("{}: file changed while archiving", header.path),
+            ));
+        }
+        self.out
+            .write_all(&[0; BLOCK_SIZE][..padding(header.size) as usize])
+    }
+
+    /// ディスク上の path を、アーカイブの中では name として追加する
+    ///
+    /// ディレクトリの中身は追加しない。シンボリックリンクはリンクのまま追加する。
+    /// FIFO やデバイスファイルなどの特殊ファイルはエラーにする (開くと読み込みが終わらないことがある)。
+    pub fn append_path(&mut self, path: &Path, name: &str) -> io::Result<()> {
+        let meta = fs::symlink_metadata(path)?;
+        let kind = if meta.is_dir() {
+            EntryType::Dir
+        } else if meta.file_type().is_symlink() {
+            EntryType::Symlink
+        } else if meta.is_file() {
+            EntryType::File
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}: cannot archive special file", path.display()),
+            ));
+        };
+        let mut header = Header::new(name, kind);
+        header.mtime = FileTime::from_last_modification_time(&meta)
+            .unix_seconds()
+            .max(0) as u64;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            header.mode = meta.mode() & 0o7777;
+            header.uid = meta.uid() as u64;
+            header.gid = meta.gid() as u64;
+        }
+        match kind {
+            EntryType::Dir => {
+                if !header.path.ends_with('/') {
+                    header.path.push('/');
+                }
+                self.append(&header, io::empty())
+            }
+            EntryType::Symlink => {
+                header.link = fs::read_link(path)?.to_string_lossy().into_owned();
+                self.append(&header, io::empty())
+            }
+            _ => {
+                header.size = meta.len();
+                self.append(&header, BufReader::new(File::open(path)?))
+            }
+        }
+    }
+
+    /// ディスク上の path を再帰的に追加する
+    ///
+    /// アーカイブの中のパスは path の最後の要素から始まる (`tar cf x.tar dir` と同じ)。
+    /// ディレクトリの中の特殊ファイルは追加せずに読み飛ばす。
+    pub fn append_all(&mut self, path: &Path) -> io::Result<()> {
+        let base = match path.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            // `.` や `..` の場合は中身だけを追加する
+            None => String::new(),
+        };
+        if !base.is_empty() {
+            self.append_path(path, &base)?;
+        }
+        if fs::symlink_metadata(path)?.is_dir() {
+            let tree =
+                fsutil::scan(path).map_err(|e| io::Error::new(e.error.kind(), e.to_string()))?;
+            for (rel, entry) in &tree {
+                if *entry == fsutil::Entry::Special {
+                    continue;
+                }
+                let rel_name = rel
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                let name = if base.is_empty() {
+                    rel_name
+                } else {
+                    format!("{}/{}", base, rel_name)
+                };
+                self.append_path(&path.join(rel), &name)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 終わりを表す 2 つの空のブロックを書き出し、出力先を返す
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(&[0; BLOCK_SIZE * 2])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// tar アーカイブを読み込む
+///
+/// next_entry でヘッダーを読んだ後、 Read としてその項目のデータを読める。
+/// データを読み終えていなくても、次の next_entry で読み飛ばす。
+pub struct Archive<R: Read> {
+    input: R,
+    /// 現在の項目のデータの残りのバイト数
+    remaining: u64,
+    /// 現在の項目のデータの後のパディングのバイト数
+    padding: u64,
+    done: bool,
+}
+
+impl<R: Read> Archive<R> {
+    pub fn new(input: R) -> Archive<R> {
+        Archive {
+            input,
+            remaining: 0,
+            padding: 0,
+            done: false,
+        }
+    }
+
+    /// 次の項目のヘッダーを読む (アーカイブの終わりなら None)
+    pub fn next_entry(&mut self) -> io::Result<Option<Header>> {
+        if self.done {
+            return Ok(None);
+        }
+        let skip = self.remaining + self.padding;
+        let skipped = io::copy(&mut (&mut self.input).take(skip), &mut io::sink())?;
+        if skipped != skip {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "truncated tar archive",
+            ));
+        }
+
+        let mut block = [0; BLOCK_SIZE];
+        if let Err(e) = self.input.read_exact(&mut block) {
+            // 終わりのブロックのないアーカイブも受け付ける
+            if e.kind() == io::ErrorKind::UnexpectedEof {
+                self.done = true;
+                return Ok(None);
+            }
+            return Err(e);
+        }
+        if block.iter().all(|&b| b == 0) {
+            self.done = true;
+            return Ok(None);
+        }
+        let header = Header::from_block(&block)?;
+        // ディレクトリやリンクにはデータがない
+        self.remaining = match header.kind {
+            EntryType::Dir | EntryType::Symlink => 0,
+            _ => header.size,
+        };
+        self.padding = padding(self.remaining);
+        Ok(Some(header))
+    }
+}
+
+impl<R: Read> Read for Archive<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let max = buf
+            .len()
+            .min(self.remaining.min(usize::MAX as u64) as usize);
+        let n = self.input.read(&mut buf[..max])?;
+        if n == 0 && max > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "truncated tar archive",
+            ));
+        }
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+/// アーカイブの中のパスを、展開先からの相対パスにする
+///
+/// 絶対パスや `..` を含むパスなど、展開先の外を指しうるパスは None 。
+/// `./` のように展開先そのものを指すパスは空のパスになる。
+pub fn safe_path(path: &str) -> Option<PathBuf> {
+    let mut safe = PathBuf::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(c) => safe.push(c),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(safe)
+}
+
+/// 展開先の中のパスの途中にシンボリックリンクがないか確かめる
+///
+/// 先に展開したシンボリックリンクを経由して、展開先の外に書き込むのを防ぐ。
+fn check_no_symlink_parents(dest: &Path, rel: &Path) -> io::Result<()> {
+    let mut path = dest.to_path_buf();
+    let parents: Vec<_> = rel.components().collect();
+    for component in &parents[..parents.len() - 1] {
+        path.push(component);
+        if fs::symlink_metadata(&path).is_ok_and(|m| m.file_type().is_symlink()) {
+            return Err(invalid(format!(
+                "{}: refusing to extract through a symbolic link",
+                rel.display()
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o7777))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+/// archive を dest に展開し、展開したパスを返す
+///
+/// パーミッションと更新日時を元に戻す。展開先の外を指すパスがあればエラーにする。
+/// 対応していない種類の項目は読み飛ばす。
+pub fn unpack<R: Read>(archive: &mut Archive<R>, dest: &Path) -> io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(dest)?;
+    let mut unpacked = vec![];
+    // ディレクトリの日時とパーミッションは、中身を展開した後で設定する
+    let mut dirs = vec![];
+    while let Some(header) = archive.next_entry()? {
+        let rel = safe_path(&header.path)
+            .ok_or_else(|| invalid(format!("{}: unsafe path in archive", header.path)))?;
+        if rel.as_os_str().is_empty() {
+            // `tar -C dir -cf x.tar .` で作ったアーカイブは `./` から始まる。
+            // 展開先はもう作ってあるので、ディレクトリなら読み飛ばす
+            if header.kind == EntryType::Dir {
+                continue;
+            }
+            return Err(invalid(format!("{}: unsafe path in archive", header.path)));
+        }
+        check_no_symlink_parents(dest, &rel)?;
+        let path = dest.join(&rel);
+        let mtime = FileTime::from_unix_time(header.mtime as i64, 0);
+
+        let existing = fs::symlink_metadata(&path).ok();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        match header.kind {
+            EntryType::Dir => {
+                if existing.as_ref().is_some_and(|m| !m.is_dir()) {
+                    fs::remove_file(&path)?;
+                }
+                if !path.is_dir() {
+                    fs::create_dir(&path)?;
+                }
+                dirs.push((path.clone(), header.mode, mtime));
+            }
+            EntryType::File => {
+                // 既存のシンボリックリンクを辿って書き込まないように、先に消す
+                if existing.is_some() {
+                    fs::remove_file(&path)?;
+                }
+                let mut out = BufWriter::new(File::create(&path)?);
+                io::copy(archive, &mut out)?;
+                out.flush()?;
+                drop(out);
+                set_mode(&path, header.mode)?;
+                filetime::set_file_mtime(&path, mtime)?;
+            }
+            EntryType::Symlink => {
+                if existing.is_some() {
+                    fs::remove_file(&path)?;
+                }
+                fsutil::symlink(Path::new(&header.link), &path)?;
+                filetime::set_symlink_file_times(&path, mtime, mtime)?;
+            }
+            EntryType::Other(_) => continue,
+        }
+        unpacked.push(rel);
+    }
+    for (path, mode, mtime) in dirs.into_iter().rev() {
+        set_mode(&path, mode)?;
+        filetime::set_file_mtime(&path, mtime)?;
+    }
+    Ok(unpacked)
+}
+
+/// `ls -l` のような形式でパーミッションを表す
+fn mode_string(kind: EntryType, mode: u32) -> String {
+    let mut s = String::new();
+    s.push(match kind {
+        EntryType::Dir => 'd',
+        EntryType::Symlink => 'l',
+        EntryType::File => '-',
+        EntryType::Other(_) => '?',
+    });
+    for shift in [6, 3, 0] {
+        let bits = (mode >> shift) & 0o7;
+        s.push(if bits & 4 != 0 { 'r' } else { '-' });
+        s.push(if bits & 2 != 0 { 'w' } else { '-' });
+        s.push(if bits & 1 != 0 { 'x' } else { '-' });
+    }
+    s
+}
+
+/// アーカイブの項目の一覧を出力する
+pub fn write_list<R: Read, W: Write>(
+    archive: &mut Archive<R>,
+    out: &mut W,
+    verbose: bool,
+) -> io::Result<()> {
+    while let Some(header) = archive.next_entry()? {
+        if verbose {
+            write!(
+                out,
+                "{} {:>10} {}",
+                mode_string(header.kind, header.mode),
+                header.size,
+                header.path
+            )?;
+            if header.kind == EntryType::Symlink {
+                write!(out, " -> {}", header.link)?;
+            }
+            writeln!(out)?;
+        } else {
+            writeln!(out, "{}", header.path)?;
+        }
+    }
+    Ok(())
+}
+
+/// `-` なら標準入力、それ以外はファイルを開く
+fn open_input(path: &Path) -> io::Result<Box<dyn Read>> {
+    if path == Path::new(STDIO_NAME) {
+        Ok(Box::new(io::stdin().lock()))
+    } else {
+        Ok(Box::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+/// tar のコマンドライン引数
+#[derive(Debug, clap::Subcommand)]
+pub enum Args {
+    /// ファイルやディレクトリをアーカイブにまとめる
+    #[command(about = "Create a ustar archive from files and directories")]
+    Pack {
+        #[arg(help = "Archive to create (- for standard output)")]
+        archive: PathBuf,
+        #[arg(required = true, help = "Files and directories to add")]
+        paths: Vec<PathBuf>,
+    },
+    /// アーカイブを展開する
+    #[command(about = "Extract a tar archive")]
+    Unpack {
+        #[arg(help = "Archive to extract (- for standard input)")]
+        archive: PathBuf,
+        #[arg(
+            short = 'C',
+            long,
+            default_value = ".",
+            help = "Directory to extract into"
+        )]
+        dest: PathBuf,
+    },
+    /// アーカイブの中身の一覧を出力する
+    #[command(about = "List the contents of a tar archive")]
+    List {
+        #[arg(help = "Archive to read (- for standard input)")]
+        archive: PathBuf,
+        #[arg(short, long, help = "Show permissions and sizes")]
+        verbose: bool,
+    },
+}
+
+/// tar コマンドのエントリポイント
+pub fn tar_main(args: Args) -> Result<i32, Box<dyn Error>> {
+    match args {
+        Args::Pack { archive, paths } => {
+            let out: Box<dyn Write> = if archive == Path::new(STDIO_NAME) {
+                Box::new(io::stdout().lock())
+            } else {
+                Box::new(BufWriter::new(File::create(&archive)?))
+            };
+            let mut builder = Builder::new(out);
+            for path in &paths {
+                builder
+                    .append_all(path)
+                    .map_err(|e| format!("{}: {}", path.display(), e))?;
+            }
+            builder.finish()?;
+        }
+        Args::Unpack { archive, dest } => {
+            let mut archive = Archive::new(open_input(&archive)?);
+            unpack(&mut archive, &dest)?;
+        }
+        Args::List { archive, verbose } => {
+            let mut archive = Archive::new(open_input(&archive)?);
+            write_list(&mut archive, &mut io::stdout().lock(), verbose)?;
+        }
+    }
+    Ok(0)
+}
+
+/// test/ のフィクスチャと、中身のあるファイルやリンクを含むディレクトリを作る
+#[cfg(test)]
+fn sample_dir() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("root");
+    fsutil::copy_tree(Path::new("test"), &root.join("test")).unwrap();
+    let long_dir = root.join("a".repeat(60)).join("b".repeat(60));
+    fs::create_dir_all(&long_dir).unwrap();
+    fs::write(long_dir.join("long.txt"), "long name").unwrap();
+    fs::write(
+        root.join("data.bin"),
+        (0..=255u8).cycle().take(1000).collect::<Vec<u8>>(),
+    )
+    .unwrap();
+    #[cfg(unix)]
+    fsutil::symlink(Path::new("data.bin"), &root.join("link")).unwrap();
+    dir
+}
+
+#[test]
+fn test_round_trip() {
+    let dir = sample_dir();
+    let root = dir.path().join("root");
+    let mut builder = Builder::new(vec![]);
+    builder.append_all(&root).unwrap();
+    let bytes = builder.finish().unwrap();
+    assert_eq!(bytes.len() % BLOCK_SIZE, 0);
+
+    let mut list = vec![];
+    write_list(&mut Archive::new(&bytes[..]), &mut list, false).unwrap();
+    let list = String::from_utf8(list).unwrap();
+    let long_name = format!("root/{}/{}/long.txt", "a".repeat(60), "b".repeat(60));
+    assert!(list.starts_with("root/\nroot/"));
+    assert!(list.contains(&format!("{}\n", long_name)));
+    assert!(list.contains("root/test/\nroot/test/test_file_1\n"));
+    assert!(list.ends_with("root/test/test_file_5\n"));
+
+    let dest = dir.path().join("dest");
+    let unpacked = unpack(&mut Archive::new(&bytes[..]), &dest).unwrap();
+    assert_eq!(unpacked[0], Path::new("root"));
+    assert!(unpacked.contains(&PathBuf::from(&long_name)));
+    assert!(
+        fsutil::diff_trees(&root, &dest.join("root"), fsutil::Compare::Checksum)
+            .unwrap()
+            .is_empty()
+    );
+    // 更新日時は秒の単位で元に戻る
+    let mtime = |p: &Path| FileTime::from_last_modification_time(&fs::metadata(p).unwrap());
+    let fixture = Path::new("test/test_file_1");
+    assert_eq!(
+        mtime(&dest.join("root/test/test_file_1")).unix_seconds(),
+        mtime(fixture).unix_seconds()
+    );
+}
+
+#[test]
+fn test_compatible_with_tar() {
+    use std::process::Command;
+
+    let dir = sample_dir();
+    let root = dir.path().join("root");
+    let mut builder = Builder::new(vec![]);
+    builder.append_all(&root).unwrap();
+    let ours = dir.path().join("ours.tar");
+    fs::write(&ours, builder.finish().unwrap()).unwrap();
+
+    // tar コマンドがなければ確かめない
+    let Ok(output) = Command::new("tar").arg("-tf").arg(&ours).output() else {
+        return;
+    };
+    assert!(output.status.success());
+    let mut ours_list = vec![];
+    write_list(
+        &mut Archive::new(File::open(&ours).unwrap()),
+        &mut ours_list,
+        false,
+    )
+    .unwrap();
+    assert_eq!(output.stdout, ours_list);
+
+    // tar コマンドで作ったアーカイブも読める
+    let theirs = dir.path().join("theirs.tar");
+    let status = Command::new("tar")
+        .arg("--format=ustar")
+        .arg("-cf")
+        .arg(&theirs)
+        .arg("-C")
+        .arg(dir.path())
+        .arg("root")
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let dest = dir.path().join("dest");
+    unpack(&mut Archive::new(File::open(&theirs).unwrap()), &dest).unwrap();
+    assert!(
+        fsutil::diff_trees(&root, &dest.join("root"), fsutil::Compare::Checksum)
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[test]
+fn test_path_traversal() {
+    let archive_with = |entries: &[Header]| {
+        let mut builder = Builder::new(vec![]);
+        for header in entries {
+            builder.append(header, &b"evil"[..]).unwrap();
+        }
+        builder.finish().unwrap()
+    };
+    let mut file = |path: &str| {
+        let mut h = Header::new(path, EntryType::File);
+        h.size = 4;
+        h
+    };
+
+    let dir = tempfile::tempdir().unwrap();
+    let dest = dir.path().join("dest");
+    // 展開先そのものを指すパスも、ファイルとしては展開できない
+    for path in ["../evil", "/tmp/evil", "a/../../evil", "."] {
+        let bytes = archive_with(&[file(path)]);
+        let err = unpack(&mut Archive::new(&bytes[..]), &dest).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", path);
+    }
+    assert!(!dir.path().join("evil").exists());
+
+    // 展開したリンクを経由して外に書き込むことはできない
+    #[cfg(unix)]
+    {
+        let mut link = Header::new("link", EntryType::Symlink);
+        link.link = "..".to_string();
+        let bytes = archive_with(&[link, file("link/evil")]);
+        let err = unpack(&mut Archive::new(&bytes[..]), &dest).unwrap_err();
+        assert!(err.to_string().contains("symbolic link"));
+        assert!(!dir.path().join("evil").exists());
+    }
+
+    // ./ で始まるパスは展開先の中を指し、 ./ 自体のディレクトリは読み飛ばす
+    let bytes = archive_with(&[Header::new("./", EntryType::Dir), file("./ok/file")]);
+    assert_eq!(
+        unpack(&mut Archive::new(&bytes[..]), &dest).unwrap(),
+        vec![PathBuf::from("ok/file")]
+    );
+    assert_eq!(fs::read(dest.join("ok/file")).unwrap(), b"evil");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_special_files() {
+    let dir = sample_dir();
+    // ソケットファイルは特殊ファイルの例として手軽に作れる
+    let socket = dir.path().join("root/socket");
+    let _listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+
+    let mut builder = Builder::new(vec![]);
+    let err = builder.append_path(&socket, "socket").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    // ディレクトリごと追加するときは読み飛ばす
+    let mut builder = Builder::new(vec![]);
+    builder.append_all(&dir.path().join("root")).unwrap();
+    let bytes = builder.finish().unwrap();
+    let mut list = vec![];
+    write_list(&mut Archive::new(&bytes[..]), &mut list, false).unwrap();
+    let list = String::from_utf8(list).unwrap();
+    assert!(list.contains("root/data.bin\n"));
+    assert!(!list.contains("socket"));
+}
+
+#[test]
+fn test_header() {
+    let mut header = Header::new("dir/file.txt", EntryType::File);
+    header.size = 1234;
+    header.mtime = 1_600_000_000;
+    header.mode = 0o640;
+    let block = header.to_block().unwrap();
+    assert_eq!(&block[124..136], b"00000002322\0");
+    assert_eq!(Header::from_block(&block).unwrap(), header);
+
+    let mut broken = block;
+    broken[0] = b'x';
+    assert!(Header::from_block(&broken).is_err());
+
+    assert!(Header::new(&"x".repeat(101), EntryType::File)
+        .to_block()
+        .is_err());
+    assert_eq!(
+        split_path(&format!("{}/b", "a".repeat(150))).unwrap().1,
+        "b"
+    );
+    assert_eq!(parse_number(&[0x80, 0, 0, 0, 0, 0, 1, 0]).unwrap(), 256);
+    assert_eq!(mode_string(EntryType::Dir, 0o755), "drwxr-xr-x");
+
+    // 途中で切れたアーカイブはエラーになる
+    let mut builder = Builder::new(vec![]);
+    builder.append(&header, &[0u8; 1234][..]).unwrap();
+    let bytes = builder.finish().unwrap();
+    let mut archive = Archive::new(&bytes[..1000]);
+    archive.next_entry().unwrap();
+    assert!(archive.next_entry().is_err());
+}
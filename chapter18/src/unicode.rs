@@ -0,0 +1,341 @@
+use serde::Serialize;
+use std::io;
+use std::io::prelude::*;
+use unicode_general_category::get_general_category;
+use unicode_normalization::char::{canonical_combining_class, is_combining_mark};
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// 結合文字を単独で表示するときに前に置く文字 (DOTTED CIRCLE)
+const DOTTED_CIRCLE: char = '\u{25cc}';
+
+/// 出力の形式
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Format {
+    Table,
+    Json,
+}
+
+/// unicode のコマンドライン引数
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    #[arg(long, value_enum, default_value_t = Format::Table, help = "Output format")]
+    pub format: Format,
+    #[arg(help = "Text to inspect (each line of standard input if omitted)")]
+    pub text: Option<String>,
+}
+
+/// 1 文字 (コードポイント) の情報
+#[derive(Debug, PartialEq, Serialize)]
+pub struct CharInfo {
+    #[serde(rename = "char")]
+    pub ch: char,
+    /// `U+0041` の形式のコードポイント
+    pub code_point: String,
+    /// Unicode の文字名 (制御文字などには名前がない)
+    pub name: Option<String>,
+    pub utf8: Vec<u8>,
+    pub utf16: Vec<u16>,
+    /// 一般カテゴリの略称 (`Lu` や `Mn` など)
+    pub category: &'static str,
+    /// 正規結合クラス (結合文字の並べ替えに使う。 0 なら基底文字)
+    pub combining_class: u8,
+    /// 結合文字か
+    pub combining: bool,
+    /// 端末に表示したときの幅 (制御文字は None)
+    pub width: Option<usize>,
+    /// 何番目の書記素クラスタに含まれるか
+    pub grapheme: usize,
+}
+
+impl CharInfo {
+    fn new(ch: char, grapheme: usize) -> CharInfo {
+        let mut utf8 = [0; 4];
+        let mut utf16 = [0; 2];
+        CharInfo {
+            ch,
+            code_point: format!("U+{:04X}", ch as u32),
+            name: unicode_names2::name(ch).map(|name| name.to_string()),
+            utf8: ch.encode_utf8(&mut utf8).as_bytes().to_vec(),
+            utf16: ch.encode_utf16(&mut utf16).to_vec(),
+            category: get_general_category(ch).abbreviation(),
+            combining_class: canonical_combining_class(ch),
+            combining: is_combining_mark(ch),
+            width: ch.width(),
+            grapheme,
+        }
+    }
+
+    /// 表に表示するための文字
+    ///
+    /// 制御文字はエスケープし、結合文字は点線の円と組み合わせて表示する。
+    fn display(&self) -> String {
+        if self.ch.is_control() {
+            self.ch.escape_default().to_string()
+        } else if self.combining {
+            format!("{}{}", DOTTED_CIRCLE, self.ch)
+        } else {
+            self.ch.to_string()
+        }
+    }
+}
+
+/// 4 種類の正規化形式
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Normalized {
+    pub nfc: String,
+    pub nfd: String,
+    pub nfkc: String,
+    pub nfkd: String,
+}
+
+/// 1 行分の文字列の情報
+#[derive(Debug, PartialEq, Serialize)]
+pub struct LineInfo {
+    pub text: String,
+    /// 端末に表示したときの幅
+    pub width: usize,
+    /// 書記素クラスタ (ユーザーが 1 文字とみなす単位) ごとに区切った文字列
+    pub graphemes: Vec<String>,
+    pub chars: Vec<CharInfo>,
+    pub normalization: Normalized,
+}
+
+/// text に含まれる文字を調べる
+pub fn inspect(text: &str) -> LineInfo {
+    let graphemes: Vec<String> = text.graphemes(true).map(String::from).collect();
+    let chars = graphemes
+        .iter()
+        .enumerate()
+        .flat_map(|(i, g)| g.chars().map(move |ch| CharInfo::new(ch, i)))
+        .collect();
+
+    LineInfo {
+        text: text.to_string(),
+        width: text.width(),
+        graphemes,
+        chars,
+        normalization: Normalized {
+            nfc: text.nfc().collect(),
+            nfd: text.nfd().collect(),
+            nfkc: text.nfkc().collect(),
+            nfkd: text.nfkd().collect(),
+        },
+    }
+}
+
+/// 表示幅が width になるように空白を足す
+fn pad(s: &str, width: usize) -> String {
+    let w = s.width();
+    format!("{}{}", s, " ".repeat(width.saturating_sub(w)))
+}
+
+/// 文字列をコードポイントの列で表す
+fn code_points(s: &str) -> String {
+    s.chars()
+        .map(|ch| format!("U+{:04X}", ch as u32))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// 1 行分の情報を表の形式で出力する
+///
+/// `#` の列は書記素クラスタの番号で、クラスタの先頭の文字にだけ表示する。
+pub fn write_table<W: Write>(out: &mut W, info: &LineInfo) -> io::Result<()> {
+    let header = [
+        "#", "char", "code", "cat", "ccc", "width", "utf-8", "utf-16", "name",
+    ];
+    let mut rows: Vec<Vec<String>> = vec![header.iter().map(|s| s.to_string()).collect()];
+    let mut last_grapheme = None;
+    for c in &info.chars {
+        let grapheme = if last_grapheme == Some(c.grapheme) {
+            String::new()
+        } else {
+            c.grapheme.to_string()
+        };
+        last_grapheme = Some(c.grapheme);
+        rows.push(vec![
+            grapheme,
+            c.display(),
+            c.code_point.clone(),
+            c.category.to_string(),
+            c.combining_class.to_string(),
+            c.width.map_or("-".to_string(), |w| w.to_string()),
+            c.utf8
+                .iter()
+                .map(|b| format!("{:02X}", b))
+                .collect::<Vec<_>>()
+                .join(" "),
+            c.utf16
+                .iter()
+                .map(|u| format!("{:04X}", u))
+                .collect::<Vec<_>>()
+                .join(" "),
+            c.name.clone().unwrap_or_default(),
+        ]);
+    }
+
+    let widths: Vec<usize> = (0..header.len())
+        .map(|i| rows.iter().map(|row| row[i].width()).max().unwrap_or(0))
+        .collect();
+    for row in &rows {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &w)| pad(cell, w))
+            .collect();
+        writeln!(out, "{}", line.join("  ").trim_end())?;
+    }
+
+    writeln!(
+        out,
+        "graphemes: {} ({})",
+        info.graphemes.len(),
+        info.graphemes.join("|")
+    )?;
+    writeln!(out, "width: {}", info.width)?;
+    let n = &info.normalization;
+    for (form, s) in [
+        ("NFC", &n.nfc),
+        ("NFD", &n.nfd),
+        ("NFKC", &n.nfkc),
+        ("NFKD", &n.nfkd),
+    ] {
+        writeln!(out, "{:<5} {} [{}]", form, s, code_points(s))?;
+    }
+    Ok(())
+}
+
+/// 1 行分の情報を 1 行の JSON で出力する
+pub fn write_json<W: Write>(out: &mut W, info: &LineInfo) -> io::Result<()> {
+    serde_json::to_writer(&mut *out, info)?;
+    writeln!(out)
+}
+
+/// args.text (省略された場合は input の各行) を調べて out に出力する
+pub fn run<R: BufRead, W: Write>(args: &Args, input: R, out: &mut W) -> io::Result<()> {
+    let write = |out: &mut W, text: &str| {
+        let info = inspect(text);
+        match args.format {
+            Format::Table => write_table(out, &info),
+            Format::Json => write_json(out, &info),
+        }
+    };
+
+    if let Some(text) = &args.text {
+        return write(out, text);
+    }
+    for (i, line) in input.lines().enumerate() {
+        if i > 0 && args.format == Format::Table {
+            writeln!(out)?;
+        }
+        write(out, &line?)?;
+    }
+    Ok(())
+}
+
+/// unicode コマンドのエントリポイント
+pub fn unicode_main(args: Args) -> io::Result<()> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    run(&args, stdin.lock(), &mut stdout.lock())
+}
+
+#[test]
+fn test_inspect_chars() {
+    let info = inspect("aあ😀");
+    assert_eq!(
+        info.chars[0],
+        CharInfo {
+            ch: 'a',
+            code_point: "U+0061".to_string(),
+            name: Some("LATIN SMALL LETTER A".to_string()),
+            utf8: vec![0x61],
+            utf16: vec![0x61],
+            category: "Ll",
+            combining_class: 0,
+            combining: false,
+            width: Some(1),
+            grapheme: 0,
+        }
+    );
+    assert_eq!(info.chars[1].utf8, vec![0xe3, 0x81, 0x82]);
+    assert_eq!(info.chars[1].utf16, vec![0x3042]);
+    assert_eq!(info.chars[1].width, Some(2));
+    assert_eq!(info.chars[1].category, "Lo");
+    // BMP の外の文字は UTF-16 ではサロゲートペアになる
+    assert_eq!(info.chars[2].utf16, vec![0xd83d, 0xde00]);
+    assert_eq!(info.chars[2].name.as_deref(), Some("GRINNING FACE"));
+    assert_eq!(info.width, 5);
+
+    let info = inspect("\t");
+    assert_eq!(info.chars[0].category, "Cc");
+    assert_eq!(info.chars[0].width, None);
+}
+
+#[test]
+fn test_inspect_graphemes_and_normalization() {
+    // e + COMBINING ACUTE ACCENT は 1 つの書記素クラスタになる
+    let info = inspect("Cafe\u{301}!");
+    assert_eq!(info.graphemes, vec!["C", "a", "f", "e\u{301}", "!"]);
+    assert_eq!(info.chars.len(), 6);
+    let accent = &info.chars[4];
+    assert!(accent.combining);
+    assert_eq!(accent.combining_class, 230);
+    assert_eq!(accent.category, "Mn");
+    assert_eq!(accent.grapheme, 3);
+    assert_eq!(info.chars[5].grapheme, 4);
+    assert_eq!(info.width, 5);
+
+    assert_eq!(info.normalization.nfc, "Caf\u{e9}!");
+    assert_eq!(info.normalization.nfd, "Cafe\u{301}!");
+
+    // 互換分解では半角カナや合字も別の文字になる
+    let info = inspect("ｶﬁ");
+    assert_eq!(info.normalization.nfc, "ｶﬁ");
+    assert_eq!(info.normalization.nfkc, "カfi");
+    assert_eq!(info.normalization.nfkd, "カfi");
+}
+
+#[test]
+fn test_write_table() {
+    let mut out = vec![];
+    write_table(&mut out, &inspect("e\u{301}")).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "#  char  code    cat  ccc  width  utf-8  utf-16  name\n\
+         0  e     U+0065  Ll   0    1      65     0065    LATIN SMALL LETTER E\n\
+         \x20  \u{25cc}\u{301}     U+0301  Mn   230  0      CC 81  0301    COMBINING ACUTE ACCENT\n\
+         graphemes: 1 (e\u{301})\n\
+         width: 1\n\
+         NFC   \u{e9} [U+00E9]\n\
+         NFD   e\u{301} [U+0065 U+0301]\n\
+         NFKC  \u{e9} [U+00E9]\n\
+         NFKD  e\u{301} [U+0065 U+0301]\n"
+    );
+}
+
+#[test]
+fn test_run_json() {
+    let args = Args {
+        format: Format::Json,
+        text: None,
+    };
+    let mut out = vec![];
+    run(&args, "a\n\u{3042}\n".as_bytes(), &mut out).unwrap();
+    let lines: Vec<serde_json::Value> = String::from_utf8(out)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[1]["text"], "\u{3042}");
+    assert_eq!(lines[1]["chars"][0]["char"], "\u{3042}");
+    assert_eq!(
+        lines[1]["chars"][0]["utf8"],
+        serde_json::json!([0xe3, 0x81, 0x82])
+    );
+    assert_eq!(lines[1]["chars"][0]["name"], "HIRAGANA LETTER A");
+    assert_eq!(lines[1]["normalization"]["nfkd"], "\u{3042}");
+}
@@ -0,0 +1,500 @@
+use regex::Regex;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// シェルのワイルドカード形式のパターン
+///
+/// `*` と `?` は `/` 以外の任意の文字列・文字に、 `**` はディレクトリをまたいだ
+/// 任意の文字列にマッチする。 `[abc]` や `[!a-z]` の文字クラスも使える。
+/// パターンに `/` が含まれなければファイル名だけを、含まれていれば相対パス全体を比較する。
+#[derive(Debug, Clone)]
+pub struct Glob {
+    re: Regex,
+    match_path: bool,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Result<Glob, regex::Error> {
+        Ok(Glob {
+            re: Regex::new(&glob_to_regex(pattern))?,
+            match_path: pattern.contains('/'),
+        })
+    }
+
+    /// rel (ルートからの相対パス) がパターンにマッチするか
+    pub fn matches(&self, rel: &Path) -> bool {
+        if self.match_path {
+            self.re.is_match(&to_slash(rel))
+        } else {
+            rel.file_name()
+                .is_some_and(|name| self.re.is_match(&name.to_string_lossy()))
+        }
+    }
+}
+
+/// パスを `/` 区切りの文字列にする
+fn to_slash(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// ワイルドカードのパターンを、全体にマッチする正規表現に変換する
+fn glob_to_regex(pattern: &str) -> String {
+    let mut re = String::from("^");
+    let mut chars = pattern.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    // `**/` は 0 個以上のディレクトリ
+                    chars.next();
+                    re.push_str("(?:.*/)?");
+                } else {
+                    re.push_str(".*");
+                }
+            }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            '[' => {
+                let class: String = chars.clone().take_while(|&c| c != ']').collect();
+                if chars.clone().nth(class.chars().count()) == Some(']') {
+                    for _ in 0..=class.chars().count() {
+                        chars.next();
+                    }
+                    let class = match class.strip_prefix('!') {
+                        Some(rest) => format!("^{}", rest),
+                        None => class,
+                    };
+                    re.push('[');
+                    re.push_str(&class.replace('\\', "\\\\").replace('[', "\\["));
+                    re.push(']');
+                } else {
+                    // 閉じられていない `[` は文字そのもの
+                    re.push_str("\\[");
+                }
+            }
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    re.push_str(&regex::escape(&escaped.to_string()));
+                }
+            }
+            _ => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+
+    re.push('$');
+    re
+}
+
+/// .gitignore の 1 行分のルール
+#[derive(Debug)]
+struct IgnoreRule {
+    glob: Glob,
+    /// `!` で始まる、除外を取り消すルール
+    negate: bool,
+    /// `/` で終わる、ディレクトリだけにマッチするルール
+    dir_only: bool,
+}
+
+/// あるディレクトリに置かれた .gitignore
+#[derive(Debug)]
+struct Gitignore {
+    /// .gitignore が置かれたディレクトリ (ルートからの相対パス)
+    base: PathBuf,
+    rules: Vec<IgnoreRule>,
+}
+
+impl Gitignore {
+    fn parse(base: PathBuf, text: &str) -> Gitignore {
+        let mut rules = vec![];
+        for line in text.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (negate, line) = match line.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            let (dir_only, line) = match line.strip_suffix('/') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            // 先頭や途中に `/` を含むパターンは .gitignore の場所からの相対パスとして扱う
+            // (先頭の `/` を取り除いた後も、ファイル名ではなくパス全体を比較する)
+            let anchored = line.contains('/');
+            let pattern = line.trim_start_matches('/');
+            if let Ok(mut glob) = Glob::new(pattern) {
+                glob.match_path = anchored;
+                rules.push(IgnoreRule {
+                    glob,
+                    negate,
+                    dir_only,
+                });
+            }
+        }
+        Gitignore { base, rules }
+    }
+
+    /// 無視するなら Some(true) 、明示的に無視しないなら Some(false) 、
+    /// どのルールにもマッチしなければ None を返す
+    fn matched(&self, rel: &Path, is_dir: bool) -> Option<bool> {
+        let rel = rel.strip_prefix(&self.base).ok()?;
+        // 後に書かれたルールが優先される
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| (is_dir || !rule.dir_only) && rule.glob.matches(rel))
+            .map(|rule| !rule.negate)
+    }
+}
+
+/// ディレクトリを辿る際のオプション
+#[derive(Debug, Clone, clap::Args)]
+pub struct WalkOptions {
+    /// 空でなければ、いずれかにマッチするファイルだけを対象にする
+    #[arg(
+        long,
+        value_name = "GLOB",
+        value_parser = Glob::new,
+        help = "Only include files whose name matches GLOB"
+    )]
+    pub include: Vec<Glob>,
+    /// マッチするファイルとディレクトリを対象から外す
+    #[arg(
+        long,
+        value_name = "GLOB",
+        value_parser = Glob::new,
+        help = "Skip files and directories matching GLOB"
+    )]
+    pub exclude: Vec<Glob>,
+    /// .gitignore に従ってファイルを除外し、 .git ディレクトリを読み飛ばす
+    #[arg(
+        long = "no-ignore",
+        action = clap::ArgAction::SetFalse,
+        help = "Don't skip files listed in .gitignore"
+    )]
+    pub gitignore: bool,
+}
+
+impl Default for WalkOptions {
+    fn default() -> Self {
+        WalkOptions {
+            include: vec![],
+            exclude: vec![],
+            gitignore: true,
+        }
+    }
+}
+
+/// ファイル操作で起きたエラーと、その対象のパス
+///
+/// ディレクトリを辿るときのほか、ファイルの検索やコピーなどで、
+/// どのパスで失敗したかを io::Error に付けて報告するのに使う。
+#[derive(Debug)]
+pub struct PathError {
+    pub path: PathBuf,
+    pub error: io::Error,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.error)
+    }
+}
+
+impl std::error::Error for PathError {}
+
+/// root 以下のファイルを名前順に再帰的に列挙する
+///
+/// シンボリックリンクは辿るが、祖先のディレクトリへ戻るリンクはループとしてエラーにする。
+/// 読み込めなかったファイルやディレクトリは Err として結果に含め、残りの列挙を続ける。
+/// root がファイルの場合は、フィルタに関係なくそのファイルだけを返す。
+pub fn walk(root: &Path, opts: &WalkOptions) -> Vec<Result<PathBuf, PathError>> {
+    let mut found = vec![];
+    match fs::metadata(root) {
+        Err(error) => found.push(Err(PathError {
+            path: root.to_path_buf(),
+            error,
+        })),
+        Ok(m) if m.is_dir() => {
+            let mut walker = Walker {
+                opts,
+                ignores: vec![],
+                ancestors: vec![],
+                found: &mut found,
+            };
+            walker.visit_dir(root, Path::new(""));
+        }
+        Ok(_) => found.push(Ok(root.to_path_buf())),
+    }
+    found
+}
+
+struct Walker<'a> {
+    opts: &'a WalkOptions,
+    /// 現在のディレクトリまでに読み込んだ .gitignore
+    ignores: Vec<Gitignore>,
+    /// 現在のディレクトリとその祖先の正規化されたパス
+    ancestors: Vec<PathBuf>,
+    found: &'a mut Vec<Result<PathBuf, PathError>>,
+}
+
+impl Walker<'_> {
+    fn error(&mut self, path: &Path, error: io::Error) {
+        self.found.push(Err(PathError {
+            path: path.to_path_buf(),
+            error,
+        }));
+    }
+
+    fn is_ignored(&self, rel: &Path, is_dir: bool) -> bool {
+        // 深い階層の .gitignore ほど優先される
+        self.ignores
+            .iter()
+            .rev()
+            .find_map(|ignore| ignore.matched(rel, is_dir))
+            .unwrap_or(false)
+    }
+
+    fn visit_dir(&mut self, dir: &Path, rel: &Path) {
+        let canonical = match dir.canonicalize() {
+            Ok(p) => p,
+            Err(e) => return self.error(dir, e),
+        };
+        if self.ancestors.contains(&canonical) {
+            let e = io::Error::other("symlink loop detected");
+            return self.error(dir, e);
+        }
+
+        let mut entries = match fs::read_dir(dir).and_then(|it| it.collect::<io::Result<Vec<_>>>())
+        {
+            Ok(entries) => entries,
+            Err(e) => return self.error(dir, e),
+        };
+        entries.sort_by_key(|e| e.file_name());
+
+        let ignore_count = self.ignores.len();
+        if self.opts.gitignore {
+            if let Ok(text) = fs::read_to_string(dir.join(".gitignore")) {
+                self.ignores
+                    .push(Gitignore::parse(rel.to_path_buf(), &text));
+            }
+        }
+        self.ancestors.push(canonical);
+
+        for entry in entries {
+            let name = entry.file_name();
+            // "." から辿る場合は "./" を付けずに表示する
+            let path = if dir == Path::new(".") {
+                PathBuf::from(&name)
+            } else {
+                dir.join(&name)
+            };
+            let rel = rel.join(&name);
+
+            // シンボリックリンクはリンク先の種類で判断する
+            let is_dir = match fs::metadata(&path) {
+                Ok(m) => m.is_dir(),
+                Err(e) => {
+                    self.error(&path, e);
+                    continue;
+                }
+            };
+
+            if self.opts.gitignore && is_dir && name == ".git" {
+                continue;
+            }
+            if self.opts.gitignore && self.is_ignored(&rel, is_dir) {
+                continue;
+            }
+            if self.opts.exclude.iter().any(|g| g.matches(&rel)) {
+                continue;
+            }
+
+            if is_dir {
+                self.visit_dir(&path, &rel);
+            } else if self.opts.include.is_empty()
+                || self.opts.include.iter().any(|g| g.matches(&rel))
+            {
+                self.found.push(Ok(path));
+            }
+        }
+
+        self.ancestors.pop();
+        self.ignores.truncate(ignore_count);
+    }
+}
+
+#[cfg(test)]
+fn make_tree(files: &[(&str, &str)]) -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    for (path, content) in files {
+        let path = dir.path().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+    dir
+}
+
+#[cfg(test)]
+fn walk_names(root: &Path, opts: &WalkOptions) -> Vec<String> {
+    walk(root, opts)
+        .into_iter()
+        .map(|r| {
+            let p = r.unwrap();
+            to_slash(p.strip_prefix(root).unwrap())
+        })
+        .collect()
+}
+
+#[test]
+fn test_glob() {
+    let g = Glob::new("*.rs").unwrap();
+    assert!(g.matches(Path::new("main.rs")));
+    assert!(g.matches(Path::new("src/main.rs")));
+    assert!(!g.matches(Path::new("main.rs.bak")));
+
+    let g = Glob::new("src/*.rs").unwrap();
+    assert!(g.matches(Path::new("src/main.rs")));
+    assert!(!g.matches(Path::new("src/bin/main.rs")));
+
+    let g = Glob::new("src/**/*.rs").unwrap();
+    assert!(g.matches(Path::new("src/main.rs")));
+    assert!(g.matches(Path::new("src/bin/main.rs")));
+
+    let g = Glob::new("test_file_[!1-3]").unwrap();
+    assert!(g.matches(Path::new("test_file_4")));
+    assert!(!g.matches(Path::new("test_file_2")));
+
+    let g = Glob::new("a?c.[tx").unwrap();
+    assert!(g.matches(Path::new("abc.[tx")));
+    assert!(!g.matches(Path::new("a/c.[tx")));
+}
+
+#[test]
+fn test_walk_sorted_and_filtered() {
+    let dir = make_tree(&[
+        ("b.txt", ""),
+        ("a.rs", ""),
+        ("sub/c.rs", ""),
+        ("sub/deep/d.txt", ""),
+        ("target/e.rs", ""),
+    ]);
+    let root = dir.path();
+
+    assert_eq!(
+        walk_names(root, &WalkOptions::default()),
+        vec!["a.rs", "b.txt", "sub/c.rs", "sub/deep/d.txt", "target/e.rs"]
+    );
+
+    let opts = WalkOptions {
+        include: vec![Glob::new("*.rs").unwrap()],
+        exclude: vec![Glob::new("target").unwrap()],
+        ..WalkOptions::default()
+    };
+    assert_eq!(walk_names(root, &opts), vec!["a.rs", "sub/c.rs"]);
+
+    let opts = WalkOptions {
+        exclude: vec![Glob::new("sub/deep").unwrap(), Glob::new("*.rs").unwrap()],
+        ..WalkOptions::default()
+    };
+    assert_eq!(walk_names(root, &opts), vec!["b.txt"]);
+}
+
+#[test]
+fn test_walk_gitignore() {
+    let dir = make_tree(&[
+        (".gitignore", "# build output\n/target/\n*.log\n!keep.log\n"),
+        (".git/HEAD", ""),
+        ("a.log", ""),
+        ("keep.log", ""),
+        ("src/main.rs", ""),
+        ("src/target/x.rs", ""),
+        ("src/.gitignore", "*.rs\n!main.rs\n"),
+        ("src/lib.rs", ""),
+        ("src/debug.log", ""),
+        ("target/out", ""),
+    ]);
+    let root = dir.path();
+
+    assert_eq!(
+        walk_names(root, &WalkOptions::default()),
+        vec![".gitignore", "keep.log", "src/.gitignore", "src/main.rs"]
+    );
+
+    let opts = WalkOptions {
+        gitignore: false,
+        ..WalkOptions::default()
+    };
+    assert_eq!(walk_names(root, &opts).len(), 10);
+}
+
+#[test]
+fn test_walk_gitignore_anchored() {
+    let dir = make_tree(&[
+        (".gitignore", "/target\n/build/\n"),
+        ("target", ""),
+        ("src/target", ""),
+        ("build/out", ""),
+        ("src/build/out", ""),
+    ]);
+    assert_eq!(
+        walk_names(dir.path(), &WalkOptions::default()),
+        vec![".gitignore", "src/build/out", "src/target"]
+    );
+}
+
+#[test]
+fn test_walk_errors_continue() {
+    let dir = make_tree(&[("a", ""), ("b", "")]);
+    let missing = dir.path().join("missing");
+    let results = walk(&missing, &WalkOptions::default());
+    assert_eq!(results.len(), 1);
+    let err = results.into_iter().next().unwrap().unwrap_err();
+    assert_eq!(err.error.kind(), io::ErrorKind::NotFound);
+
+    // ファイルを直接指定した場合はそのまま返す
+    let file = dir.path().join("a");
+    let opts = WalkOptions {
+        include: vec![Glob::new("*.rs").unwrap()],
+        ..WalkOptions::default()
+    };
+    assert_eq!(walk(&file, &opts).pop().unwrap().unwrap(), file);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_walk_symlink_loop() {
+    use std::os::unix::fs::symlink;
+
+    let dir = make_tree(&[("sub/a", ""), ("other/b", "")]);
+    let root = dir.path();
+    symlink(root, root.join("sub/loop")).unwrap();
+    symlink(root.join("other"), root.join("sub/link")).unwrap();
+    symlink(root.join("nowhere"), root.join("broken")).unwrap();
+
+    let results = walk(root, &WalkOptions::default());
+    let mut files = vec![];
+    let mut errors = vec![];
+    for r in results {
+        match r {
+            Ok(p) => files.push(to_slash(p.strip_prefix(root).unwrap())),
+            Err(e) => errors.push(e),
+        }
+    }
+
+    // ループしているリンクの先は辿らないが、ループでないリンクは辿る
+    assert_eq!(files, vec!["other/b", "sub/a", "sub/link/b"]);
+    assert_eq!(errors.len(), 2);
+    assert!(errors[0].path.ends_with("broken"));
+    assert_eq!(errors[0].error.kind(), io::ErrorKind::NotFound);
+    assert!(errors[1].path.ends_with("sub/loop"));
+    assert!(errors[1].to_string().contains("symlink loop detected"));
+}
@@ -0,0 +1,428 @@
+use filetime::FileTime;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use crate::fsutil::file_id;
+use crate::walk::{self, WalkOptions};
+
+/// ある時点のファイルの状態
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileState {
+    pub len: u64,
+    pub mtime: FileTime,
+    /// ファイルの実体を表す (デバイス, inode) の組 (名前の変更の検出に使う)
+    pub id: Option<(u64, u64)>,
+}
+
+/// root 以下のファイルの状態
+pub type Snapshot = BTreeMap<PathBuf, FileState>;
+
+/// root 以下のファイルの状態を記録する
+///
+/// 読み込めなかったファイルは、存在しないものとして扱う。
+pub fn snapshot(root: &Path, opts: &WalkOptions) -> Snapshot {
+    walk::walk(root, opts)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter_map(|path| {
+            let meta = fs::metadata(&path).ok()?;
+            let state = FileState {
+                len: meta.len(),
+                mtime: FileTime::from_last_modification_time(&meta),
+                id: file_id(&meta),
+            };
+            Some((path, state))
+        })
+        .collect()
+}
+
+/// ファイルの変更
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Event {
+    Created(PathBuf),
+    Modified(PathBuf),
+    Removed(PathBuf),
+    Renamed { from: PathBuf, to: PathBuf },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Created(p) => write!(f, "created {}", p.display()),
+            Event::Modified(p) => write!(f, "modified {}", p.display()),
+            Event::Removed(p) => write!(f, "removed {}", p.display()),
+            Event::Renamed { from, to } => {
+                write!(f, "renamed {} -> {}", from.display(), to.display())
+            }
+        }
+    }
+}
+
+/// 2 つのスナップショットの違いをイベントの列にする
+///
+/// 消えたファイルと同じ実体で、サイズと更新日時も同じファイルが現れた場合は、
+/// 名前の変更とみなす (消えたファイルの inode が新しいファイルに再利用されることがある)。
+pub fn diff_snapshots(old: &Snapshot, new: &Snapshot) -> Vec<Event> {
+    let mut events = vec![];
+    let mut removed: HashMap<(u64, u64), (&PathBuf, &FileState)> = HashMap::new();
+    for (path, state) in old {
+        if !new.contains_key(path) {
+            match state.id {
+                Some(id) => {
+                    removed.insert(id, (path, state));
+                }
+                None => events.push(Event::Removed(path.clone())),
+            }
+        }
+    }
+    for (path, state) in new {
+        match old.get(path) {
+            Some(old_state) if old_state.len != state.len || old_state.mtime != state.mtime => {
+                events.push(Event::Modified(path.clone()))
+            }
+            Some(_) => {}
+            None => match state.id.and_then(|id| {
+                let (from, old_state) = removed.get(&id)?;
+                if old_state.len != state.len || old_state.mtime != state.mtime {
+                    return None;
+                }
+                let from = *from;
+                removed.remove(&id);
+                Some(from)
+            }) {
+                Some(from) => events.push(Event::Renamed {
+                    from: from.clone(),
+                    to: path.clone(),
+                }),
+                None => events.push(Event::Created(path.clone())),
+            },
+        }
+    }
+    let mut removed: Vec<&PathBuf> = removed.into_values().map(|(p, _)| p).collect();
+    removed.sort();
+    events.extend(removed.into_iter().map(|p| Event::Removed(p.clone())));
+    events
+}
+
+/// root 以下を interval ごとに調べ、各回の変更をまとめてチャンネルに送るスレッドを開始する
+///
+/// 最初のスナップショットは戻る前にとるので、この関数から戻った後の変更はすべて通知される。
+/// 変更がなかった回も空の Vec を送るので、受信側は変更のなかった回数を数えられる。
+/// 受信側が破棄されると、次の回の送信に失敗してスレッドは終了する。
+pub fn start_watcher(
+    root: PathBuf,
+    opts: WalkOptions,
+    interval: Duration,
+) -> mpsc::Receiver<Vec<Event>> {
+    let (sender, receiver) = mpsc::channel();
+    let mut last = snapshot(&root, &opts);
+
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        let current = snapshot(&root, &opts);
+        if sender.send(diff_snapshots(&last, &current)).is_err() {
+            return;
+        }
+        last = current;
+    });
+
+    receiver
+}
+
+/// 続けて起きたイベントを、変更のない回が quiet_scans 回続くまでまとめるイテレータ
+///
+/// イベントは調べるたびにまとめて届くので、時間ではなく変更のなかった回数で区切る。
+pub struct Debounced {
+    receiver: mpsc::Receiver<Vec<Event>>,
+    quiet_scans: usize,
+}
+
+/// start_watcher の receiver のイベントを、変更のない回が quiet_scans 回続くまでまとめる
+pub fn debounce(receiver: mpsc::Receiver<Vec<Event>>, quiet_scans: usize) -> Debounced {
+    Debounced {
+        receiver,
+        quiet_scans,
+    }
+}
+
+impl Iterator for Debounced {
+    type Item = Vec<Event>;
+
+    /// 次のイベントのまとまりを返す (送信側がなくなれば None)
+    fn next(&mut self) -> Option<Vec<Event>> {
+        let mut batch = vec![];
+        while batch.is_empty() {
+            batch = self.receiver.recv().ok()?;
+        }
+        let mut quiet = 0;
+        while quiet < self.quiet_scans {
+            match self.receiver.recv() {
+                Ok(events) if events.is_empty() => quiet += 1,
+                Ok(events) => {
+                    batch.extend(events);
+                    quiet = 0;
+                }
+                Err(_) => break,
+            }
+        }
+        Some(coalesce(batch))
+    }
+}
+
+/// 同じファイルへのイベントを、起きた順に従ってまとめる
+///
+/// 重複を取り除き、作られたばかりのファイルの変更は作成に、消される前の変更は削除に含める。
+/// 作られてから消えたファイルのイベントは取り除き、消されてから作り直されたファイル
+/// (エディタが保存するときによくある) は変更とする。
+pub fn coalesce(events: Vec<Event>) -> Vec<Event> {
+    let mut out: Vec<Option<Event>> = vec![];
+    // ファイルごとの、 out の中の最後のイベントの位置
+    let mut last: HashMap<PathBuf, usize> = HashMap::new();
+
+    for event in events {
+        let path = match &event {
+            Event::Created(p) | Event::Modified(p) | Event::Removed(p) => p.clone(),
+            Event::Renamed { .. } => {
+                if !out.contains(&Some(event.clone())) {
+                    out.push(Some(event));
+                }
+                continue;
+            }
+        };
+        let prev = last.get(&path).map(|&i| (i, out[i].clone()));
+        match (prev, &event) {
+            // 作られてから消えた
+            (Some((i, Some(Event::Created(_)))), Event::Removed(_)) => {
+                out[i] = None;
+                last.remove(&path);
+            }
+            // 消されてから作り直された
+            (Some((i, Some(Event::Removed(_)))), Event::Created(_)) => {
+                out[i] = Some(Event::Modified(path));
+            }
+            // 変更されてから消えた
+            (Some((i, Some(Event::Modified(_)))), Event::Removed(_)) => {
+                out[i] = Some(event);
+            }
+            // 作られた後や変更された後の変更、同じイベントの重複
+            (Some((_, Some(Event::Created(_) | Event::Modified(_)))), Event::Modified(_)) => {}
+            (Some((_, Some(prev))), _) if prev == event => {}
+            _ => {
+                last.insert(path, out.len());
+                out.push(Some(event));
+            }
+        }
+    }
+    out.into_iter().flatten().collect()
+}
+
+/// watch のコマンドライン引数
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    #[command(flatten)]
+    pub walk: WalkOptions,
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 500,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Milliseconds between scans"
+    )]
+    pub interval: u64,
+    /// 変更は調べるたびにまとめて届くので、 interval 単位に切り上げて扱う
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 1000,
+        help = "Wait until no change is seen for MS milliseconds (rounded up to whole scans)"
+    )]
+    pub debounce: u64,
+    #[arg(long, help = "Exit after the first batch of changes")]
+    pub once: bool,
+    #[arg(default_value = ".", help = "Directory to watch")]
+    pub root: PathBuf,
+    /// 変更があるたびに実行するコマンド
+    #[arg(last = true, value_name = "COMMAND", help = "Command to run on change")]
+    pub command: Vec<String>,
+}
+
+/// watch コマンドのエントリポイント
+///
+/// 変更のまとまりごとにイベントを出力し、コマンドがあれば実行する。
+pub fn watch_main(args: Args) -> Result<i32, Box<dyn Error>> {
+    if !args.root.is_dir() {
+        return Err(format!("{}: not a directory", args.root.display()).into());
+    }
+    let receiver = start_watcher(
+        args.root.clone(),
+        args.walk.clone(),
+        Duration::from_millis(args.interval),
+    );
+    let quiet_scans = args.debounce.div_ceil(args.interval) as usize;
+    for batch in debounce(receiver, quiet_scans) {
+        for event in &batch {
+            println!("{}", event);
+        }
+        if let Some((program, rest)) = args.command.split_first() {
+            match process::Command::new(program).args(rest).status() {
+                Ok(status) if !status.success() => eprintln!("watch: {}: {}", program, status),
+                Ok(_) => {}
+                Err(e) => eprintln!("watch: {}: {}", program, e),
+            }
+        }
+        if args.once {
+            break;
+        }
+    }
+    Ok(0)
+}
+
+#[test]
+fn test_diff_snapshots() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    let opts = WalkOptions::default();
+    fs::write(root.join("keep"), "keep").unwrap();
+    fs::write(root.join("edit"), "edit").unwrap();
+    fs::write(root.join("gone"), "gone").unwrap();
+    fs::write(root.join("old_name"), "moved").unwrap();
+    let before = snapshot(root, &opts);
+    assert_eq!(before.len(), 4);
+
+    fs::write(root.join("edit"), "edited").unwrap();
+    fs::remove_file(root.join("gone")).unwrap();
+    fs::rename(root.join("old_name"), root.join("new_name")).unwrap();
+    fs::write(root.join("new"), "").unwrap();
+    let after = snapshot(root, &opts);
+
+    let events = diff_snapshots(&before, &after);
+    let mut expected = vec![
+        Event::Modified(root.join("edit")),
+        Event::Created(root.join("new")),
+        Event::Removed(root.join("gone")),
+    ];
+    if cfg!(unix) {
+        expected.insert(
+            2,
+            Event::Renamed {
+                from: root.join("old_name"),
+                to: root.join("new_name"),
+            },
+        );
+    }
+    assert_eq!(events.len(), expected.len());
+    for e in &expected {
+        assert!(events.contains(e), "{} not in {:?}", e, events);
+    }
+    assert!(diff_snapshots(&after, &after).is_empty());
+}
+
+#[test]
+fn test_coalesce() {
+    let p = |s: &str| PathBuf::from(s);
+    let events = vec![
+        Event::Created(p("a")),
+        Event::Modified(p("a")),
+        Event::Modified(p("b")),
+        Event::Modified(p("b")),
+        Event::Created(p("tmp")),
+        Event::Removed(p("tmp")),
+        Event::Removed(p("c")),
+    ];
+    assert_eq!(
+        coalesce(events),
+        vec![
+            Event::Created(p("a")),
+            Event::Modified(p("b")),
+            Event::Removed(p("c")),
+        ]
+    );
+
+    // 順番によって意味が変わる
+    let events = vec![
+        Event::Removed(p("saved")),
+        Event::Created(p("saved")),
+        Event::Modified(p("gone")),
+        Event::Removed(p("gone")),
+        Event::Created(p("back")),
+        Event::Removed(p("back")),
+        Event::Created(p("back")),
+    ];
+    assert_eq!(
+        coalesce(events),
+        vec![
+            Event::Modified(p("saved")),
+            Event::Removed(p("gone")),
+            Event::Created(p("back")),
+        ]
+    );
+}
+
+#[test]
+fn test_debounce_counts_quiet_scans() {
+    let p = |s: &str| PathBuf::from(s);
+    let (sender, receiver) = mpsc::channel();
+    for scan in [
+        vec![],
+        vec![Event::Created(p("a"))],
+        vec![],
+        vec![Event::Modified(p("b"))],
+        vec![],
+        vec![],
+        vec![Event::Removed(p("c"))],
+    ] {
+        sender.send(scan).unwrap();
+    }
+    drop(sender);
+
+    // 変更のない回が 2 回続くまでは 1 つのまとまりになる
+    let mut batches = debounce(receiver, 2);
+    assert_eq!(
+        batches.next(),
+        Some(vec![Event::Created(p("a")), Event::Modified(p("b"))])
+    );
+    assert_eq!(batches.next(), Some(vec![Event::Removed(p("c"))]));
+    assert_eq!(batches.next(), None);
+}
+
+#[test]
+fn test_watcher() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().to_path_buf();
+    fs::write(root.join("existing"), "").unwrap();
+    let receiver = start_watcher(
+        root.clone(),
+        WalkOptions::default(),
+        Duration::from_millis(10),
+    );
+    let mut batches = debounce(receiver, 20);
+
+    // 続けて書き込んだファイルは 1 つのまとまりとして通知される
+    for i in 0..3 {
+        fs::write(root.join(format!("file{}", i)), "x").unwrap();
+        thread::sleep(Duration::from_millis(20));
+    }
+    let mut events = vec![];
+    while events.len() < 3 {
+        events.extend(batches.next().unwrap());
+    }
+    assert_eq!(
+        events,
+        (0..3)
+            .map(|i| Event::Created(root.join(format!("file{}", i))))
+            .collect::<Vec<_>>()
+    );
+
+    fs::write(root.join("existing"), "changed").unwrap();
+    assert_eq!(
+        batches.next().unwrap(),
+        vec![Event::Modified(root.join("existing"))]
+    );
+}
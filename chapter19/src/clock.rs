@@ -0,0 +1,249 @@
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// 現在時刻と待機を提供する時計
+///
+/// thread::sleep を直接呼ばずにこのトレイトを通すと、テストでは ManualClock に差し替えて
+/// 実際に待たずに時間を進められる。
+pub trait Clock: Send + Sync {
+    /// 現在時刻
+    fn now(&self) -> Instant;
+    /// d だけ待つ
+    fn sleep(&self, d: Duration);
+}
+
+/// 実際の時刻を使う時計
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, d: Duration) {
+        thread::sleep(d);
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    /// 時計を作ってから進めた時間
+    elapsed: Duration,
+    /// sleep で待っているスレッドの数
+    sleepers: usize,
+}
+
+/// テスト用の、手で進める時計
+///
+/// new で作った時計の sleep は、 advance で時刻が進むまで戻らない。
+/// auto_advance で作った時計の sleep はすぐに戻り、そのぶん時刻を進める。
+#[derive(Debug)]
+pub struct ManualClock {
+    start: Instant,
+    state: Mutex<State>,
+    changed: Condvar,
+    auto_advance: bool,
+}
+
+impl ManualClock {
+    fn with_mode(auto_advance: bool) -> ManualClock {
+        ManualClock {
+            start: Instant::now(),
+            state: Mutex::new(State {
+                elapsed: Duration::ZERO,
+                sleepers: 0,
+            }),
+            changed: Condvar::new(),
+            auto_advance,
+        }
+    }
+
+    /// advance を呼ぶまで時刻が進まない時計を作る
+    pub fn new() -> ManualClock {
+        ManualClock::with_mode(false)
+    }
+
+    /// sleep するたびに、待つ時間だけ時刻が進む時計を作る
+    ///
+    /// 複数のスレッドが同時に sleep しても時刻は重ならず、順に足される。
+    /// そのため並列に sleep すると実際の時計よりも時刻が進む
+    /// (4 つのスレッドで 1 秒ずつ 8 回 sleep すると、 2 秒ではなく 8 秒進む)。
+    /// 並列な処理の経過時間を確かめるテストでは、 new で作って advance で進める。
+    pub fn auto_advance() -> ManualClock {
+        ManualClock::with_mode(true)
+    }
+
+    /// 時刻を d だけ進め、待ち終わったスレッドを起こす
+    pub fn advance(&self, d: Duration) {
+        self.state.lock().unwrap().elapsed += d;
+        self.changed.notify_all();
+    }
+
+    /// 時計を作ってから進めた時間
+    pub fn elapsed(&self) -> Duration {
+        self.state.lock().unwrap().elapsed
+    }
+
+    /// sleep で待っているスレッドの数
+    pub fn sleepers(&self) -> usize {
+        self.state.lock().unwrap().sleepers
+    }
+
+    /// n 個以上のスレッドが sleep で待つようになるまで待つ
+    ///
+    /// advance の前に呼ぶと、他のスレッドが sleep に入る前に時刻を進めてしまうことがない。
+    pub fn wait_for_sleepers(&self, n: usize) {
+        let mut state = self.state.lock().unwrap();
+        while state.sleepers < n {
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn sleep(&self, d: Duration) {
+        let mut state = self.state.lock().unwrap();
+        if self.auto_advance {
+            state.elapsed += d;
+            drop(state);
+            self.changed.notify_all();
+            return;
+        }
+        let deadline = state.elapsed + d;
+        state.sleepers += 1;
+        self.changed.notify_all();
+        while state.elapsed < deadline {
+            state = self.changed.wait(state).unwrap();
+        }
+        state.sleepers -= 1;
+    }
+}
+
+/// d だけ経った時刻を 1 度だけ送るチャンネルを返す
+///
+/// 送信するスレッドは時計が d だけ進むまで sleep で止まっている。
+/// ManualClock を使う場合は、進めない限り受信側を破棄してもスレッドは終了しない。
+pub fn after(clock: Arc<dyn Clock>, d: Duration) -> mpsc::Receiver<Instant> {
+    let (sender, receiver) = mpsc::sync_channel(1);
+    thread::spawn(move || {
+        clock.sleep(d);
+        let _ = sender.send(clock.now());
+    });
+    receiver
+}
+
+/// period ごとに時刻を送るチャンネルを返す
+///
+/// 送る時刻は開始から period の倍数の時刻に合わせるので、送信にかかる時間で周期がずれていくことはない。
+/// 受信側が遅ければ、次の時刻は受信されるまで送られず、その間に過ぎた周期は飛ばす。
+/// 受信側が破棄されると、次の周期で送ろうとしたときにスレッドは終了する
+/// (ManualClock を使う場合は、次の周期まで進めない限り終了しない)。
+pub fn tick(clock: Arc<dyn Clock>, period: Duration) -> mpsc::Receiver<Instant> {
+    let (sender, receiver) = mpsc::sync_channel(1);
+    thread::spawn(move || {
+        let mut next = clock.now();
+        loop {
+            next += period;
+            let now = clock.now();
+            while next < now {
+                next += period;
+            }
+            clock.sleep(next.saturating_duration_since(now));
+            if sender.send(clock.now()).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+#[test]
+fn test_manual_clock() {
+    let clock = Arc::new(ManualClock::new());
+    let start = clock.now();
+    let sleeper = {
+        let clock = clock.clone();
+        thread::spawn(move || {
+            clock.sleep(Duration::from_secs(10));
+            clock.now()
+        })
+    };
+    clock.wait_for_sleepers(1);
+    // まだ時刻が足りないので起きない
+    clock.advance(Duration::from_secs(9));
+    assert_eq!(clock.sleepers(), 1);
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(sleeper.join().unwrap() - start, Duration::from_secs(10));
+    assert_eq!(clock.sleepers(), 0);
+}
+
+#[test]
+fn test_auto_advance() {
+    let clock = ManualClock::auto_advance();
+    let real = Instant::now();
+    clock.sleep(Duration::from_secs(3600));
+    clock.sleep(Duration::from_secs(1));
+    assert_eq!(clock.elapsed(), Duration::from_secs(3601));
+    assert!(real.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn test_after_and_tick() {
+    let clock = Arc::new(ManualClock::new());
+    let start = clock.now();
+
+    let timeout = after(clock.clone(), Duration::from_secs(5));
+    let ticks = tick(clock.clone(), Duration::from_secs(2));
+    clock.wait_for_sleepers(2);
+    assert!(timeout.try_recv().is_err());
+    assert!(ticks.try_recv().is_err());
+
+    clock.advance(Duration::from_secs(2));
+    assert_eq!(ticks.recv().unwrap() - start, Duration::from_secs(2));
+    // tick は次の周期を待ち、 after はまだ待っている
+    clock.wait_for_sleepers(2);
+    clock.advance(Duration::from_secs(2));
+    assert_eq!(ticks.recv().unwrap() - start, Duration::from_secs(4));
+    clock.wait_for_sleepers(2);
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(timeout.recv().unwrap() - start, Duration::from_secs(5));
+    // after は 1 度だけ送る
+    assert!(timeout.recv().is_err());
+
+    // 遅れて起きても、次は周期の倍数の時刻に送る (送った時刻から period 待つのではない)
+    let recv = || ticks.recv_timeout(Duration::from_secs(10)).unwrap() - start;
+    clock.wait_for_sleepers(1);
+    clock.advance(Duration::from_secs(2));
+    assert_eq!(recv(), Duration::from_secs(7));
+    clock.wait_for_sleepers(1);
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(recv(), Duration::from_secs(8));
+    // 周期をまたいで遅れた場合、過ぎた周期は飛ばす
+    clock.wait_for_sleepers(1);
+    clock.advance(Duration::from_secs(5));
+    assert_eq!(recv(), Duration::from_secs(13));
+    clock.wait_for_sleepers(1);
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(recv(), Duration::from_secs(14));
+
+    // 受信側を破棄して次の周期まで進めると、 tick のスレッドは終了する
+    drop(ticks);
+    clock.wait_for_sleepers(1);
+    clock.advance(Duration::from_secs(2));
+    while clock.sleepers() > 0 {
+        thread::yield_now();
+    }
+}
@@ -1,11 +1,26 @@
 #![allow(unused)]
-use rayon::prelude::*;
+mod clock;
+mod pipeline;
+mod pool;
+
+use clock::{Clock, ManualClock, SystemClock};
+use pool::ThreadPool;
 use std::io;
 use std::sync::Arc;
 use std::{thread, time};
 
 fn main() {
     println!("Hello, world!");
+
+    let pool = ThreadPool::new(0);
+    let to = Arc::new("Taro".to_string());
+    let greets = vec!["Hello".to_string(), "こんにちは".to_string()];
+    if let Err(errors) = greet_all(&pool, Arc::new(SystemClock), &to, greets) {
+        for e in errors {
+            eprintln!("{}", e);
+        }
+        std::process::exit(1);
+    }
 }
 
 #[test]
@@ -24,38 +39,42 @@ fn test_greeeeet() {
     // 複数のスレッドで共有したい不変の値
     let to: Arc<String> = Arc::new("Taro".to_string());
 
-    // spawn で並列処理
-    // by_spawn(&to, greets);
-
-    // rayon で並列処理
-    by_rayon(&to, greets).unwrap();
+    // スレッドプールで並列処理
+    // 実際には待たない時計を使う
+    // (並列の sleep は重ならずに足されるので、 clock.elapsed() は実際の経過時間の目安にならない)
+    let pool = ThreadPool::new(4);
+    let real = time::Instant::now();
+    greet_all(&pool, Arc::new(ManualClock::auto_advance()), &to, greets).unwrap();
+    assert!(real.elapsed() < time::Duration::from_secs(1));
 }
-fn by_spawn(to: &Arc<String>, greets: Vec<String>) {
-    let mut thread_handles = vec![];
-    for g in greets {
-        // clone して使う
-        // 完全な値のコピーではなく、スマートポインタ Arc のコピー
-        let to_c = to.clone();
-        // 後で join するために spawn の返り値 `JoinHandle` をベクタに詰めている
-        thread_handles.push(thread::spawn(move || sleep_and_say(&to_c, &g)));
-    }
 
-    for handle in thread_handles {
-        handle.join().unwrap().unwrap()
-    }
+#[test]
+fn test_greet_nobody() {
+    let to: Arc<String> = Arc::new("Taro".to_string());
+    let pool = ThreadPool::new(4);
+    assert!(greet_all(&pool, Arc::new(ManualClock::new()), &to, vec![]).is_ok());
 }
 
-fn by_rayon(to: &Arc<String>, greets: Vec<String>) -> io::Result<()> {
-    greets
-        .par_iter()
-        .map(|greet| sleep_and_say(to, greet))
-        .reduce_with(|r1, r2| if r1.is_err() { r1 } else { r2 })
-        .unwrap()
+/// 挨拶をスレッドプールで並列に出力する
+///
+/// 挨拶ごとにスレッドを作らず、プールのワーカーで順に処理する。
+/// 失敗した挨拶があれば、そのすべてのエラーを返す。
+fn greet_all(
+    pool: &ThreadPool,
+    clock: Arc<dyn Clock>,
+    to: &Arc<String>,
+    greets: Vec<String>,
+) -> Result<(), Vec<io::Error>> {
+    // clone して使う
+    // 完全な値のコピーではなく、スマートポインタ Arc のコピー
+    let to = to.clone();
+    pool.try_map(greets, move |g| sleep_and_say(clock.as_ref(), &to, &g))
+        .map(|_| ())
 }
 
-fn sleep_and_say(to: &Arc<String>, greet: &str) -> io::Result<()> {
+fn sleep_and_say(clock: &dyn Clock, to: &Arc<String>, greet: &str) -> io::Result<()> {
     let one_sec = time::Duration::from_secs(1);
-    thread::sleep(one_sec);
+    clock.sleep(one_sec);
     println!("{}, {}", greet, to);
     Ok(())
 }
@@ -64,18 +83,41 @@ use std::sync::mpsc;
 
 #[test]
 fn test_channel() {
-    let r = start_sender();
-    message_printer(r)
+    let clock = Arc::new(ManualClock::auto_advance());
+    let r = start_sender(clock.clone());
+    assert_eq!(message_printer(clock.as_ref(), r), 10);
+}
+
+#[test]
+fn test_sender_follows_clock() {
+    let clock = Arc::new(ManualClock::new());
+    let r = start_sender(clock.clone());
+
+    // 1 秒経つまでは何も送られない
+    clock.wait_for_sleepers(1);
+    assert!(r.try_recv().is_err());
+    for i in 0..3 {
+        clock.advance(time::Duration::from_secs(1));
+        assert_eq!(r.recv().unwrap(), format!("message {}", i));
+        clock.wait_for_sleepers(1);
+    }
+
+    // 送信側は sleep で止まっているので、受信側を破棄してから時計を進めて終了させる
+    drop(r);
+    clock.advance(time::Duration::from_secs(1));
+    while clock.sleepers() > 0 {
+        thread::yield_now();
+    }
 }
 
 /// 10 件のメッセージを 1 秒ごとに送信する
-fn start_sender() -> mpsc::Receiver<String> {
+fn start_sender(clock: Arc<dyn Clock>) -> mpsc::Receiver<String> {
     let (sender, receiver) = mpsc::sync_channel(3);
 
     thread::spawn(move || {
         let one_sec = time::Duration::from_secs(1);
         for i in 0..10 {
-            thread::sleep(one_sec);
+            clock.sleep(one_sec);
             let msg = format!("message {}", i);
             println!("send '{}'", msg);
             if sender.send(msg).is_err() {
@@ -87,13 +129,16 @@ fn start_sender() -> mpsc::Receiver<String> {
     receiver
 }
 
-/// 3 秒ごとにメッセージを読み込んで出力する
-fn message_printer(r: mpsc::Receiver<String>) {
+/// 3 秒ごとにメッセージを読み込んで出力し、出力した件数を返す
+fn message_printer(clock: &dyn Clock, r: mpsc::Receiver<String>) -> usize {
     let five_sec = time::Duration::from_secs(3);
+    let mut count = 0;
     for m in r {
-        thread::sleep(five_sec);
-        println!("{}", m)
+        clock.sleep(five_sec);
+        println!("{}", m);
+        count += 1;
     }
+    count
 }
 
 use std::sync::Mutex;
@@ -0,0 +1,496 @@
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::pool::panic_message;
+
+/// ステージで起きたエラー
+///
+/// エラーは下流のステージへ値と同じチャンネルで流れ、 sink で集められる。
+#[derive(Debug, Clone, PartialEq)]
+pub enum StageError {
+    /// ステージの関数が Err を返した (その値は捨てて、次の値の処理を続ける)
+    Failed { stage: String, message: String },
+    /// ステージの関数が panic した (そのステージは終了する)
+    Panicked { stage: String, message: String },
+}
+
+impl fmt::Display for StageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StageError::Failed { stage, message } => write!(f, "{}: {}", stage, message),
+            StageError::Panicked { stage, message } => {
+                write!(f, "{}: panicked: {}", stage, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for StageError {}
+
+/// チャンネルを流れる値
+type Item<T> = Result<T, StageError>;
+
+/// 1 つのステージ (スレッド) の処理量
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageReport {
+    pub name: String,
+    /// 受け取った値の数 (上流から流れてきたエラーは含まない)
+    pub items_in: u64,
+    /// 下流に送った値の数
+    pub items_out: u64,
+    /// ステージの関数を実行していた時間
+    pub busy: Duration,
+    /// ステージのスレッドが動いていた時間
+    pub elapsed: Duration,
+}
+
+impl StageReport {
+    fn new(name: &str) -> StageReport {
+        StageReport {
+            name: name.to_string(),
+            items_in: 0,
+            items_out: 0,
+            busy: Duration::ZERO,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    /// 1 秒あたりに処理した値の数
+    pub fn throughput(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs == 0.0 {
+            0.0
+        } else {
+            self.items_in as f64 / secs
+        }
+    }
+}
+
+impl fmt::Display for StageReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} in, {} out, busy {:?} of {:?} ({:.1} items/s)",
+            self.name,
+            self.items_in,
+            self.items_out,
+            self.busy,
+            self.elapsed,
+            self.throughput()
+        )
+    }
+}
+
+/// パイプラインを最後まで実行した結果
+#[derive(Debug)]
+pub struct Outcome<T> {
+    /// sink の結果
+    pub value: T,
+    /// 途中のステージで起きたエラー (起きた順)
+    pub errors: Vec<StageError>,
+    /// 上流から順に並べた、各ステージの処理量
+    pub stages: Vec<StageReport>,
+}
+
+impl<T> Outcome<T> {
+    /// エラーがなければ sink の結果を、あればすべてのエラーを返す
+    pub fn into_result(self) -> Result<T, Vec<StageError>> {
+        if self.errors.is_empty() {
+            Ok(self.value)
+        } else {
+            Err(self.errors)
+        }
+    }
+}
+
+/// ステージの関数が 1 つの値に対して返す結果
+enum Step<U> {
+    Emit(U),
+    Skip,
+    Fail(String),
+}
+
+/// recv で受け取った値に f を適用して output に送るループ
+///
+/// 上流のエラーはそのまま下流に流す。 f が panic したら、そのことを下流に伝えて終了する。
+/// 下流のステージが終了していたら (送信に失敗したら) 終了する。
+fn run_stage<T, U>(
+    name: &str,
+    mut recv: impl FnMut() -> Option<Item<T>>,
+    output: SyncSender<Item<U>>,
+    mut f: impl FnMut(T) -> Step<U>,
+) -> StageReport {
+    let start = Instant::now();
+    let mut report = StageReport::new(name);
+    while let Some(item) = recv() {
+        let out = match item {
+            Err(e) => Err(e),
+            Ok(value) => {
+                report.items_in += 1;
+                let t = Instant::now();
+                let step = panic::catch_unwind(AssertUnwindSafe(|| f(value)));
+                report.busy += t.elapsed();
+                match step {
+                    Ok(Step::Emit(u)) => {
+                        report.items_out += 1;
+                        Ok(u)
+                    }
+                    Ok(Step::Skip) => continue,
+                    Ok(Step::Fail(message)) => Err(StageError::Failed {
+                        stage: name.to_string(),
+                        message,
+                    }),
+                    Err(payload) => {
+                        let _ = output.send(Err(StageError::Panicked {
+                            stage: name.to_string(),
+                            message: panic_message(payload),
+                        }));
+                        break;
+                    }
+                }
+            }
+        };
+        if output.send(out).is_err() {
+            break;
+        }
+    }
+    report.elapsed = start.elapsed();
+    report
+}
+
+/// 各ステージを別のスレッドで実行し、容量の決まったチャンネル (sync_channel) でつなぐパイプライン
+///
+/// start_sender と message_printer のように 1 つの送信側と 1 つの受信側に限らず、
+/// `source -> map -> filter -> sink` のようにステージをつなげられる。
+/// 下流のステージが遅ければ、チャンネルがいっぱいになって上流のステージは待たされる。
+pub struct Pipeline<T> {
+    receiver: Receiver<Item<T>>,
+    /// 上流から順に並べた、各ステージのスレッド
+    stages: Vec<JoinHandle<StageReport>>,
+    /// ステージの間のチャンネルの容量
+    capacity: usize,
+}
+
+/// iter の値を流すパイプラインを作る
+///
+/// capacity はステージの間のチャンネルの容量 (0 なら送信側と受信側が直接受け渡す)。
+pub fn source<I>(name: &str, iter: I, capacity: usize) -> Pipeline<I::Item>
+where
+    I: IntoIterator + Send + 'static,
+    I::Item: Send + 'static,
+{
+    let (sender, receiver) = mpsc::sync_channel(capacity);
+    let name = name.to_string();
+    let handle = thread::spawn(move || {
+        let mut iter = iter.into_iter();
+        let mut next = || iter.next().map(Ok);
+        run_stage(&name, &mut next, sender, Step::Emit)
+    });
+    Pipeline {
+        receiver,
+        stages: vec![handle],
+        capacity,
+    }
+}
+
+/// 複数のパイプラインの値を 1 つにまとめる (fan-in)
+///
+/// 値の順序は、それぞれのパイプラインの中でだけ保たれる。
+pub fn merge<T: Send + 'static>(name: &str, inputs: Vec<Pipeline<T>>) -> Pipeline<T> {
+    let capacity = inputs.iter().map(|p| p.capacity).max().unwrap_or(0);
+    let (sender, receiver) = mpsc::sync_channel(capacity);
+    let mut stages = vec![];
+    for (i, input) in inputs.into_iter().enumerate() {
+        stages.extend(input.stages);
+        let sender = sender.clone();
+        let name = format!("{}[{}]", name, i);
+        let input = input.receiver;
+        stages.push(thread::spawn(move || {
+            run_stage(&name, || input.recv().ok(), sender, Step::Emit)
+        }));
+    }
+    Pipeline {
+        receiver,
+        stages,
+        capacity,
+    }
+}
+
+impl<T: Send + 'static> Pipeline<T> {
+    /// 1 つのスレッドで実行するステージを加える
+    fn then<U, F>(mut self, name: &str, f: F) -> Pipeline<U>
+    where
+        U: Send + 'static,
+        F: FnMut(T) -> Step<U> + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(self.capacity);
+        let name = name.to_string();
+        let input = self.receiver;
+        self.stages.push(thread::spawn(move || {
+            run_stage(&name, || input.recv().ok(), sender, f)
+        }));
+        Pipeline {
+            receiver,
+            stages: self.stages,
+            capacity: self.capacity,
+        }
+    }
+
+    /// 各値を f で変換する
+    pub fn map<U, F>(self, name: &str, mut f: F) -> Pipeline<U>
+    where
+        U: Send + 'static,
+        F: FnMut(T) -> U + Send + 'static,
+    {
+        self.then(name, move |value| Step::Emit(f(value)))
+    }
+
+    /// 各値を f で変換し、 Err になった値はエラーとして下流に流す
+    pub fn try_map<U, E, F>(self, name: &str, mut f: F) -> Pipeline<U>
+    where
+        U: Send + 'static,
+        E: fmt::Display,
+        F: FnMut(T) -> Result<U, E> + Send + 'static,
+    {
+        self.then(name, move |value| match f(value) {
+            Ok(u) => Step::Emit(u),
+            Err(e) => Step::Fail(e.to_string()),
+        })
+    }
+
+    /// f が true を返す値だけを残す
+    pub fn filter<F>(self, name: &str, mut f: F) -> Pipeline<T>
+    where
+        F: FnMut(&T) -> bool + Send + 'static,
+    {
+        self.then(name, move |value| {
+            if f(&value) {
+                Step::Emit(value)
+            } else {
+                Step::Skip
+            }
+        })
+    }
+
+    /// workers 個のスレッドで並列に f を適用し、結果を 1 つのチャンネルにまとめる (fan-out と fan-in)
+    ///
+    /// 値の順序は保たれない。
+    pub fn fan_out<U, F>(mut self, name: &str, workers: usize, f: F) -> Pipeline<U>
+    where
+        U: Send + 'static,
+        F: Fn(T) -> U + Send + Sync + 'static,
+    {
+        assert!(workers > 0, "fan_out needs at least one worker");
+        let (sender, receiver) = mpsc::sync_channel(self.capacity);
+        // ワーカーは 1 つの受信側を共有し、 Mutex で取り合う
+        let input = Arc::new(Mutex::new(self.receiver));
+        let f = Arc::new(f);
+        for i in 0..workers {
+            let input = input.clone();
+            let sender = sender.clone();
+            let f = f.clone();
+            let name = format!("{}[{}]", name, i);
+            self.stages.push(thread::spawn(move || {
+                // ロックは受信する間だけ持つ
+                let recv = || input.lock().unwrap().recv().ok();
+                run_stage(&name, recv, sender, |value| Step::Emit(f(value)))
+            }));
+        }
+        Pipeline {
+            receiver,
+            stages: self.stages,
+            capacity: self.capacity,
+        }
+    }
+
+    /// すべてのステージの終了を待ち、処理量を集める
+    fn join(stages: Vec<JoinHandle<StageReport>>) -> Vec<StageReport> {
+        stages
+            .into_iter()
+            // ステージの関数の panic は run_stage で捕まえているので、スレッド自体は panic しない
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    }
+
+    /// 各値を f に渡すステージを加え、パイプライン全体が終わるのを待つ
+    pub fn for_each<F>(self, name: &str, mut f: F) -> Outcome<()>
+    where
+        F: FnMut(T) + Send + 'static,
+    {
+        // sink は値を下流に送らず、上流と自分のエラーだけが呼び出し元に届く
+        let outcome = self
+            .then(name, move |value| {
+                f(value);
+                Step::<()>::Skip
+            })
+            .collect();
+        Outcome {
+            value: (),
+            errors: outcome.errors,
+            stages: outcome.stages,
+        }
+    }
+
+    /// すべての値を呼び出し元のスレッドで集め、パイプライン全体が終わるのを待つ
+    pub fn collect(self) -> Outcome<Vec<T>> {
+        let mut values = vec![];
+        let mut errors = vec![];
+        for item in self.receiver {
+            match item {
+                Ok(value) => values.push(value),
+                Err(e) => errors.push(e),
+            }
+        }
+        Outcome {
+            value: values,
+            errors,
+            stages: Self::join(self.stages),
+        }
+    }
+}
+
+#[test]
+fn test_source_map_filter_collect() {
+    let outcome = source("numbers", 1..=10, 2)
+        .map("square", |n: u64| n * n)
+        .filter("even", |n| n % 2 == 0)
+        .collect();
+    assert!(outcome.errors.is_empty());
+    assert_eq!(outcome.value, vec![4, 16, 36, 64, 100]);
+
+    let names: Vec<&str> = outcome.stages.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, vec!["numbers", "square", "even"]);
+    assert_eq!(outcome.stages[1].items_in, 10);
+    assert_eq!(outcome.stages[2].items_in, 10);
+    assert_eq!(outcome.stages[2].items_out, 5);
+}
+
+#[test]
+fn test_empty_source() {
+    let outcome = source("empty", Vec::<i32>::new(), 1)
+        .map("double", |n| n * 2)
+        .collect();
+    assert_eq!(outcome.into_result(), Ok(vec![]));
+}
+
+#[test]
+fn test_errors_flow_downstream() {
+    let outcome = source("words", vec!["1", "two", "3", "four"], 1)
+        .try_map("parse", |s: &str| s.parse::<i32>())
+        .map("double", |n| n * 2)
+        .collect();
+    assert_eq!(outcome.value, vec![2, 6]);
+    assert_eq!(
+        outcome.errors,
+        vec![
+            StageError::Failed {
+                stage: "parse".to_string(),
+                message: "invalid digit found in string".to_string()
+            };
+            2
+        ]
+    );
+    // エラーは下流のステージの関数には渡らない
+    assert_eq!(outcome.stages[2].items_in, 2);
+    assert!(outcome.into_result().is_err());
+}
+
+#[test]
+fn test_panic_propagates() {
+    let outcome = source("numbers", 0.., 1)
+        .map("explode", |n: u32| {
+            if n == 3 {
+                panic!("cannot handle {}", n);
+            }
+            n
+        })
+        .collect();
+    // panic したステージは終了し、上流の無限の source も送信に失敗して終了する
+    assert_eq!(outcome.value, vec![0, 1, 2]);
+    assert_eq!(
+        outcome.errors,
+        vec![StageError::Panicked {
+            stage: "explode".to_string(),
+            message: "cannot handle 3".to_string()
+        }]
+    );
+
+    // sink の panic も呼び出し元に届く
+    let outcome = source("numbers", 0..5, 1).for_each("printer", |n| {
+        if n == 2 {
+            panic!("printer jammed");
+        }
+    });
+    assert_eq!(
+        outcome.errors,
+        vec![StageError::Panicked {
+            stage: "printer".to_string(),
+            message: "printer jammed".to_string()
+        }]
+    );
+    assert_eq!(outcome.stages[1].items_in, 3);
+}
+
+#[test]
+fn test_fan_out_and_merge() {
+    let outcome = source("numbers", 0..100u64, 4)
+        .fan_out("slow square", 4, |n| {
+            thread::sleep(Duration::from_millis(1));
+            n * n
+        })
+        .collect();
+    let mut squares = outcome.value;
+    squares.sort();
+    assert_eq!(squares, (0..100u64).map(|n| n * n).collect::<Vec<_>>());
+    let workers: Vec<&StageReport> = outcome
+        .stages
+        .iter()
+        .filter(|s| s.name.starts_with("slow square["))
+        .collect();
+    assert_eq!(workers.len(), 4);
+    assert_eq!(workers.iter().map(|s| s.items_in).sum::<u64>(), 100);
+
+    let odds = source("odds", (1..10).step_by(2), 1);
+    let evens = source("evens", (0..10).step_by(2), 1);
+    let mut all = merge("merge", vec![odds, evens])
+        .collect()
+        .into_result()
+        .unwrap();
+    all.sort();
+    assert_eq!(all, (0..10).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_for_each_and_backpressure() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let produced = Arc::new(AtomicUsize::new(0));
+    let max_ahead = Arc::new(AtomicUsize::new(0));
+    let consumed = Arc::new(AtomicUsize::new(0));
+    let (p, c, m) = (produced.clone(), consumed.clone(), max_ahead.clone());
+    let outcome = source("fast", 0..50, 2)
+        .map("count", move |n| {
+            p.fetch_add(1, Ordering::SeqCst);
+            n
+        })
+        .for_each("slow sink", move |_| {
+            thread::sleep(Duration::from_millis(1));
+            let done = c.fetch_add(1, Ordering::SeqCst) + 1;
+            let ahead = produced.load(Ordering::SeqCst).saturating_sub(done);
+            m.fetch_max(ahead, Ordering::SeqCst);
+        });
+    assert!(outcome.errors.is_empty());
+    assert_eq!(consumed.load(Ordering::SeqCst), 50);
+    // 上流は、チャンネルの容量と処理中の値の分しか先に進めない
+    assert!(max_ahead.load(Ordering::SeqCst) <= 2 + 2);
+    let sink = outcome.stages.last().unwrap();
+    assert_eq!(sink.name, "slow sink");
+    assert_eq!(sink.items_in, 50);
+    assert!(sink.busy >= Duration::from_millis(50));
+    assert!(sink.throughput() > 0.0);
+    assert!(sink.to_string().starts_with("slow sink: 50 in, 0 out"));
+}
@@ -0,0 +1,405 @@
+use std::any::Any;
+use std::fmt;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// ジョブが結果を返さなかった理由
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobError {
+    /// ジョブが panic した (panic のメッセージ)
+    Panicked(String),
+    /// 実行される前にキャンセルされた
+    Cancelled,
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JobError::Panicked(msg) => write!(f, "job panicked: {}", msg),
+            JobError::Cancelled => write!(f, "job cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for JobError {}
+
+impl From<JobError> for io::Error {
+    fn from(e: JobError) -> Self {
+        io::Error::other(e)
+    }
+}
+
+/// panic の値からメッセージを取り出す
+pub(crate) fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// submit したジョブの結果を受け取るためのハンドル
+pub struct JobHandle<T> {
+    receiver: mpsc::Receiver<Result<T, JobError>>,
+    token: CancelToken,
+}
+
+impl<T> JobHandle<T> {
+    /// ジョブが終わるのを待って結果を返す
+    pub fn join(self) -> Result<T, JobError> {
+        // ジョブが実行されずに捨てられると、送信側がなくなる
+        self.receiver.recv().unwrap_or(Err(JobError::Cancelled))
+    }
+
+    /// ジョブがまだ始まっていなければキャンセルする
+    ///
+    /// submit_with で渡したトークンを使っている場合は、同じトークンのジョブもすべてキャンセルされる。
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+}
+
+/// ジョブのまとまりをキャンセルするためのトークン
+///
+/// 同じトークンで submit_with したジョブは、 cancel するとまだ始まっていないものが実行されなくなる。
+/// 実行中のジョブは止まらないので、長いジョブはトークンを持ち込んで自分で確かめる。
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// 決まった数のワーカースレッドでジョブを実行するスレッドプール
+///
+/// by_spawn のようにジョブごとにスレッドを作らず、キューに入れたジョブをワーカーが順に取り出して実行する。
+/// キャンセルは CancelToken ごとなので、あるジョブのまとまりをキャンセルしても、プールはそのまま使い続けられる。
+/// drop すると、キューに残っているジョブがすべて終わるのを待つ。
+pub struct ThreadPool {
+    sender: Option<mpsc::Sender<Job>>,
+    workers: Vec<thread::JoinHandle<()>>,
+    /// shutdown_now で立て、キューに残っているジョブを捨てさせる
+    stopping: Arc<AtomicBool>,
+}
+
+impl ThreadPool {
+    /// size 個のワーカーを持つスレッドプールを作る (0 なら CPU 数)
+    pub fn new(size: usize) -> ThreadPool {
+        let size = match size {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
+        let (sender, receiver) = mpsc::channel::<Job>();
+        // ワーカーは 1 つのキューを共有し、 Mutex で取り合う
+        let receiver = Arc::new(Mutex::new(receiver));
+        let stopping = Arc::new(AtomicBool::new(false));
+
+        let workers = (0..size)
+            .map(|_| {
+                let receiver = receiver.clone();
+                let stopping = stopping.clone();
+                thread::spawn(move || loop {
+                    // ロックはジョブを取り出す間だけ持つ
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        // shutdown_now の後のジョブは実行せずに捨てる
+                        Ok(job) if !stopping.load(Ordering::SeqCst) => job(),
+                        Ok(_) => {}
+                        Err(_) => break,
+                    }
+                })
+            })
+            .collect();
+
+        ThreadPool {
+            sender: Some(sender),
+            workers,
+            stopping,
+        }
+    }
+
+    /// ワーカーの数
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// ジョブをキューに入れる
+    ///
+    /// ジョブが panic してもワーカーは止まらず、 JobHandle::join が JobError::Panicked を返す。
+    pub fn submit<F, T>(&self, f: F) -> JobHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.submit_with(&CancelToken::new(), f)
+    }
+
+    /// token でキャンセルできるジョブをキューに入れる
+    ///
+    /// ジョブの順番が来たときに token がキャンセルされていれば、実行せずに JobError::Cancelled にする。
+    pub fn submit_with<F, T>(&self, token: &CancelToken, f: F) -> JobHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        let job_token = token.clone();
+        let job = Box::new(move || {
+            if job_token.is_cancelled() {
+                return;
+            }
+            let result = panic::catch_unwind(AssertUnwindSafe(f))
+                .map_err(|payload| JobError::Panicked(panic_message(payload)));
+            // 結果を待たずにハンドルが捨てられていてもかまわない
+            let _ = sender.send(result);
+        });
+        if !token.is_cancelled() {
+            // ワーカーは drop されるまで受信し続けるので、送信は失敗しない
+            self.sender.as_ref().unwrap().send(job).unwrap();
+        }
+        JobHandle {
+            receiver,
+            token: token.clone(),
+        }
+    }
+
+    /// items のそれぞれに f を並列に適用し、すべての結果を items の順に返す
+    ///
+    /// 失敗があれば、最初の 1 つだけでなくすべてのエラーを items の順に返す。
+    /// panic やキャンセルも JobError からの変換でエラーに含める。 items が空なら Ok(空の Vec) 。
+    pub fn try_map<I, F, T, E>(&self, items: I, f: F) -> Result<Vec<T>, Vec<E>>
+    where
+        I: IntoIterator,
+        I::Item: Send + 'static,
+        F: Fn(I::Item) -> Result<T, E> + Send + Sync + 'static,
+        T: Send + 'static,
+        E: From<JobError> + Send + 'static,
+    {
+        self.try_map_with(&CancelToken::new(), items, f)
+    }
+
+    /// token でキャンセルできる try_map
+    ///
+    /// 別のスレッドから token をキャンセルすると、まだ始まっていない items は JobError::Cancelled になる。
+    pub fn try_map_with<I, F, T, E>(
+        &self,
+        token: &CancelToken,
+        items: I,
+        f: F,
+    ) -> Result<Vec<T>, Vec<E>>
+    where
+        I: IntoIterator,
+        I::Item: Send + 'static,
+        F: Fn(I::Item) -> Result<T, E> + Send + Sync + 'static,
+        T: Send + 'static,
+        E: From<JobError> + Send + 'static,
+    {
+        let f = Arc::new(f);
+        let handles: Vec<JobHandle<Result<T, E>>> = items
+            .into_iter()
+            .map(|item| {
+                let f = f.clone();
+                self.submit_with(token, move || f(item))
+            })
+            .collect();
+
+        let mut values = vec![];
+        let mut errors = vec![];
+        for handle in handles {
+            match handle.join() {
+                Ok(Ok(value)) => values.push(value),
+                Ok(Err(e)) => errors.push(e),
+                Err(e) => errors.push(E::from(e)),
+            }
+        }
+        if errors.is_empty() {
+            Ok(values)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// キューに残っているジョブがすべて終わるのを待ってから、ワーカーを終了する
+    pub fn shutdown(self) {
+        // 実際の処理は drop で行う
+    }
+
+    /// 残っているジョブをキャンセルし、実行中のジョブが終わるのを待ってワーカーを終了する
+    pub fn shutdown_now(self) {
+        self.stopping.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // 送信側を閉じると、ワーカーはキューが空になった時点でループを抜ける
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            // ジョブの panic は catch_unwind で捕まえているので、ワーカー自体は panic しない
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+use std::time::Duration;
+
+#[test]
+fn test_submit_and_join() {
+    let pool = ThreadPool::new(3);
+    assert_eq!(pool.size(), 3);
+    let handles: Vec<_> = (0..10).map(|i| pool.submit(move || i * i)).collect();
+    let results: Vec<i32> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+    assert_eq!(results, (0..10).map(|i| i * i).collect::<Vec<_>>());
+
+    assert!(ThreadPool::new(0).size() >= 1);
+}
+
+#[test]
+fn test_try_map_collects_all_errors() {
+    let pool = ThreadPool::new(4);
+    let result = pool.try_map(1..=6, |i| {
+        if i % 2 == 0 {
+            Err(TestError::Even(i))
+        } else {
+            Ok(i)
+        }
+    });
+    assert_eq!(
+        result,
+        Err(vec![
+            TestError::Even(2),
+            TestError::Even(4),
+            TestError::Even(6)
+        ])
+    );
+
+    let result: Result<Vec<i32>, Vec<io::Error>> = pool.try_map(vec![1, 2, 3], |i| Ok(i * 10));
+    assert_eq!(result.unwrap(), vec![10, 20, 30]);
+}
+
+#[cfg(test)]
+#[derive(Debug, PartialEq)]
+enum TestError {
+    Even(i32),
+    Job(JobError),
+}
+
+#[cfg(test)]
+impl From<JobError> for TestError {
+    fn from(e: JobError) -> Self {
+        TestError::Job(e)
+    }
+}
+
+#[test]
+fn test_empty_input() {
+    // by_rayon の reduce_with(..).unwrap() と違い、空の入力でも panic しない
+    let pool = ThreadPool::new(2);
+    let result: Result<Vec<()>, Vec<io::Error>> = pool.try_map(Vec::<String>::new(), |_| Ok(()));
+    assert!(result.unwrap().is_empty());
+    pool.shutdown();
+}
+
+#[test]
+fn test_panic() {
+    let pool = ThreadPool::new(1);
+    let handle = pool.submit(|| -> i32 { panic!("boom") });
+    assert_eq!(handle.join(), Err(JobError::Panicked("boom".to_string())));
+    // panic したジョブの後も、同じワーカーで次のジョブを実行できる
+    assert_eq!(pool.submit(|| 42).join(), Ok(42));
+
+    let result: Result<Vec<i32>, Vec<TestError>> =
+        pool.try_map(0..3, |i| if i == 1 { panic!("bad {}", i) } else { Ok(i) });
+    assert_eq!(
+        result,
+        Err(vec![TestError::Job(JobError::Panicked(
+            "bad 1".to_string()
+        ))])
+    );
+}
+
+#[test]
+fn test_cancel() {
+    let pool = ThreadPool::new(1);
+    let token = CancelToken::new();
+    let (started_tx, started_rx) = mpsc::channel();
+
+    // 実行中のジョブはトークンでキャンセルに気づいて終了する
+    let job_token = token.clone();
+    let running = pool.submit_with(&token, move || {
+        started_tx.send(()).unwrap();
+        let mut spins = 0;
+        while !job_token.is_cancelled() {
+            thread::sleep(Duration::from_millis(1));
+            spins += 1;
+        }
+        spins
+    });
+    let queued: Vec<_> = (0..5)
+        .map(|i| pool.submit_with(&token, move || i))
+        .collect();
+    // 別のトークンのジョブはキャンセルされない
+    let other = pool.submit(|| "other");
+    started_rx.recv().unwrap();
+    token.cancel();
+
+    assert!(running.join().is_ok());
+    for handle in queued {
+        assert_eq!(handle.join(), Err(JobError::Cancelled));
+    }
+    assert_eq!(other.join(), Ok("other"));
+    assert_eq!(
+        pool.submit_with(&token, || 1).join(),
+        Err(JobError::Cancelled)
+    );
+
+    // キャンセルした後もプールは使い続けられる
+    // ワーカーを blocker で止めておき、 handle のジョブが始まる前にキャンセルする
+    let (release_tx, release_rx) = mpsc::channel::<()>();
+    let blocker = pool.submit(move || release_rx.recv().unwrap());
+    let handle = pool.submit(|| 2);
+    handle.cancel();
+    release_tx.send(()).unwrap();
+    assert_eq!(handle.join(), Err(JobError::Cancelled));
+    assert!(blocker.join().is_ok());
+    let result: Result<Vec<i32>, Vec<TestError>> = pool.try_map(0..3, Ok);
+    assert_eq!(result, Ok(vec![0, 1, 2]));
+    pool.shutdown_now();
+}
+
+#[test]
+fn test_shutdown_waits_for_queued_jobs() {
+    use std::sync::atomic::AtomicUsize;
+
+    let count = Arc::new(AtomicUsize::new(0));
+    let pool = ThreadPool::new(2);
+    for _ in 0..20 {
+        let count = count.clone();
+        pool.submit(move || {
+            thread::sleep(Duration::from_millis(1));
+            count.fetch_add(1, Ordering::SeqCst);
+        });
+    }
+    pool.shutdown();
+    assert_eq!(count.load(Ordering::SeqCst), 20);
+}